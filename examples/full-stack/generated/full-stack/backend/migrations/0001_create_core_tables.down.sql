@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS book_borrow_ledger;
+DROP TABLE IF EXISTS members;
+DROP TABLE IF EXISTS books;
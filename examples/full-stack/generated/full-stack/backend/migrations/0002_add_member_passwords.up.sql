@@ -0,0 +1 @@
+ALTER TABLE members ADD COLUMN password_hash TEXT;
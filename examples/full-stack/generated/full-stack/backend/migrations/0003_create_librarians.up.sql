@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS librarians (
+    librarian_id  SERIAL PRIMARY KEY,
+    username      TEXT UNIQUE NOT NULL,
+    password_hash TEXT NOT NULL,
+    role          TEXT NOT NULL DEFAULT 'librarian' CHECK (role IN ('librarian'))
+);
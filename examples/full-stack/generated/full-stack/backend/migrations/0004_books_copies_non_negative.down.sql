@@ -0,0 +1 @@
+ALTER TABLE books DROP CONSTRAINT IF EXISTS books_copies_non_negative;
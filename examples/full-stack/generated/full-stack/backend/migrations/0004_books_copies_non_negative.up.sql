@@ -0,0 +1,7 @@
+-- Overlapping checkouts could oversell a title before checkout became
+-- atomic, leaving a negative count behind. Clamp those rows first, or the
+-- constraint below would refuse the existing data.
+UPDATE books SET number_of_copies = 0 WHERE number_of_copies < 0;
+
+ALTER TABLE books
+    ADD CONSTRAINT books_copies_non_negative CHECK (number_of_copies >= 0);
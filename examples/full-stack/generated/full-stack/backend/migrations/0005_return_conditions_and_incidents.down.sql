@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS loan_incidents;
+ALTER TABLE book_borrow_ledger DROP CONSTRAINT IF EXISTS ledger_return_condition_valid;
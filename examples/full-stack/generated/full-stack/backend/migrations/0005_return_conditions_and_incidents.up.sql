@@ -0,0 +1,13 @@
+ALTER TABLE book_borrow_ledger
+    ADD CONSTRAINT ledger_return_condition_valid
+    CHECK (return_condition IS NULL OR return_condition IN ('good', 'damaged', 'lost'));
+
+CREATE TABLE IF NOT EXISTS loan_incidents (
+    incident_id SERIAL PRIMARY KEY,
+    ledger_id   INTEGER NOT NULL REFERENCES book_borrow_ledger(id),
+    member_id   INTEGER NOT NULL REFERENCES members(member_id),
+    book_id     INTEGER NOT NULL REFERENCES books(book_id),
+    condition   TEXT NOT NULL CHECK (condition IN ('damaged', 'lost')),
+    notes       TEXT,
+    reported_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
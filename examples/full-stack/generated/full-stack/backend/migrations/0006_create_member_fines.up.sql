@@ -0,0 +1,13 @@
+-- Charges are positive, payments negative; a member's balance is the sum.
+CREATE TABLE IF NOT EXISTS member_fines (
+    fine_id      SERIAL PRIMARY KEY,
+    member_id    INTEGER NOT NULL REFERENCES members(member_id),
+    ledger_id    INTEGER REFERENCES book_borrow_ledger(id),
+    kind         TEXT NOT NULL CHECK (kind IN ('late_return', 'payment')),
+    amount_cents INTEGER NOT NULL,
+    description  TEXT,
+    created_at   TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CHECK ((kind = 'payment') = (amount_cents < 0))
+);
+
+CREATE INDEX IF NOT EXISTS member_fines_member_idx ON member_fines (member_id);
@@ -0,0 +1,18 @@
+-- waiting: in the queue; ready: a copy is reserved until expires_at;
+-- fulfilled / cancelled / expired: closed.
+CREATE TABLE IF NOT EXISTS holds (
+    hold_id    SERIAL PRIMARY KEY,
+    book_id    INTEGER NOT NULL REFERENCES books(book_id),
+    member_id  INTEGER NOT NULL REFERENCES members(member_id),
+    status     TEXT NOT NULL DEFAULT 'waiting'
+               CHECK (status IN ('waiting', 'ready', 'fulfilled', 'cancelled', 'expired')),
+    placed_at  TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ready_at   TIMESTAMP,
+    expires_at TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS holds_one_active_per_member
+    ON holds (book_id, member_id) WHERE status IN ('waiting', 'ready');
+
+CREATE INDEX IF NOT EXISTS holds_queue_idx
+    ON holds (book_id, placed_at, hold_id) WHERE status = 'waiting';
@@ -0,0 +1,3 @@
+ALTER TABLE book_borrow_ledger DROP COLUMN max_renewals;
+ALTER TABLE book_borrow_ledger DROP COLUMN renewal_count;
+ALTER TABLE books DROP COLUMN max_renewals;
@@ -0,0 +1,7 @@
+-- NULL means the server-wide default from LoanPolicy applies.
+ALTER TABLE books ADD COLUMN max_renewals INTEGER CHECK (max_renewals >= 0);
+
+-- The limit is copied onto each loan at checkout so later policy changes do
+-- not affect loans already out. Loans that predate this migration get 2.
+ALTER TABLE book_borrow_ledger ADD COLUMN renewal_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE book_borrow_ledger ADD COLUMN max_renewals INTEGER NOT NULL DEFAULT 2;
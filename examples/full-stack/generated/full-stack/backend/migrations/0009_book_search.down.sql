@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS books_year_idx;
+DROP INDEX IF EXISTS books_author_idx;
+DROP INDEX IF EXISTS books_name_idx;
+DROP INDEX IF EXISTS books_search_idx;
+ALTER TABLE books DROP COLUMN IF EXISTS search_vector;
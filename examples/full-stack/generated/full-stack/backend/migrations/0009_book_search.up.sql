@@ -0,0 +1,10 @@
+ALTER TABLE books
+    ADD COLUMN IF NOT EXISTS search_vector tsvector
+    GENERATED ALWAYS AS (
+        to_tsvector('english', coalesce(name, '') || ' ' || coalesce(author, ''))
+    ) STORED;
+
+CREATE INDEX IF NOT EXISTS books_search_idx ON books USING GIN (search_vector);
+CREATE INDEX IF NOT EXISTS books_name_idx ON books (name, book_id);
+CREATE INDEX IF NOT EXISTS books_author_idx ON books (author, book_id);
+CREATE INDEX IF NOT EXISTS books_year_idx ON books ((COALESCE(publication_year, 0)), book_id);
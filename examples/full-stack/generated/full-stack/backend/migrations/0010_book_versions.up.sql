@@ -0,0 +1,3 @@
+-- Bumped on every librarian edit; clients send it back as If-Match so two
+-- people editing the same record cannot silently overwrite each other.
+ALTER TABLE books ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,4 @@
+-- Books are archived rather than deleted so loan history keeps pointing at
+-- a real row. Archived books are hidden from the catalogue and cannot be
+-- borrowed or held.
+ALTER TABLE books ADD COLUMN archived_at TIMESTAMP;
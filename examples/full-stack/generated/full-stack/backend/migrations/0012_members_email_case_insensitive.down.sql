@@ -0,0 +1 @@
+DROP INDEX IF EXISTS members_email_lower_key;
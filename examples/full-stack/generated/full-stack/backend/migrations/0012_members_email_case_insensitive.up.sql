@@ -0,0 +1,3 @@
+-- Emails are compared case-insensitively, so Ada@Example.com and
+-- ada@example.com cannot register as two members.
+CREATE UNIQUE INDEX IF NOT EXISTS members_email_lower_key ON members (LOWER(email));
@@ -0,0 +1,5 @@
+-- A deactivated member can no longer log in or borrow. An erased member is
+-- also deactivated, and their personal fields have been overwritten; their
+-- loans, fines and holds keep pointing at the row.
+ALTER TABLE members ADD COLUMN deactivated_at TIMESTAMP;
+ALTER TABLE members ADD COLUMN erased_at TIMESTAMP;
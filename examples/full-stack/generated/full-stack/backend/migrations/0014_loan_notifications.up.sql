@@ -0,0 +1,18 @@
+-- One row per reminder, inserted before the reminder is sent so the unique
+-- key below lets only one sweep (or one server) claim it. `due_at` is the
+-- loan's due date when it was sent, so a renewed loan is reminded again about
+-- its new date. `ordinal` numbers the reminders of one kind for one due date
+-- from 0, so each repeat of an overdue reminder has a key of its own.
+CREATE TABLE IF NOT EXISTS loan_notifications (
+    notification_id SERIAL PRIMARY KEY,
+    ledger_id       INTEGER NOT NULL REFERENCES book_borrow_ledger(id),
+    member_id       INTEGER NOT NULL REFERENCES members(member_id),
+    kind            TEXT NOT NULL CHECK (kind IN ('due_soon', 'overdue')),
+    channel         TEXT NOT NULL,
+    due_at          TIMESTAMP NOT NULL,
+    ordinal         INTEGER NOT NULL DEFAULT 0,
+    sent_at         TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS loan_notifications_claim_key
+    ON loan_notifications (ledger_id, kind, due_at, ordinal);
@@ -0,0 +1,3 @@
+ALTER TABLE members DROP COLUMN blocked_reason;
+ALTER TABLE members DROP COLUMN blocked_at;
+ALTER TABLE books DROP COLUMN min_age;
@@ -0,0 +1,8 @@
+-- Titles with a minimum age are only lent to members known to be at least
+-- that old. NULL means anyone may borrow it.
+ALTER TABLE books ADD COLUMN min_age INTEGER CHECK (min_age >= 0);
+
+-- A librarian can suspend a member's borrowing; the reason is shown to them
+-- when a checkout is refused.
+ALTER TABLE members ADD COLUMN blocked_at TIMESTAMP;
+ALTER TABLE members ADD COLUMN blocked_reason TEXT;
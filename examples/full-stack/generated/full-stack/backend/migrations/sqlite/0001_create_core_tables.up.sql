@@ -0,0 +1,31 @@
+-- SQLite cannot add constraints to an existing table, so the checks that
+-- Postgres gains in 0004 and 0005 are declared here from the start.
+CREATE TABLE IF NOT EXISTS books (
+    book_id          INTEGER PRIMARY KEY AUTOINCREMENT,
+    name             TEXT NOT NULL,
+    author           TEXT NOT NULL,
+    number_of_copies INTEGER NOT NULL DEFAULT 0
+                     CONSTRAINT books_copies_non_negative CHECK (number_of_copies >= 0),
+    publication_year INTEGER,
+    edition          TEXT
+);
+
+CREATE TABLE IF NOT EXISTS members (
+    member_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name      TEXT NOT NULL,
+    address   TEXT,
+    age       INTEGER,
+    email     TEXT UNIQUE NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS book_borrow_ledger (
+    id               INTEGER PRIMARY KEY AUTOINCREMENT,
+    book_id          INTEGER NOT NULL REFERENCES books(book_id),
+    member_id        INTEGER NOT NULL REFERENCES members(member_id),
+    borrow_date      TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expected_return  TIMESTAMP NOT NULL,
+    actual_return    TIMESTAMP,
+    return_condition TEXT
+                     CONSTRAINT ledger_return_condition_valid
+                     CHECK (return_condition IS NULL OR return_condition IN ('good', 'damaged', 'lost'))
+);
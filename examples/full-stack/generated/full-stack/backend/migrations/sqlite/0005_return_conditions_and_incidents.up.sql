@@ -0,0 +1,10 @@
+-- ledger_return_condition_valid is part of book_borrow_ledger since 0001 on SQLite.
+CREATE TABLE IF NOT EXISTS loan_incidents (
+    incident_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    ledger_id   INTEGER NOT NULL REFERENCES book_borrow_ledger(id),
+    member_id   INTEGER NOT NULL REFERENCES members(member_id),
+    book_id     INTEGER NOT NULL REFERENCES books(book_id),
+    condition   TEXT NOT NULL CHECK (condition IN ('damaged', 'lost')),
+    notes       TEXT,
+    reported_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
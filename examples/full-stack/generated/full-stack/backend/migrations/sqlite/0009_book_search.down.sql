@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS books_year_idx;
+DROP INDEX IF EXISTS books_author_idx;
+DROP INDEX IF EXISTS books_name_idx;
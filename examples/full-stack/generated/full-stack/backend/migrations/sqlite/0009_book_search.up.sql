@@ -0,0 +1,5 @@
+-- SQLite has no tsvector; `q` searches match words with LIKE instead, so
+-- only the sort indexes are created here.
+CREATE INDEX IF NOT EXISTS books_name_idx ON books (name, book_id);
+CREATE INDEX IF NOT EXISTS books_author_idx ON books (author, book_id);
+CREATE INDEX IF NOT EXISTS books_year_idx ON books ((COALESCE(publication_year, 0)), book_id);
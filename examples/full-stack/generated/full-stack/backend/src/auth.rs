@@ -0,0 +1,424 @@
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse, ResponseError};
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::Utc;
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::{ready, Ready};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::OnceLock;
+
+use crate::error::{problem, ApiError};
+use crate::repo::LibraryRepository;
+
+/// How long a session token stays valid after login.
+pub const SESSION_TTL: chrono::Duration = chrono::Duration::hours(12);
+
+// ── Passwords ──────────────────────────────────────────────────────────
+
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(parsed) => Argon2::default()
+            .verify_password(password.as_bytes(), &parsed)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Checks `password` against `hash`, or, without one, against a fixed hash
+/// and fails, so that both take as long. Logins use it for accounts that do
+/// not exist or have no password, so response times do not tell them apart.
+pub fn verify_password_or_dummy(password: &str, hash: Option<&str>) -> bool {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+    match hash {
+        Some(hash) => verify_password(password, hash),
+        None => {
+            let dummy = DUMMY_HASH.get_or_init(|| {
+                hash_password("dummy password").expect("hashing a fixed password")
+            });
+            verify_password(password, dummy);
+            false
+        }
+    }
+}
+
+// ── Session tokens ─────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Member,
+    Librarian,
+}
+
+/// `sub` is a `member_id` for members and a `librarian_id` for librarians.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: i32,
+    pub role: Role,
+    pub exp: i64,
+}
+
+/// Signing keys for session tokens, shared with handlers via `web::Data`.
+pub struct AuthKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl AuthKeys {
+    pub fn from_secret(secret: &[u8]) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+        }
+    }
+
+    /// Keys from a random secret; tokens will not survive a restart.
+    pub fn ephemeral() -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        Self::from_secret(&secret)
+    }
+
+    pub fn issue(&self, sub: i32, role: Role) -> Result<String, jsonwebtoken::errors::Error> {
+        let claims = Claims {
+            sub,
+            role,
+            exp: (Utc::now() + SESSION_TTL).timestamp(),
+        };
+        jsonwebtoken::encode(&Header::default(), &claims, &self.encoding)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+        jsonwebtoken::decode::<Claims>(token, &self.decoding, &Validation::default())
+            .map(|data| data.claims)
+    }
+}
+
+// ── Extractor ──────────────────────────────────────────────────────────
+
+#[derive(Debug)]
+pub enum AuthError {
+    MissingToken,
+    InvalidToken,
+    Forbidden,
+    Misconfigured,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            AuthError::MissingToken => "missing bearer token",
+            AuthError::InvalidToken => "invalid or expired session token",
+            AuthError::Forbidden => "token does not grant access to this resource",
+            AuthError::Misconfigured => "authentication is not configured",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AuthError::MissingToken | AuthError::InvalidToken => StatusCode::UNAUTHORIZED,
+            AuthError::Forbidden => StatusCode::FORBIDDEN,
+            AuthError::Misconfigured => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let code = match self {
+            AuthError::MissingToken => "missing_token",
+            AuthError::InvalidToken => "invalid_token",
+            AuthError::Forbidden => "forbidden",
+            AuthError::Misconfigured => "auth_misconfigured",
+        };
+        problem(self.status_code(), code, &self.to_string(), Default::default())
+    }
+}
+
+/// A member holding a valid session token for an open account. On routes
+/// with a `{member_id}` segment, extraction also fails unless the token
+/// belongs to that member. The account is read from `R` on every request, so
+/// closing or erasing it ends sessions issued before, with
+/// `AccountDeactivated`.
+#[derive(Debug)]
+pub struct AuthenticatedMember<R> {
+    pub member_id: i32,
+    repo: PhantomData<fn() -> R>,
+}
+
+impl<R: LibraryRepository> FromRequest for AuthenticatedMember<R> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let member_id = authenticate(req);
+        let repo = req.app_data::<web::Data<R>>().cloned();
+        Box::pin(async move {
+            let member_id = member_id?;
+            let repo = repo.ok_or(AuthError::Misconfigured)?;
+            match repo.find_member(member_id).await? {
+                Some(m) if m.deactivated_at.is_some() => Err(ApiError::AccountDeactivated.into()),
+                Some(_) => Ok(AuthenticatedMember {
+                    member_id,
+                    repo: PhantomData,
+                }),
+                None => Err(AuthError::InvalidToken.into()),
+            }
+        })
+    }
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(actix_web::http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+fn claims(req: &HttpRequest) -> Result<Claims, AuthError> {
+    let keys = req
+        .app_data::<web::Data<AuthKeys>>()
+        .ok_or(AuthError::Misconfigured)?;
+    let token = bearer_token(req).ok_or(AuthError::MissingToken)?;
+    keys.verify(token).map_err(|_| AuthError::InvalidToken)
+}
+
+/// The member id in a member token, if it matches any `{member_id}` segment.
+fn authenticate(req: &HttpRequest) -> Result<i32, AuthError> {
+    let claims = claims(req)?;
+    if claims.role != Role::Member {
+        return Err(AuthError::Forbidden);
+    }
+
+    if let Some(path_id) = req.match_info().get("member_id") {
+        if path_id.parse::<i32>().ok() != Some(claims.sub) {
+            return Err(AuthError::Forbidden);
+        }
+    }
+
+    Ok(claims.sub)
+}
+
+/// A librarian holding a valid session token, for routes that read across
+/// members. Member tokens are `Forbidden`.
+#[derive(Debug)]
+pub struct AuthenticatedLibrarian {
+    pub librarian_id: i32,
+}
+
+impl FromRequest for AuthenticatedLibrarian {
+    type Error = AuthError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(claims(req).and_then(|claims| match claims.role {
+            Role::Librarian => Ok(AuthenticatedLibrarian { librarian_id: claims.sub }),
+            Role::Member => Err(AuthError::Forbidden),
+        }))
+    }
+}
+
+// ── Middleware ─────────────────────────────────────────────────────────
+
+/// Lets reads through and requires a librarian token for anything that
+/// modifies the wrapped scope.
+pub async fn require_librarian_for_writes(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    if ![Method::GET, Method::HEAD, Method::OPTIONS].contains(req.method()) {
+        let denied = match claims(req.request()) {
+            Ok(claims) if claims.role == Role::Librarian => None,
+            Ok(_) => Some(AuthError::Forbidden),
+            Err(e) => Some(e),
+        };
+        if let Some(e) = denied {
+            return Ok(req.error_response(e).map_into_right_body());
+        }
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo::{MemoryRepository, NewMember};
+    use actix_web::{middleware::from_fn, test, App};
+    use serde_json::json;
+
+    fn keys() -> web::Data<AuthKeys> {
+        web::Data::new(AuthKeys::from_secret(b"test-secret"))
+    }
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    async fn member_ok(member: AuthenticatedMember<MemoryRepository>) -> HttpResponse {
+        HttpResponse::Ok().json(json!({"member_id": member.member_id}))
+    }
+
+    async fn librarian_ok(librarian: AuthenticatedLibrarian) -> HttpResponse {
+        HttpResponse::Ok().json(json!({"librarian_id": librarian.librarian_id}))
+    }
+
+    macro_rules! books_app {
+        ($keys:expr) => {
+            test::init_service(
+                App::new().app_data($keys.clone()).service(
+                    web::scope("/api/books")
+                        .wrap(from_fn(require_librarian_for_writes))
+                        .route("", web::get().to(ok))
+                        .route("", web::post().to(ok))
+                        .route("/{book_id}", web::delete().to(ok)),
+                ),
+            )
+            .await
+        };
+    }
+
+    #[actix_web::test]
+    async fn password_round_trip() {
+        let hash = hash_password("correct horse").unwrap();
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+        assert!(!verify_password("correct horse", "not-a-hash"));
+        assert!(verify_password_or_dummy("correct horse", Some(&hash)));
+        assert!(!verify_password_or_dummy("dummy password", None));
+    }
+
+    #[actix_web::test]
+    async fn members_cannot_modify_books() {
+        let keys = keys();
+        let app = books_app!(keys);
+        let token = keys.issue(1, Role::Member).unwrap();
+
+        let req = test::TestRequest::post()
+            .uri("/api/books")
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+        let req = test::TestRequest::delete()
+            .uri("/api/books/7")
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn anonymous_writes_are_unauthorized_but_reads_pass() {
+        let keys = keys();
+        let app = books_app!(keys);
+
+        let req = test::TestRequest::post().uri("/api/books").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::get().uri("/api/books").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn librarians_can_modify_books() {
+        let keys = keys();
+        let app = books_app!(keys);
+        let token = keys.issue(1, Role::Librarian).unwrap();
+
+        let req = test::TestRequest::post()
+            .uri("/api/books")
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::delete()
+            .uri("/api/books/7")
+            .insert_header(("Authorization", format!("Bearer {token}")))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn member_routes_reject_other_members_and_librarians() {
+        let keys = keys();
+        let repo = web::Data::new(MemoryRepository::new());
+        repo.create_member(NewMember {
+            name: "Test Member".into(),
+            address: None,
+            age: 30,
+            email: "member@example.com".into(),
+            password_hash: "not used".into(),
+        })
+        .await
+        .unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(keys.clone())
+                .app_data(repo.clone())
+                .route("/api/members/{member_id}/borrowed", web::get().to(member_ok)),
+        )
+        .await;
+
+        let own = keys.issue(1, Role::Member).unwrap();
+        let req = test::TestRequest::get()
+            .uri("/api/members/1/borrowed")
+            .insert_header(("Authorization", format!("Bearer {own}")))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get()
+            .uri("/api/members/2/borrowed")
+            .insert_header(("Authorization", format!("Bearer {own}")))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+        let librarian = keys.issue(1, Role::Librarian).unwrap();
+        let req = test::TestRequest::get()
+            .uri("/api/members/1/borrowed")
+            .insert_header(("Authorization", format!("Bearer {librarian}")))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn librarian_routes_reject_members() {
+        let keys = keys();
+        let app = test::init_service(
+            App::new()
+                .app_data(keys.clone())
+                .route("/api/loans", web::get().to(librarian_ok)),
+        )
+        .await;
+
+        let librarian = keys.issue(3, Role::Librarian).unwrap();
+        let req = test::TestRequest::get()
+            .uri("/api/loans")
+            .insert_header(("Authorization", format!("Bearer {librarian}")))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let member = keys.issue(3, Role::Member).unwrap();
+        let req = test::TestRequest::get()
+            .uri("/api/loans")
+            .insert_header(("Authorization", format!("Bearer {member}")))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+        let req = test::TestRequest::get().uri("/api/loans").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+    }
+}
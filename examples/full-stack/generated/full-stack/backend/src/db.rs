@@ -1,46 +1,684 @@
-use sqlx::PgPool;
-use log::info;
-
-pub async fn migrate(pool: &PgPool) -> Result<(), sqlx::Error> {
-    sqlx::query(
-        r#"CREATE TABLE IF NOT EXISTS books (
-            book_id          SERIAL PRIMARY KEY,
-            name             TEXT NOT NULL,
-            author           TEXT NOT NULL,
-            number_of_copies INTEGER NOT NULL DEFAULT 0,
-            publication_year INTEGER,
-            edition          TEXT
-        )"#,
-    )
-    .execute(pool)
-    .await?;
-
-    sqlx::query(
-        r#"CREATE TABLE IF NOT EXISTS members (
-            member_id SERIAL PRIMARY KEY,
-            name      TEXT NOT NULL,
-            address   TEXT,
-            age       INTEGER,
-            email     TEXT UNIQUE NOT NULL
-        )"#,
-    )
-    .execute(pool)
-    .await?;
-
-    sqlx::query(
-        r#"CREATE TABLE IF NOT EXISTS book_borrow_ledger (
-            id               SERIAL PRIMARY KEY,
-            book_id          INTEGER NOT NULL REFERENCES books(book_id),
-            member_id        INTEGER NOT NULL REFERENCES members(member_id),
-            borrow_date      TIMESTAMP NOT NULL DEFAULT NOW(),
-            expected_return  TIMESTAMP NOT NULL,
-            actual_return    TIMESTAMP,
-            return_condition TEXT
-        )"#,
-    )
-    .execute(pool)
-    .await?;
-
-    info!("✔ Database schema migrated");
+use chrono::NaiveDateTime;
+use sha2::{Digest, Sha256};
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{Connection, Database, PgConnection, PgPool, Pool, Postgres};
+#[cfg(feature = "sqlite")]
+use sqlx::sqlite::{Sqlite, SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// ── Connection ─────────────────────────────────────────────────────────
+
+/// How many times to try the first connection, and how long to wait between
+/// tries. The wait doubles after each failure, up to `max_backoff`.
+#[derive(Debug, Clone)]
+pub struct ConnectRetry {
+    pub attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl ConnectRetry {
+    /// Wait after the `failed`-th failed attempt (1-based).
+    fn backoff(&self, failed: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failed.saturating_sub(1));
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// Opens the pool, retrying while Postgres is still starting (as it often is
+/// when compose brings everything up at once). Returns the last error once
+/// the attempts run out.
+pub async fn connect(options: PgPoolOptions, url: &str, retry: &ConnectRetry) -> Result<PgPool, sqlx::Error> {
+    // A bare connection fails as soon as the server refuses; the pool itself
+    // would keep retrying internally until its acquire timeout. The timeout
+    // still bounds a host that never answers.
+    let attempt = || async {
+        let probe = actix_web::rt::time::timeout(options.get_acquire_timeout(), PgConnection::connect(url))
+            .await
+            .map_err(|_| sqlx::Error::PoolTimedOut)??;
+        probe.close().await?;
+        options.clone().connect(url).await
+    };
+    let mut failed = 0;
+    loop {
+        match attempt().await {
+            Ok(pool) => return Ok(pool),
+            Err(e) => {
+                failed += 1;
+                if failed >= retry.attempts {
+                    return Err(e);
+                }
+                let wait = retry.backoff(failed);
+                warn!(
+                    attempt = failed,
+                    of = retry.attempts,
+                    error = %e,
+                    retry_in_ms = wait.as_millis() as u64,
+                    "Postgres not reachable; retrying"
+                );
+                actix_web::rt::time::sleep(wait).await;
+            }
+        }
+    }
+}
+
+/// Opens (creating if needed) an SQLite database file, or `sqlite::memory:`.
+/// The pool holds a single connection that never expires: SQLite has one
+/// writer at a time, so transactions take turns on it instead of failing
+/// with "database is locked", and an in-memory database lives exactly as
+/// long as that connection.
+#[cfg(feature = "sqlite")]
+pub async fn connect_sqlite(url: &str) -> Result<SqlitePool, sqlx::Error> {
+    let options = url
+        .parse::<SqliteConnectOptions>()?
+        .create_if_missing(true)
+        .journal_mode(SqliteJournalMode::Wal)
+        .foreign_keys(true);
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .min_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(options)
+        .await
+}
+
+// ── Migrations ─────────────────────────────────────────────────────────
+
+/// A numbered schema change. The SQL lives in `migrations/` and is embedded
+/// at compile time; applied versions are recorded in `schema_migrations`.
+/// It is written to run on both backends, except where a migration says
+/// otherwise (see `SqliteSql`).
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    up: &'static str,
+    down: &'static str,
+    #[cfg(feature = "sqlite")]
+    sqlite: SqliteSql,
+    guard: Option<&'static Guard>,
+}
+
+/// What a migration runs on SQLite.
+#[cfg(feature = "sqlite")]
+#[derive(Clone, Copy)]
+enum SqliteSql {
+    /// The shared SQL, with `SERIAL PRIMARY KEY` spelled the SQLite way.
+    Shared,
+    /// Statements SQLite has no equivalent for, kept in `migrations/sqlite/`.
+    Own { up: &'static str, down: &'static str },
+    /// Nothing: the change only concerns databases Postgres created before
+    /// SQLite was supported.
+    Skip,
+}
+
+/// Existing data a migration cannot apply over. `query` is portable SQL
+/// returning one `(group, id)` row per offending record; the runner checks it
+/// in the migration's transaction and stops with the ids, grouped, instead
+/// of letting a constraint fail with no hint which rows are to blame.
+pub struct Guard {
+    query: &'static str,
+    /// What the rows in one group have in common.
+    problem: &'static str,
+    /// How an operator resolves it before starting again.
+    fix: &'static str,
+}
+
+/// Members whose emails differ only in case, which the old case-sensitive
+/// key allowed but `members_email_lower_key` does not.
+static EMAIL_CASE_DUPLICATES: Guard = Guard {
+    query: "SELECT LOWER(email), member_id FROM members \
+            WHERE LOWER(email) IN (SELECT LOWER(email) FROM members GROUP BY LOWER(email) HAVING COUNT(*) > 1) \
+            ORDER BY 1, 2",
+    problem: "members share an email address that differs only in case",
+    fix: "change the email of all but one member in each group",
+};
+
+impl Migration {
+    fn checksum<DB: Backend>(&self) -> String {
+        format!("{:x}", Sha256::digest(DB::up(self).as_bytes()))
+    }
+}
+
+macro_rules! migration {
+    ($version:expr, $name:literal) => {
+        migration!(@ $version, $name, SqliteSql::Shared, None)
+    };
+    ($version:expr, $name:literal, guard = $guard:expr) => {
+        migration!(@ $version, $name, SqliteSql::Shared, Some(&$guard))
+    };
+    ($version:expr, $name:literal, sqlite = own) => {
+        migration!(@ $version, $name, SqliteSql::Own {
+            up: include_str!(concat!("../migrations/sqlite/", $name, ".up.sql")),
+            down: include_str!(concat!("../migrations/sqlite/", $name, ".down.sql")),
+        }, None)
+    };
+    ($version:expr, $name:literal, sqlite = skip) => {
+        migration!(@ $version, $name, SqliteSql::Skip, None)
+    };
+    (@ $version:expr, $name:literal, $sqlite:expr, $guard:expr) => {
+        Migration {
+            version: $version,
+            name: $name,
+            up: include_str!(concat!("../migrations/", $name, ".up.sql")),
+            down: include_str!(concat!("../migrations/", $name, ".down.sql")),
+            #[cfg(feature = "sqlite")]
+            sqlite: $sqlite,
+            guard: $guard,
+        }
+    };
+}
+
+/// Every migration, in the order it must be applied. Never edit one that
+/// has shipped; add a new one instead.
+pub static MIGRATIONS: &[Migration] = &[
+    migration!(1, "0001_create_core_tables", sqlite = own),
+    migration!(2, "0002_add_member_passwords"),
+    migration!(3, "0003_create_librarians"),
+    migration!(4, "0004_books_copies_non_negative", sqlite = skip),
+    migration!(5, "0005_return_conditions_and_incidents", sqlite = own),
+    migration!(6, "0006_create_member_fines"),
+    migration!(7, "0007_create_holds"),
+    migration!(8, "0008_loan_renewals"),
+    migration!(9, "0009_book_search", sqlite = own),
+    migration!(10, "0010_book_versions"),
+    migration!(11, "0011_book_archiving"),
+    migration!(12, "0012_members_email_case_insensitive", guard = EMAIL_CASE_DUPLICATES),
+    migration!(13, "0013_member_accounts"),
+    migration!(14, "0014_loan_notifications"),
+    migration!(15, "0015_borrowing_limits"),
+];
+
+/// Advisory lock key (ASCII "library") that serialises concurrent runners.
+const MIGRATION_LOCK_KEY: i64 = 0x006c_6962_7261_7279;
+
+#[derive(Debug)]
+pub enum MigrateError {
+    Sql(sqlx::Error),
+    ChecksumMismatch { version: i64, name: String },
+    UnknownVersion { version: i64, name: String },
+    /// The migration's `Guard` found rows it cannot apply over; one list of
+    /// ids per group.
+    Blocked { version: i64, name: String, problem: &'static str, fix: &'static str, groups: Vec<Vec<i32>> },
+}
+
+impl fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrateError::Sql(e) => write!(f, "{}", e),
+            MigrateError::ChecksumMismatch { version, name } => write!(
+                f,
+                "migration {} ({}) was modified after it was applied",
+                version, name
+            ),
+            MigrateError::UnknownVersion { version, name } => write!(
+                f,
+                "database has migration {} ({}) which this binary does not know about",
+                version, name
+            ),
+            MigrateError::Blocked { version, name, problem, fix, groups } => {
+                let groups: Vec<String> = groups
+                    .iter()
+                    .map(|ids| ids.iter().map(i32::to_string).collect::<Vec<_>>().join(", "))
+                    .collect();
+                write!(
+                    f,
+                    "migration {} ({}) cannot be applied: {} (ids {}); {}, then start again",
+                    version,
+                    name,
+                    problem,
+                    groups.join("; "),
+                    fix
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for MigrateError {}
+
+impl From<sqlx::Error> for MigrateError {
+    fn from(e: sqlx::Error) -> Self {
+        MigrateError::Sql(e)
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct AppliedMigration {
+    version: i64,
+    name: String,
+    checksum: String,
+    applied_at: NaiveDateTime,
+}
+
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    pub applied_at: Option<NaiveDateTime>,
+    /// Applied, but the SQL on disk no longer matches the recorded checksum.
+    pub modified: bool,
+}
+
+/// A database the server can run on. The migration bookkeeping, probes and
+/// seed statements are the same SQL on every backend (see
+/// `portable_statements!`); what differs is the SQL a few migrations run and
+/// how concurrent runners are kept apart.
+pub trait Backend: Database {
+    fn up(m: &Migration) -> Cow<'static, str>;
+    fn down(m: &Migration) -> Cow<'static, str>;
+    /// Held for the whole migration run.
+    async fn lock(conn: &mut Self::Connection) -> Result<(), sqlx::Error>;
+    async fn unlock(conn: &mut Self::Connection) -> Result<(), sqlx::Error>;
+
+    async fn ensure_migrations_table(conn: &mut Self::Connection) -> Result<(), sqlx::Error>;
+    async fn applied_migrations(
+        conn: &mut Self::Connection,
+    ) -> Result<HashMap<i64, AppliedMigration>, sqlx::Error>;
+    /// Checks the migration's guard, runs the up SQL and records the
+    /// version, in one transaction.
+    async fn apply(conn: &mut Self::Connection, m: &Migration) -> Result<(), MigrateError>;
+    /// Runs the down SQL and forgets the version, in one transaction.
+    async fn revert(conn: &mut Self::Connection, m: &Migration) -> Result<(), sqlx::Error>;
+    async fn applied_versions(pool: &Pool<Self>) -> Result<Vec<i64>, sqlx::Error>;
+    async fn ping(pool: &Pool<Self>) -> Result<(), sqlx::Error>;
+    async fn overdue_loans(pool: &Pool<Self>, now: NaiveDateTime) -> Result<i64, sqlx::Error>;
+    /// Returns whether the account was created.
+    async fn insert_librarian(pool: &Pool<Self>, username: &str, password_hash: &str) -> Result<bool, sqlx::Error>;
+}
+
+/// The `Backend` methods whose SQL runs unchanged on Postgres and SQLite.
+macro_rules! portable_statements {
+    () => {
+        async fn ensure_migrations_table(conn: &mut Self::Connection) -> Result<(), sqlx::Error> {
+            sqlx::query(
+                r#"CREATE TABLE IF NOT EXISTS schema_migrations (
+                    version    BIGINT PRIMARY KEY,
+                    name       TEXT NOT NULL,
+                    checksum   TEXT NOT NULL,
+                    applied_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+                )"#,
+            )
+            .execute(&mut *conn)
+            .await?;
+            Ok(())
+        }
+
+        async fn applied_migrations(
+            conn: &mut Self::Connection,
+        ) -> Result<HashMap<i64, AppliedMigration>, sqlx::Error> {
+            let rows = sqlx::query_as::<_, AppliedMigration>(
+                "SELECT version, name, checksum, applied_at FROM schema_migrations",
+            )
+            .fetch_all(&mut *conn)
+            .await?;
+            Ok(rows.into_iter().map(|m| (m.version, m)).collect())
+        }
+
+        async fn apply(conn: &mut Self::Connection, m: &Migration) -> Result<(), MigrateError> {
+            let mut tx = conn.begin().await?;
+            if let Some(guard) = m.guard {
+                let rows = sqlx::query_as::<_, (String, i32)>(guard.query)
+                    .fetch_all(&mut *tx)
+                    .await?;
+                if !rows.is_empty() {
+                    let mut groups: Vec<(String, Vec<i32>)> = Vec::new();
+                    for (key, id) in rows {
+                        match groups.last_mut() {
+                            Some((last, ids)) if *last == key => ids.push(id),
+                            _ => groups.push((key, vec![id])),
+                        }
+                    }
+                    return Err(MigrateError::Blocked {
+                        version: m.version,
+                        name: m.name.to_string(),
+                        problem: guard.problem,
+                        fix: guard.fix,
+                        groups: groups.into_iter().map(|(_, ids)| ids).collect(),
+                    });
+                }
+            }
+            let up = Self::up(m);
+            // A migration SQLite skips has nothing to run, only a version to record.
+            if !up.is_empty() {
+                sqlx::raw_sql(&up).execute(&mut *tx).await?;
+            }
+            sqlx::query("INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)")
+                .bind(m.version)
+                .bind(m.name)
+                .bind(m.checksum::<Self>())
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+            Ok(())
+        }
+
+        async fn revert(conn: &mut Self::Connection, m: &Migration) -> Result<(), sqlx::Error> {
+            let mut tx = conn.begin().await?;
+            let down = Self::down(m);
+            if !down.is_empty() {
+                sqlx::raw_sql(&down).execute(&mut *tx).await?;
+            }
+            sqlx::query("DELETE FROM schema_migrations WHERE version = $1")
+                .bind(m.version)
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await
+        }
+
+        async fn applied_versions(pool: &Pool<Self>) -> Result<Vec<i64>, sqlx::Error> {
+            sqlx::query_scalar("SELECT version FROM schema_migrations")
+                .fetch_all(pool)
+                .await
+        }
+
+        async fn ping(pool: &Pool<Self>) -> Result<(), sqlx::Error> {
+            sqlx::query("SELECT 1").execute(pool).await?;
+            Ok(())
+        }
+
+        async fn overdue_loans(pool: &Pool<Self>, now: NaiveDateTime) -> Result<i64, sqlx::Error> {
+            sqlx::query_scalar(
+                "SELECT COUNT(*) FROM book_borrow_ledger WHERE actual_return IS NULL AND expected_return < $1",
+            )
+            .bind(now)
+            .fetch_one(pool)
+            .await
+        }
+
+        async fn insert_librarian(pool: &Pool<Self>, username: &str, password_hash: &str) -> Result<bool, sqlx::Error> {
+            let created = sqlx::query(
+                "INSERT INTO librarians (username, password_hash) VALUES ($1, $2) ON CONFLICT (username) DO NOTHING",
+            )
+            .bind(username)
+            .bind(password_hash)
+            .execute(pool)
+            .await?;
+            Ok(created.rows_affected() > 0)
+        }
+    };
+}
+
+impl Backend for Postgres {
+    fn up(m: &Migration) -> Cow<'static, str> {
+        Cow::Borrowed(m.up)
+    }
+
+    fn down(m: &Migration) -> Cow<'static, str> {
+        Cow::Borrowed(m.down)
+    }
+
+    async fn lock(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT pg_advisory_lock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn unlock(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+        sqlx::query("SELECT pg_advisory_unlock($1)")
+            .bind(MIGRATION_LOCK_KEY)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    portable_statements!();
+}
+
+#[cfg(feature = "sqlite")]
+impl Backend for Sqlite {
+    fn up(m: &Migration) -> Cow<'static, str> {
+        match m.sqlite {
+            SqliteSql::Shared => sqlite_keys(m.up),
+            SqliteSql::Own { up, .. } => Cow::Borrowed(up),
+            SqliteSql::Skip => Cow::Borrowed(""),
+        }
+    }
+
+    fn down(m: &Migration) -> Cow<'static, str> {
+        match m.sqlite {
+            SqliteSql::Shared => sqlite_keys(m.down),
+            SqliteSql::Own { down, .. } => Cow::Borrowed(down),
+            SqliteSql::Skip => Cow::Borrowed(""),
+        }
+    }
+
+    // The file is only ever opened by one process, through one connection.
+    async fn lock(_: &mut Self::Connection) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    async fn unlock(_: &mut Self::Connection) -> Result<(), sqlx::Error> {
+        Ok(())
+    }
+
+    portable_statements!();
+}
+
+/// SQLite has no `SERIAL`; an `AUTOINCREMENT` rowid never reuses an id either.
+#[cfg(feature = "sqlite")]
+fn sqlite_keys(sql: &'static str) -> Cow<'static, str> {
+    const SERIAL: &str = "SERIAL PRIMARY KEY";
+    if sql.contains(SERIAL) {
+        Cow::Owned(sql.replace(SERIAL, "INTEGER PRIMARY KEY AUTOINCREMENT"))
+    } else {
+        Cow::Borrowed(sql)
+    }
+}
+
+fn verify_applied<DB: Backend>(applied: &HashMap<i64, AppliedMigration>) -> Result<(), MigrateError> {
+    for row in applied.values() {
+        match MIGRATIONS.iter().find(|m| m.version == row.version) {
+            None => {
+                return Err(MigrateError::UnknownVersion {
+                    version: row.version,
+                    name: row.name.clone(),
+                })
+            }
+            Some(m) if m.checksum::<DB>() != row.checksum => {
+                return Err(MigrateError::ChecksumMismatch {
+                    version: row.version,
+                    name: row.name.clone(),
+                })
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+async fn apply_pending<DB: Backend>(conn: &mut DB::Connection) -> Result<(), MigrateError> {
+    DB::ensure_migrations_table(conn).await?;
+    let applied = DB::applied_migrations(conn).await?;
+    verify_applied::<DB>(&applied)?;
+
+    for m in MIGRATIONS.iter().filter(|m| !applied.contains_key(&m.version)) {
+        DB::apply(conn, m).await?;
+        info!(version = m.version, name = m.name, "applied migration");
+    }
+    Ok(())
+}
+
+async fn revert_to<DB: Backend>(conn: &mut DB::Connection, target: i64) -> Result<(), MigrateError> {
+    DB::ensure_migrations_table(conn).await?;
+    let applied = DB::applied_migrations(conn).await?;
+    verify_applied::<DB>(&applied)?;
+
+    for m in MIGRATIONS
+        .iter()
+        .rev()
+        .filter(|m| m.version > target && applied.contains_key(&m.version))
+    {
+        DB::revert(conn, m).await?;
+        info!(version = m.version, name = m.name, "rolled back migration");
+    }
     Ok(())
 }
+
+/// Applies every pending migration, each in its own transaction. On
+/// Postgres an advisory lock keeps two instances starting at once from
+/// racing each other.
+pub async fn migrate<DB: Backend>(pool: &Pool<DB>) -> Result<(), MigrateError> {
+    let mut conn = pool.acquire().await?;
+    DB::lock(&mut conn).await?;
+    let result = apply_pending::<DB>(&mut conn).await;
+    DB::unlock(&mut conn).await?;
+    result?;
+
+    info!("database schema migrated");
+    Ok(())
+}
+
+/// Reverts applied migrations newer than `target`, newest first.
+pub async fn rollback<DB: Backend>(pool: &Pool<DB>, target: i64) -> Result<(), MigrateError> {
+    let mut conn = pool.acquire().await?;
+    DB::lock(&mut conn).await?;
+    let result = revert_to::<DB>(&mut conn, target).await;
+    DB::unlock(&mut conn).await?;
+    result
+}
+
+/// Every known migration alongside when (if ever) it was applied.
+pub async fn status<DB: Backend>(pool: &Pool<DB>) -> Result<Vec<MigrationStatus>, MigrateError> {
+    let mut conn = pool.acquire().await?;
+    DB::ensure_migrations_table(&mut conn).await?;
+    let applied = DB::applied_migrations(&mut conn).await?;
+
+    Ok(MIGRATIONS
+        .iter()
+        .map(|m| {
+            let row = applied.get(&m.version);
+            MigrationStatus {
+                version: m.version,
+                name: m.name.to_string(),
+                applied_at: row.map(|a| a.applied_at),
+                modified: row.is_some_and(|a| a.checksum != m.checksum::<DB>()),
+            }
+        })
+        .collect())
+}
+
+/// Versions this binary knows about that the database has not recorded.
+/// Read-only, unlike `status`, so probes can call it freely; fails if the
+/// migrations table does not exist yet.
+pub async fn pending_versions<DB: Backend>(pool: &Pool<DB>) -> Result<Vec<i64>, sqlx::Error> {
+    let applied = DB::applied_versions(pool).await?;
+    Ok(MIGRATIONS
+        .iter()
+        .map(|m| m.version)
+        .filter(|v| !applied.contains(v))
+        .collect())
+}
+
+// ── Seed data ──────────────────────────────────────────────────────────
+
+/// Creates the bootstrap librarian account unless that username already exists.
+pub async fn ensure_librarian<DB: Backend>(
+    pool: &Pool<DB>,
+    username: &str,
+    password_hash: &str,
+) -> Result<(), sqlx::Error> {
+    if DB::insert_librarian(pool, username, password_hash).await? {
+        info!(username, "created librarian account");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let retry = ConnectRetry {
+            attempts: 10,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(3),
+        };
+        let waits: Vec<u128> = (1..=5).map(|n| retry.backoff(n).as_millis()).collect();
+        assert_eq!(waits, [500, 1000, 2000, 3000, 3000]);
+        assert_eq!(retry.backoff(u32::MAX), Duration::from_secs(3));
+    }
+
+    #[actix_web::test]
+    async fn connect_gives_up_after_the_last_attempt() {
+        let retry = ConnectRetry {
+            attempts: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(20),
+        };
+        let options = PgPoolOptions::new();
+        let started = std::time::Instant::now();
+        // Nothing listens on port 1; each attempt fails immediately.
+        let result = connect(options, "postgres://nobody@127.0.0.1:1/none", &retry).await;
+        assert!(result.is_err());
+        assert!(started.elapsed() >= Duration::from_millis(30), "waited 10ms then 20ms between attempts");
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[actix_web::test]
+    async fn sqlite_migrations_roll_back_and_reapply() {
+        let pool = connect_sqlite("sqlite::memory:").await.unwrap();
+        migrate(&pool).await.unwrap();
+        assert!(pending_versions(&pool).await.unwrap().is_empty());
+
+        rollback(&pool, 0).await.unwrap();
+        assert_eq!(pending_versions(&pool).await.unwrap().len(), MIGRATIONS.len());
+        let tables: Vec<String> =
+            sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(tables, ["schema_migrations"]);
+
+        migrate(&pool).await.unwrap();
+        let statuses = status(&pool).await.unwrap();
+        assert!(statuses.iter().all(|m| m.applied_at.is_some() && !m.modified));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[actix_web::test]
+    async fn case_variant_emails_stop_the_migration_with_their_ids() {
+        let pool = connect_sqlite("sqlite::memory:").await.unwrap();
+        migrate(&pool).await.unwrap();
+        rollback(&pool, 11).await.unwrap();
+        for (name, email) in [("Ada", "ada@example.com"), ("Bob", "bob@example.com"), ("Ada Again", "Ada@Example.com")] {
+            sqlx::query("INSERT INTO members (name, email) VALUES ($1, $2)")
+                .bind(name)
+                .bind(email)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let err = migrate(&pool).await.unwrap_err();
+        assert!(matches!(&err, MigrateError::Blocked { version: 12, groups, .. } if *groups == [vec![1, 3]]), "{err}");
+        assert!(err.to_string().contains("(ids 1, 3)"), "{err}");
+        assert_eq!(pending_versions(&pool).await.unwrap().first(), Some(&12), "nothing was applied");
+
+        sqlx::query("UPDATE members SET email = 'ada.again@example.com' WHERE member_id = 3")
+            .execute(&pool)
+            .await
+            .unwrap();
+        migrate(&pool).await.unwrap();
+    }
+
+    #[test]
+    fn migrations_are_numbered_in_order() {
+        for (i, m) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(m.version, i as i64 + 1, "{} is out of sequence", m.name);
+            assert!(
+                m.name.starts_with(&format!("{:04}_", m.version)),
+                "{} does not match its version {}",
+                m.name,
+                m.version
+            );
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::fmt;
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::validation::FieldError;
+
+/// RFC 7807 problem document, served as `application/problem+json`. `code` is
+/// a stable, machine-readable identifier clients can branch on; `detail` is
+/// for humans and may change. Some errors add extra members such as
+/// `book_id`, `balance_cents` or, for 422s, a list of field `errors`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    #[schema(example = "about:blank")]
+    pub type_: String,
+    #[schema(example = "Conflict")]
+    pub title: String,
+    #[schema(example = 409)]
+    pub status: u16,
+    #[schema(example = "no_copies_available")]
+    pub code: String,
+    #[schema(example = "no copies available for book: 7")]
+    pub detail: String,
+}
+
+pub fn problem(status: StatusCode, code: &str, detail: &str, extensions: Map<String, Value>) -> HttpResponse {
+    let problem = Problem {
+        type_: "about:blank".into(),
+        title: status.canonical_reason().unwrap_or("Error").into(),
+        status: status.as_u16(),
+        code: code.into(),
+        detail: detail.into(),
+    };
+    let mut body = match serde_json::to_value(problem) {
+        Ok(Value::Object(map)) => map,
+        _ => unreachable!("problems serialize to objects"),
+    };
+    body.extend(extensions);
+
+    HttpResponse::build(status)
+        .content_type("application/problem+json")
+        .json(Value::Object(body))
+}
+
+/// Everything a handler can fail with. Domain failures get their own variant
+/// so each has a fixed status and code; database errors that are not handled
+/// explicitly are classified by `From<sqlx::Error>`.
+#[derive(Debug)]
+pub enum ApiError {
+    // ── Request ──
+    BadRequest(String),
+    Validation(Vec<FieldError>),
+    InvalidCredentials,
+    PreconditionRequired,
+    VersionMismatch { book_id: Option<i32> },
+
+    // ── Lookups ──
+    NotFound,
+    BookNotFound(i32),
+    LoanNotFound,
+    HoldNotFound,
+
+    // ── Business rules ──
+    EmailTaken,
+    AccountDeactivated,
+    MemberHasLoans { open_loans: i64 },
+    FinesBlockCheckout { balance_cents: i64, limit_cents: i64 },
+    MemberBlocked { reason: Option<String> },
+    LoanLimitReached { max_open_loans: i64, open_loans: i64 },
+    TitleLimitReached { book_id: i32, max_copies_per_title: i64 },
+    AgeRestricted { book_id: i32, min_age: i32 },
+    NoCopiesAvailable(i32),
+    RenewalLimitReached { max_renewals: i32 },
+    LoanOverdue,
+    HoldsWaiting,
+    CopiesAvailable,
+    HoldAlreadyActive,
+    PaymentExceedsBalance { balance_cents: i64 },
+    NegativeStock { book_id: i32, number_of_copies: i32 },
+    StockAboveLimit { book_id: i32, number_of_copies: i32, max_copies: i32 },
+    BookOnLoan { copies_on_loan: i64 },
+    AlreadyArchived,
+    NotArchived,
+
+    // ── Integrity ──
+    Duplicate,
+    ForeignKeyViolation,
+    ConstraintViolation,
+
+    // ── Server ──
+    Database(sqlx::Error),
+    Internal(String),
+}
+
+impl ApiError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::PreconditionRequired => "if_match_required",
+            ApiError::VersionMismatch { .. } => "version_mismatch",
+            ApiError::NotFound => "not_found",
+            ApiError::BookNotFound(_) => "book_not_found",
+            ApiError::LoanNotFound => "loan_not_found",
+            ApiError::HoldNotFound => "hold_not_found",
+            ApiError::EmailTaken => "email_taken",
+            ApiError::AccountDeactivated => "account_deactivated",
+            ApiError::MemberHasLoans { .. } => "member_has_loans",
+            ApiError::FinesBlockCheckout { .. } => "fines_block_checkout",
+            ApiError::MemberBlocked { .. } => "member_blocked",
+            ApiError::LoanLimitReached { .. } => "loan_limit_reached",
+            ApiError::TitleLimitReached { .. } => "title_limit_reached",
+            ApiError::AgeRestricted { .. } => "age_restricted",
+            ApiError::NoCopiesAvailable(_) => "no_copies_available",
+            ApiError::RenewalLimitReached { .. } => "renewal_limit_reached",
+            ApiError::LoanOverdue => "loan_overdue",
+            ApiError::HoldsWaiting => "holds_waiting",
+            ApiError::CopiesAvailable => "copies_available",
+            ApiError::HoldAlreadyActive => "hold_already_active",
+            ApiError::PaymentExceedsBalance { .. } => "payment_exceeds_balance",
+            ApiError::NegativeStock { .. } => "negative_stock",
+            ApiError::StockAboveLimit { .. } => "stock_above_limit",
+            ApiError::BookOnLoan { .. } => "book_on_loan",
+            ApiError::AlreadyArchived => "already_archived",
+            ApiError::NotArchived => "not_archived",
+            ApiError::Duplicate => "duplicate",
+            ApiError::ForeignKeyViolation => "foreign_key_violation",
+            ApiError::ConstraintViolation => "constraint_violation",
+            ApiError::Database(_) | ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// Extra members merged into the problem body.
+    fn extensions(&self) -> Map<String, Value> {
+        let value = match self {
+            ApiError::Validation(errors) => json!({"errors": errors}),
+            ApiError::VersionMismatch { book_id: Some(id) } => json!({"book_id": id}),
+            ApiError::BookNotFound(id) | ApiError::NoCopiesAvailable(id) => json!({"book_id": id}),
+            ApiError::FinesBlockCheckout { balance_cents, limit_cents } => {
+                json!({"balance_cents": balance_cents, "limit_cents": limit_cents})
+            }
+            ApiError::MemberBlocked { reason } => json!({"reason": reason}),
+            ApiError::LoanLimitReached { max_open_loans, open_loans } => {
+                json!({"max_open_loans": max_open_loans, "open_loans": open_loans})
+            }
+            ApiError::TitleLimitReached { book_id, max_copies_per_title } => {
+                json!({"book_id": book_id, "max_copies_per_title": max_copies_per_title})
+            }
+            ApiError::AgeRestricted { book_id, min_age } => json!({"book_id": book_id, "min_age": min_age}),
+            ApiError::RenewalLimitReached { max_renewals } => json!({"max_renewals": max_renewals}),
+            ApiError::PaymentExceedsBalance { balance_cents } => json!({"balance_cents": balance_cents}),
+            ApiError::NegativeStock { book_id, number_of_copies } => {
+                json!({"book_id": book_id, "number_of_copies": number_of_copies})
+            }
+            ApiError::StockAboveLimit { book_id, number_of_copies, max_copies } => {
+                json!({"book_id": book_id, "number_of_copies": number_of_copies, "max_copies": max_copies})
+            }
+            ApiError::BookOnLoan { copies_on_loan } => json!({"copies_on_loan": copies_on_loan}),
+            ApiError::MemberHasLoans { open_loans } => json!({"open_loans": open_loans}),
+            _ => return Map::new(),
+        };
+        match value {
+            Value::Object(map) => map,
+            _ => unreachable!("extensions are always objects"),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::BadRequest(msg) => f.write_str(msg),
+            ApiError::Validation(errors) => write!(f, "request has {} invalid field(s)", errors.len()),
+            ApiError::InvalidCredentials => f.write_str("invalid credentials"),
+            ApiError::PreconditionRequired => f.write_str("If-Match header with the book's ETag is required"),
+            ApiError::VersionMismatch { .. } => {
+                f.write_str("book was modified by someone else; reload and try again")
+            }
+            ApiError::NotFound => f.write_str("resource not found"),
+            ApiError::BookNotFound(id) => write!(f, "book not found: {}", id),
+            ApiError::LoanNotFound => f.write_str("no active loan found"),
+            ApiError::HoldNotFound => f.write_str("no active hold found"),
+            ApiError::EmailTaken => f.write_str("a member with this email already exists"),
+            ApiError::AccountDeactivated => f.write_str("this account has been closed"),
+            ApiError::MemberHasLoans { .. } => {
+                f.write_str("return every borrowed book before closing the account")
+            }
+            ApiError::FinesBlockCheckout { .. } => f.write_str("outstanding fines exceed the borrowing limit"),
+            ApiError::MemberBlocked { .. } => f.write_str("borrowing has been suspended for this account by a librarian"),
+            ApiError::LoanLimitReached { max_open_loans, .. } => {
+                write!(f, "members may have at most {} books out at once; return some first", max_open_loans)
+            }
+            ApiError::TitleLimitReached { book_id, max_copies_per_title } => write!(
+                f,
+                "members may have at most {} copies of book {} out at once",
+                max_copies_per_title, book_id
+            ),
+            ApiError::AgeRestricted { book_id, min_age } => write!(
+                f,
+                "book {} is restricted to members aged {} or over; members without a recorded age cannot borrow it",
+                book_id, min_age
+            ),
+            ApiError::NoCopiesAvailable(id) => write!(f, "no copies available for book: {}", id),
+            ApiError::RenewalLimitReached { .. } => f.write_str("renewal limit reached"),
+            ApiError::LoanOverdue => f.write_str("overdue loans cannot be renewed; please return the book"),
+            ApiError::HoldsWaiting => f.write_str("other members are waiting for this book"),
+            ApiError::CopiesAvailable => f.write_str("copies are available; check the book out instead"),
+            ApiError::HoldAlreadyActive => f.write_str("you already have an active hold on this book"),
+            ApiError::PaymentExceedsBalance { .. } => f.write_str("payment exceeds outstanding balance"),
+            ApiError::NegativeStock { .. } => f.write_str("number_of_copies cannot go below zero"),
+            ApiError::StockAboveLimit { max_copies, .. } => {
+                write!(f, "number_of_copies cannot go above {}", max_copies)
+            }
+            ApiError::BookOnLoan { .. } => {
+                f.write_str("book still has copies on loan; wait for them to be returned")
+            }
+            ApiError::AlreadyArchived => f.write_str("book is already archived"),
+            ApiError::NotArchived => f.write_str("book is not archived"),
+            ApiError::Duplicate => f.write_str("a record with these values already exists"),
+            ApiError::ForeignKeyViolation => {
+                f.write_str("the request refers to a record that does not exist or is still in use")
+            }
+            ApiError::ConstraintViolation => f.write_str("a value is outside its allowed range"),
+            // Never echo database or library errors to clients.
+            ApiError::Database(_) | ApiError::Internal(_) => f.write_str("an internal error occurred"),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            ApiError::FinesBlockCheckout { .. }
+            | ApiError::AccountDeactivated
+            | ApiError::MemberBlocked { .. }
+            | ApiError::AgeRestricted { .. } => StatusCode::FORBIDDEN,
+            ApiError::NotFound | ApiError::BookNotFound(_) | ApiError::LoanNotFound | ApiError::HoldNotFound => {
+                StatusCode::NOT_FOUND
+            }
+            ApiError::VersionMismatch { .. } => StatusCode::PRECONDITION_FAILED,
+            ApiError::Validation(_) | ApiError::ConstraintViolation => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            ApiError::EmailTaken
+            | ApiError::MemberHasLoans { .. }
+            | ApiError::NoCopiesAvailable(_)
+            | ApiError::LoanLimitReached { .. }
+            | ApiError::TitleLimitReached { .. }
+            | ApiError::RenewalLimitReached { .. }
+            | ApiError::LoanOverdue
+            | ApiError::HoldsWaiting
+            | ApiError::CopiesAvailable
+            | ApiError::HoldAlreadyActive
+            | ApiError::PaymentExceedsBalance { .. }
+            | ApiError::NegativeStock { .. }
+            | ApiError::StockAboveLimit { .. }
+            | ApiError::BookOnLoan { .. }
+            | ApiError::AlreadyArchived
+            | ApiError::NotArchived
+            | ApiError::Duplicate
+            | ApiError::ForeignKeyViolation => StatusCode::CONFLICT,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            ApiError::Database(e) => error!(error = %e, "database error"),
+            ApiError::Internal(e) => error!(error = %e, "internal error"),
+            _ => {}
+        }
+        problem(self.status_code(), self.code(), &self.to_string(), self.extensions())
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::RowNotFound => ApiError::NotFound,
+            sqlx::Error::Database(db) if db.is_unique_violation() => ApiError::Duplicate,
+            sqlx::Error::Database(db) if db.is_foreign_key_violation() => ApiError::ForeignKeyViolation,
+            sqlx::Error::Database(db) if db.is_check_violation() => ApiError::ConstraintViolation,
+            _ => ApiError::Database(e),
+        }
+    }
+}
+
+impl From<actix_web::error::BlockingError> for ApiError {
+    fn from(e: actix_web::error::BlockingError) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+impl From<argon2::password_hash::Error> for ApiError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for ApiError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+/// Turns actix's plain-text JSON, query and path extractor failures into
+/// problem documents like every other error.
+pub fn bad_request(err: impl fmt::Display) -> actix_web::Error {
+    actix_web::error::InternalError::from_response(
+        err.to_string(),
+        problem(StatusCode::BAD_REQUEST, "bad_request", &err.to_string(), Map::new()),
+    )
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+
+    async fn body_of(err: ApiError) -> (StatusCode, Value) {
+        let res = err.error_response();
+        let status = res.status();
+        let bytes = to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[actix_web::test]
+    async fn problems_carry_status_code_and_extensions() {
+        let (status, body) = body_of(ApiError::FinesBlockCheckout {
+            balance_cents: 1_250,
+            limit_cents: 1_000,
+        })
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["status"], 403);
+        assert_eq!(body["title"], "Forbidden");
+        assert_eq!(body["code"], "fines_block_checkout");
+        assert_eq!(body["balance_cents"], 1_250);
+        assert_eq!(body["limit_cents"], 1_000);
+    }
+
+    #[actix_web::test]
+    async fn internal_errors_do_not_leak_details() {
+        let (status, body) = body_of(ApiError::Internal("connection refused to 10.0.0.5".into())).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["code"], "internal_error");
+        assert!(!body["detail"].as_str().unwrap().contains("10.0.0.5"));
+    }
+
+    #[actix_web::test]
+    async fn missing_rows_map_to_not_found() {
+        let (status, body) = body_of(sqlx::Error::RowNotFound.into()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "not_found");
+    }
+}
@@ -0,0 +1,75 @@
+use chrono::NaiveDateTime;
+
+/// Late-return pricing and the balance above which borrowing is suspended.
+/// Amounts are in cents so they stay exact in the database.
+#[derive(Debug, Clone)]
+pub struct FinePolicy {
+    pub daily_rate_cents: i32,
+    pub block_threshold_cents: i64,
+}
+
+impl Default for FinePolicy {
+    fn default() -> Self {
+        Self {
+            daily_rate_cents: 25,
+            block_threshold_cents: 1_000,
+        }
+    }
+}
+
+impl FinePolicy {
+    /// Fee for returning at `actual` a loan due at `expected`. Every started
+    /// day past the due time counts as a full day.
+    pub fn late_fee_cents(&self, expected: NaiveDateTime, actual: NaiveDateTime) -> i32 {
+        let late = actual - expected;
+        if late <= chrono::Duration::zero() {
+            return 0;
+        }
+        let days = (late.num_seconds() + 86_399) / 86_400;
+        i32::try_from(days)
+            .unwrap_or(i32::MAX)
+            .saturating_mul(self.daily_rate_cents)
+    }
+
+    pub fn blocks_checkout(&self, balance_cents: i64) -> bool {
+        balance_cents > self.block_threshold_cents
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, day)
+            .unwrap()
+            .and_hms_opt(hour, 0, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn on_time_returns_are_free() {
+        let policy = FinePolicy::default();
+        assert_eq!(policy.late_fee_cents(at(10, 12), at(9, 12)), 0);
+        assert_eq!(policy.late_fee_cents(at(10, 12), at(10, 12)), 0);
+    }
+
+    #[test]
+    fn partial_days_round_up() {
+        let policy = FinePolicy {
+            daily_rate_cents: 50,
+            ..FinePolicy::default()
+        };
+        assert_eq!(policy.late_fee_cents(at(10, 12), at(10, 13)), 50);
+        assert_eq!(policy.late_fee_cents(at(10, 12), at(11, 12)), 50);
+        assert_eq!(policy.late_fee_cents(at(10, 12), at(13, 11)), 150);
+    }
+
+    #[test]
+    fn threshold_is_exclusive() {
+        let policy = FinePolicy::default();
+        assert!(!policy.blocks_checkout(policy.block_threshold_cents));
+        assert!(policy.blocks_checkout(policy.block_threshold_cents + 1));
+    }
+}
@@ -1,265 +1,1963 @@
-use actix_web::{web, HttpResponse, Responder};
-use chrono::{NaiveDateTime, Utc};
+use actix_web::http::header;
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde_json::json;
-use sqlx::PgPool;
 
+use crate::auth::{self, AuthKeys, AuthenticatedLibrarian, AuthenticatedMember, Role};
+use crate::error::{ApiError, Problem};
+use crate::fines::FinePolicy;
+use crate::holds::HoldPolicy;
+use crate::loans::LoanPolicy;
+use crate::metrics::Metrics;
 use crate::models::*;
+use crate::pagination::{Cursor, Page, PageRequest, SortKey, SortOrder};
+use crate::repo::{LibraryRepository, NewMember};
+use crate::validation::ValidJson;
+
+type ApiResult = Result<HttpResponse, ApiError>;
 
 // ── Member: Register ───────────────────────────────────────────────────
 
-pub async fn register(
-    pool: web::Data<PgPool>,
-    body: web::Json<RegisterRequest>,
-) -> impl Responder {
-    let full_name = format!("{} {}", body.first_name, body.last_name);
-    let result = sqlx::query_scalar::<_, i32>(
-        "INSERT INTO members (name, address, age, email) VALUES ($1, $2, $3, $4) RETURNING member_id",
+#[utoipa::path(
+    post, path = "/api/register", tag = "auth",
+    request_body = RegisterRequest,
+    responses(
+        (status = 201, description = "Member created", body = Object),
+        (status = 409, description = "Email already registered", body = Problem),
+        (status = 422, description = "Invalid fields", body = Problem),
     )
-    .bind(&full_name)
-    .bind(&body.address)
-    .bind(body.age)
-    .bind(&body.email)
-    .fetch_one(pool.get_ref())
-    .await;
-
-    match result {
-        Ok(member_id) => HttpResponse::Created().json(json!({
-            "member_id": member_id,
-            "name": full_name,
-            "message": "Registration successful"
-        })),
-        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
-    }
+)]
+#[tracing::instrument(skip_all, fields(member_id))]
+pub async fn register<R: LibraryRepository>(
+    repo: web::Data<R>,
+    body: ValidJson<RegisterRequest>,
+) -> ApiResult {
+    let password = body.password.clone();
+    let password_hash = web::block(move || auth::hash_password(&password)).await??;
+
+    let full_name = format!("{} {}", body.first_name, body.last_name);
+    let member_id = repo
+        .create_member(NewMember {
+            name: full_name.clone(),
+            address: body.address.clone(),
+            age: body.age,
+            email: body.email.trim().to_string(),
+            password_hash,
+        })
+        .await?;
+    tracing::Span::current().record("member_id", member_id);
+
+    Ok(HttpResponse::Created().json(json!({
+        "member_id": member_id,
+        "name": full_name,
+        "message": "Registration successful"
+    })))
 }
 
 // ── Member: Login ──────────────────────────────────────────────────────
 
-pub async fn login(
-    pool: web::Data<PgPool>,
+#[utoipa::path(
+    post, path = "/api/login", tag = "auth",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, description = "Session token for the member", body = Object),
+        (status = 401, description = "Unknown member or wrong password", body = Problem),
+        (status = 403, description = "Account has been closed", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(member_id = body.member_id))]
+pub async fn login<R: LibraryRepository>(
+    repo: web::Data<R>,
+    keys: web::Data<AuthKeys>,
     body: web::Json<LoginRequest>,
-) -> impl Responder {
-    let result = sqlx::query_scalar::<_, String>(
-        "SELECT name FROM members WHERE member_id = $1",
+) -> ApiResult {
+    let member = repo.find_member(body.member_id).await?;
+
+    // Members created before passwords existed have no hash and cannot log in.
+    // Like unknown ids, they still wait for a hash check, so the response
+    // time does not reveal which member ids exist.
+    let hash = member.as_ref().and_then(|m| m.password_hash.clone());
+    let password = body.password.clone();
+    let verified =
+        web::block(move || auth::verify_password_or_dummy(&password, hash.as_deref())).await?;
+    let member = match member {
+        Some(member) if verified => member,
+        _ => return Err(ApiError::InvalidCredentials),
+    };
+    if member.deactivated_at.is_some() {
+        return Err(ApiError::AccountDeactivated);
+    }
+
+    let token = keys.issue(member.member_id, Role::Member)?;
+    Ok(HttpResponse::Ok().json(json!({
+        "member_id": member.member_id,
+        "name": member.name,
+        "token": token,
+        "token_type": "Bearer",
+        "expires_in": auth::SESSION_TTL.num_seconds(),
+        "message": "Login successful"
+    })))
+}
+
+// ── Member: Profile ────────────────────────────────────────────────────
+
+#[utoipa::path(
+    get, path = "/api/members/{member_id}", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The member's profile", body = Member),
+        (status = 403, description = "Account closed or erased", body = Problem),
     )
-    .bind(body.member_id)
-    .fetch_optional(pool.get_ref())
-    .await;
-
-    match result {
-        Ok(Some(name)) => HttpResponse::Ok().json(json!({
-            "member_id": body.member_id,
-            "name": name,
-            "message": "Login successful"
-        })),
-        Ok(None) => HttpResponse::Unauthorized().json(json!({"error": "invalid member ID"})),
-        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id))]
+pub async fn get_member<R: LibraryRepository>(
+    repo: web::Data<R>,
+    member: AuthenticatedMember<R>,
+) -> ApiResult {
+    let profile = repo
+        .find_member(member.member_id)
+        .await?
+        .filter(|m| m.erased_at.is_none())
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(HttpResponse::Ok().json(profile))
+}
+
+#[utoipa::path(
+    patch, path = "/api/members/{member_id}", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token")),
+    request_body = UpdateMemberRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The updated profile", body = Member),
+        (status = 400, description = "No fields to update", body = Problem),
+        (status = 403, description = "Account closed or erased", body = Problem),
+        (status = 409, description = "Email already registered", body = Problem),
+        (status = 422, description = "Invalid fields", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id))]
+pub async fn update_member<R: LibraryRepository>(
+    repo: web::Data<R>,
+    member: AuthenticatedMember<R>,
+    body: ValidJson<UpdateMemberRequest>,
+) -> ApiResult {
+    let mut changes = body.0;
+    if changes.is_empty() {
+        return Err(ApiError::BadRequest("no fields to update".into()));
     }
+    changes.email = changes.email.map(|e| e.trim().to_string());
+
+    let profile = repo.update_member(member.member_id, &changes).await?;
+    Ok(HttpResponse::Ok().json(profile))
 }
 
-// ── Member: Checkout ───────────────────────────────────────────────────
+/// Closes the account: the member can no longer log in, borrow or hold
+/// books, and their active holds are cancelled. Loan history is kept.
+#[utoipa::path(
+    post, path = "/api/members/{member_id}/deactivate", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Account closed", body = Object),
+        (status = 409, description = "Books are still on loan", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id))]
+pub async fn deactivate_member<R: LibraryRepository>(
+    repo: web::Data<R>,
+    hold_policy: web::Data<HoldPolicy>,
+    member: AuthenticatedMember<R>,
+) -> ApiResult {
+    let deactivated_at = repo
+        .deactivate_member(member.member_id, Utc::now().naive_utc(), &hold_policy)
+        .await?;
 
-pub async fn checkout(
-    pool: web::Data<PgPool>,
-    path: web::Path<i32>,
-    body: web::Json<CheckoutRequest>,
-) -> impl Responder {
-    let member_id = path.into_inner();
-    let now = Utc::now().naive_utc();
-    let expected_return = now + chrono::Duration::days(14);
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Account closed",
+        "deactivated_at": deactivated_at
+    })))
+}
 
-    let mut tx = match pool.begin().await {
-        Ok(tx) => tx,
-        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
-    };
+/// Everything stored about the member, as a JSON download.
+#[utoipa::path(
+    get, path = "/api/members/{member_id}/export", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Profile, loans, fines, holds and incidents", body = MemberExport),
+        (status = 404, description = "Member does not exist", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id))]
+pub async fn export_member<R: LibraryRepository>(
+    repo: web::Data<R>,
+    member: AuthenticatedMember<R>,
+) -> ApiResult {
+    let export = repo.export_member(member.member_id).await?;
 
-    for &book_id in &body.book_ids {
-        // Check availability
-        let copies: Option<i32> = sqlx::query_scalar(
-            "SELECT number_of_copies FROM books WHERE book_id = $1",
-        )
-        .bind(book_id)
-        .fetch_optional(&mut *tx)
-        .await
-        .unwrap_or(None);
+    Ok(HttpResponse::Ok()
+        .insert_header(header::ContentDisposition::attachment(format!(
+            "member-{}.json",
+            member.member_id
+        )))
+        .json(export))
+}
 
-        match copies {
-            None => {
-                return HttpResponse::NotFound().json(json!({"error": format!("book not found: {}", book_id)}));
-            }
-            Some(c) if c <= 0 => {
-                return HttpResponse::Conflict().json(json!({"error": format!("no copies available for book: {}", book_id)}));
-            }
-            _ => {}
-        }
+/// Closes the account and overwrites the member's personal details. Loans,
+/// fines and holds stay, no longer tied to a person, so library statistics
+/// do not change.
+#[utoipa::path(
+    delete, path = "/api/members/{member_id}", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token")),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Personal data erased", body = Object),
+        (status = 409, description = "Books are still on loan", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id))]
+pub async fn erase_member<R: LibraryRepository>(
+    repo: web::Data<R>,
+    hold_policy: web::Data<HoldPolicy>,
+    member: AuthenticatedMember<R>,
+) -> ApiResult {
+    repo.erase_member(member.member_id, Utc::now().naive_utc(), &hold_policy)
+        .await?;
 
-        // Decrement copies
-        let _ = sqlx::query("UPDATE books SET number_of_copies = number_of_copies - 1 WHERE book_id = $1")
-            .bind(book_id)
-            .execute(&mut *tx)
-            .await;
+    Ok(HttpResponse::Ok().json(json!({"message": "Personal data erased"})))
+}
 
-        // Insert ledger entry
-        let _ = sqlx::query(
-            "INSERT INTO book_borrow_ledger (book_id, member_id, borrow_date, expected_return) VALUES ($1, $2, $3, $4)",
-        )
-        .bind(book_id)
-        .bind(member_id)
-        .bind(now)
-        .bind(expected_return)
-        .execute(&mut *tx)
-        .await;
-    }
+// ── Member: Checkout ───────────────────────────────────────────────────
 
-    if let Err(e) = tx.commit().await {
-        return HttpResponse::InternalServerError().json(json!({"error": e.to_string()}));
-    }
+#[utoipa::path(
+    post, path = "/api/members/{member_id}/checkout", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token")),
+    request_body = CheckoutRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "All books checked out", body = Object),
+        (status = 403, description = "Account closed or blocked, outstanding fines block borrowing, or a title is age-restricted", body = Problem),
+        (status = 404, description = "A book does not exist or is archived", body = Problem),
+        (status = 409, description = "A book has no copies left, or the loan or per-title limit would be exceeded; nothing was checked out", body = Problem),
+        (status = 422, description = "Empty, duplicate or invalid book_ids", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id, book_ids = ?body.book_ids))]
+pub async fn checkout<R: LibraryRepository>(
+    repo: web::Data<R>,
+    policy: web::Data<FinePolicy>,
+    loan_policy: web::Data<LoanPolicy>,
+    metrics: web::Data<Metrics>,
+    member: AuthenticatedMember<R>,
+    body: ValidJson<CheckoutRequest>,
+) -> ApiResult {
+    let now = Utc::now().naive_utc();
+    let expected_return = repo
+        .checkout(member.member_id, &body.book_ids, now, &policy, &loan_policy)
+        .await?;
+    metrics.checkouts.inc_by(body.book_ids.len() as u64);
 
-    HttpResponse::Ok().json(json!({
+    Ok(HttpResponse::Ok().json(json!({
         "message": "Checkout successful",
         "books_checked": body.book_ids.len(),
         "expected_return": expected_return.format("%Y-%m-%d").to_string()
-    }))
+    })))
 }
 
 // ── Member: Borrowed Books ─────────────────────────────────────────────
 
-pub async fn borrowed_books(
-    pool: web::Data<PgPool>,
-    path: web::Path<i32>,
-) -> impl Responder {
-    let member_id = path.into_inner();
+#[utoipa::path(
+    get, path = "/api/members/{member_id}/borrowed", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token")),
+    security(("bearer" = [])),
+    responses((status = 200, description = "The member's open loans, newest first", body = Vec<Object>))
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id))]
+pub async fn borrowed_books<R: LibraryRepository>(
+    repo: web::Data<R>,
+    member: AuthenticatedMember<R>,
+) -> ApiResult {
     let now = Utc::now().naive_utc();
+    let ledgers = repo.open_loans(member.member_id).await?;
+
+    let result: Vec<_> = ledgers
+        .into_iter()
+        .map(|l| {
+            let is_overdue = now > l.expected_return;
+            json!({
+                "id": l.id,
+                "book_id": l.book_id,
+                "member_id": l.member_id,
+                "borrow_date": l.borrow_date.to_string(),
+                "expected_return": l.expected_return.to_string(),
+                "actual_return": l.actual_return.map(|d| d.to_string()),
+                "return_condition": l.return_condition,
+                "renewal_count": l.renewal_count,
+                "renewals_left": (l.max_renewals - l.renewal_count).max(0),
+                "book_name": l.book_name,
+                "is_overdue": is_overdue,
+            })
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(result))
+}
+
+// ── Member: Loan History ───────────────────────────────────────────────
 
-    let rows = sqlx::query_as::<_, BorrowLedger>(
-        r#"SELECT l.id, l.book_id, l.member_id, l.borrow_date, l.expected_return,
-                  l.actual_return, l.return_condition, b.name AS book_name
-           FROM book_borrow_ledger l
-           JOIN books b ON b.book_id = l.book_id
-           WHERE l.member_id = $1 AND l.actual_return IS NULL
-           ORDER BY l.borrow_date DESC"#,
+#[utoipa::path(
+    get, path = "/api/members/{member_id}/history", tag = "members",
+    params(
+        ("member_id" = i32, Path, description = "Must match the member in the bearer token"),
+        LoanQuery,
+    ),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "One page of the member's loans, open and returned, newest first; or all of them as CSV",
+            content((Page<BorrowLedger> = "application/json"), (String = "text/csv"))),
+        (status = 400, description = "Cursor is invalid or the date range is reversed", body = Problem),
     )
-    .bind(member_id)
-    .fetch_all(pool.get_ref())
-    .await;
-
-    match rows {
-        Ok(ledgers) => {
-            let result: Vec<_> = ledgers
-                .into_iter()
-                .map(|l| {
-                    let is_overdue = now > l.expected_return;
-                    json!({
-                        "id": l.id,
-                        "book_id": l.book_id,
-                        "member_id": l.member_id,
-                        "borrow_date": l.borrow_date.to_string(),
-                        "expected_return": l.expected_return.to_string(),
-                        "actual_return": l.actual_return.map(|d| d.to_string()),
-                        "return_condition": l.return_condition,
-                        "book_name": l.book_name,
-                        "is_overdue": is_overdue,
-                    })
-                })
-                .collect();
-            HttpResponse::Ok().json(result)
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id))]
+pub async fn loan_history<R: LibraryRepository>(
+    repo: web::Data<R>,
+    member: AuthenticatedMember<R>,
+    query: web::Query<LoanQuery>,
+) -> ApiResult {
+    let filename = format!("loans-member-{}.csv", member.member_id);
+    loan_listing(repo.get_ref(), Some(member.member_id), &query, &filename).await
+}
+
+/// Loan listings are newest first. Their cursors hold the last row's borrow
+/// time in nanoseconds, so they round-trip through every backend exactly.
+const LOAN_CURSOR_SORT: &str = "borrow_date";
+
+/// Columns of a CSV loan export, in `BorrowLedger` field order.
+const LOAN_CSV_HEADER: [&str; 10] = [
+    "id",
+    "book_id",
+    "member_id",
+    "borrow_date",
+    "expected_return",
+    "actual_return",
+    "return_condition",
+    "renewal_count",
+    "max_renewals",
+    "book_name",
+];
+
+fn loan_cursor(last: &BorrowLedger) -> Option<String> {
+    let cursor = Cursor {
+        sort: LOAN_CURSOR_SORT.to_string(),
+        order: SortOrder::Desc,
+        key: SortKey::Int(last.borrow_date.and_utc().timestamp_nanos_opt()?),
+        id: last.id,
+    };
+    Some(cursor.encode())
+}
+
+fn loan_position(token: &str) -> Option<(NaiveDateTime, i32)> {
+    match Cursor::decode(token)? {
+        Cursor {
+            sort,
+            order: SortOrder::Desc,
+            key: SortKey::Int(nanos),
+            id,
+        } if sort == LOAN_CURSOR_SORT => Some((DateTime::from_timestamp_nanos(nanos).naive_utc(), id)),
+        _ => None,
+    }
+}
+
+fn loans_csv(loans: &[BorrowLedger]) -> Result<Vec<u8>, csv::Error> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer.write_record(LOAN_CSV_HEADER)?;
+    for loan in loans {
+        writer.serialize(loan)?;
+    }
+    writer.into_inner().map_err(|e| e.into_error().into())
+}
+
+/// Answers a loan listing of one member or, with `member_id` unset, of
+/// everyone: a page of JSON, or every match as a CSV download.
+async fn loan_listing<R: LibraryRepository>(
+    repo: &R,
+    member_id: Option<i32>,
+    query: &LoanQuery,
+    filename: &str,
+) -> ApiResult {
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return Err(ApiError::BadRequest("from must not be after to".into()));
         }
-        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
     }
+    let now = Utc::now().naive_utc();
+
+    if query.format == LoanFormat::Csv {
+        let (loans, _) = repo.list_loans(member_id, query, now, None, 0, None).await?;
+        let body = loans_csv(&loans).map_err(|e| ApiError::Internal(e.to_string()))?;
+        return Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header(header::ContentDisposition::attachment(filename))
+            .body(body));
+    }
+
+    let after = match query.cursor.as_deref() {
+        None => None,
+        Some(token) => Some(loan_position(token).ok_or_else(|| {
+            ApiError::BadRequest("cursor is invalid or was issued for a different listing".into())
+        })?),
+    };
+    let request = PageRequest::new(query.limit, query.page, after.is_some());
+    let (loans, total) = repo
+        .list_loans(member_id, query, now, after, request.offset, Some(request.fetch_limit()))
+        .await?;
+
+    Ok(HttpResponse::Ok().json(request.page(loans, total, loan_cursor)))
+}
+
+// ── Member: Renew Loan ─────────────────────────────────────────────────
+
+#[utoipa::path(
+    post, path = "/api/members/{member_id}/loans/{ledger_id}/renew", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token"), ("ledger_id" = i32, Path)),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "New due date", body = Object),
+        (status = 404, description = "No such open loan", body = Problem),
+        (status = 409, description = "Limit reached, loan overdue or holds waiting", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id, ledger_id = path.1, book_id))]
+pub async fn renew_loan<R: LibraryRepository>(
+    repo: web::Data<R>,
+    loan_policy: web::Data<LoanPolicy>,
+    metrics: web::Data<Metrics>,
+    member: AuthenticatedMember<R>,
+    path: web::Path<(i32, i32)>,
+) -> ApiResult {
+    let (_, ledger_id) = path.into_inner();
+    let now = Utc::now().naive_utc();
+
+    let loan = repo
+        .renew_loan(member.member_id, ledger_id, now, &loan_policy)
+        .await?;
+    tracing::Span::current().record("book_id", loan.book_id);
+    metrics.renewals.inc();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Loan renewed",
+        "ledger_id": ledger_id,
+        "expected_return": loan.expected_return.format("%Y-%m-%d").to_string(),
+        "renewal_count": loan.renewal_count,
+        "renewals_left": loan.max_renewals - loan.renewal_count
+    })))
 }
 
 // ── Member: Return Book ────────────────────────────────────────────────
 
-pub async fn return_book(
-    pool: web::Data<PgPool>,
-    path: web::Path<i32>,
-    body: web::Json<ReturnRequest>,
-) -> impl Responder {
-    let member_id = path.into_inner();
+#[utoipa::path(
+    post, path = "/api/members/{member_id}/return", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token")),
+    request_body = ReturnRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Loan closed; includes any late fee and incident", body = Object),
+        (status = 404, description = "Member has no open loan of this book", body = Problem),
+        (status = 422, description = "Blank or overlong notes", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id, book_id = body.book_id))]
+pub async fn return_book<R: LibraryRepository>(
+    repo: web::Data<R>,
+    policy: web::Data<FinePolicy>,
+    hold_policy: web::Data<HoldPolicy>,
+    metrics: web::Data<Metrics>,
+    member: AuthenticatedMember<R>,
+    body: ValidJson<ReturnRequest>,
+) -> ApiResult {
     let now = Utc::now().naive_utc();
+    let outcome = repo
+        .return_book(member.member_id, &body, now, &policy, &hold_policy)
+        .await?;
+    metrics.record_return(body.condition);
 
-    let result = sqlx::query(
-        "UPDATE book_borrow_ledger SET actual_return = $1, return_condition = 'good' WHERE book_id = $2 AND member_id = $3 AND actual_return IS NULL",
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Book returned successfully",
+        "ledger_id": outcome.ledger_id,
+        "condition": body.condition,
+        "incident_id": outcome.incident_id,
+        "late_fee_cents": outcome.late_fee_cents,
+        "reserved_for_hold": outcome.reserved_for_hold
+    })))
+}
+
+// ── Member: Fines ──────────────────────────────────────────────────────
+
+#[utoipa::path(
+    get, path = "/api/members/{member_id}/fines", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token")),
+    security(("bearer" = [])),
+    responses((status = 200, description = "Balance and every charge and payment", body = Object))
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id))]
+pub async fn member_fines<R: LibraryRepository>(
+    repo: web::Data<R>,
+    member: AuthenticatedMember<R>,
+) -> ApiResult {
+    let entries = repo.fines(member.member_id).await?;
+
+    let balance_cents: i64 = entries.iter().map(|f| i64::from(f.amount_cents)).sum();
+    Ok(HttpResponse::Ok().json(json!({
+        "member_id": member.member_id,
+        "balance_cents": balance_cents,
+        "entries": entries
+    })))
+}
+
+#[utoipa::path(
+    post, path = "/api/members/{member_id}/fines/payments", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token")),
+    request_body = FinePaymentRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Payment recorded; new balance", body = Object),
+        (status = 400, description = "Amount is not positive", body = Problem),
+        (status = 409, description = "Amount exceeds the balance", body = Problem),
     )
-    .bind(now)
-    .bind(body.book_id)
-    .bind(member_id)
-    .execute(pool.get_ref())
-    .await;
-
-    match result {
-        Ok(r) if r.rows_affected() == 0 => {
-            HttpResponse::NotFound().json(json!({"error": "no active borrow record found"}))
-        }
-        Ok(_) => {
-            let _ = sqlx::query("UPDATE books SET number_of_copies = number_of_copies + 1 WHERE book_id = $1")
-                .bind(body.book_id)
-                .execute(pool.get_ref())
-                .await;
-            HttpResponse::Ok().json(json!({"message": "Book returned successfully"}))
-        }
-        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id))]
+pub async fn pay_fine<R: LibraryRepository>(
+    repo: web::Data<R>,
+    member: AuthenticatedMember<R>,
+    body: web::Json<FinePaymentRequest>,
+) -> ApiResult {
+    if body.amount_cents <= 0 {
+        return Err(ApiError::BadRequest("amount_cents must be positive".into()));
     }
+
+    let balance_cents = repo
+        .pay_fine(member.member_id, body.amount_cents, Utc::now().naive_utc())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Payment recorded",
+        "balance_cents": balance_cents
+    })))
+}
+
+// ── Member: Holds ──────────────────────────────────────────────────────
+
+#[utoipa::path(
+    post, path = "/api/members/{member_id}/holds", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token")),
+    request_body = HoldRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 201, description = "Joined the queue", body = Hold),
+        (status = 403, description = "Account has been closed", body = Problem),
+        (status = 404, description = "Book does not exist or is archived", body = Problem),
+        (status = 409, description = "Copies are available or a hold is already active", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id, book_id = body.book_id))]
+pub async fn place_hold<R: LibraryRepository>(
+    repo: web::Data<R>,
+    member: AuthenticatedMember<R>,
+    body: web::Json<HoldRequest>,
+) -> ApiResult {
+    let hold = repo
+        .place_hold(member.member_id, body.book_id, Utc::now().naive_utc())
+        .await?;
+
+    Ok(HttpResponse::Created().json(hold))
+}
+
+#[utoipa::path(
+    get, path = "/api/members/{member_id}/holds", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token")),
+    security(("bearer" = [])),
+    responses((status = 200, description = "Waiting and ready holds with queue positions", body = Vec<Hold>))
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id))]
+pub async fn list_holds<R: LibraryRepository>(
+    repo: web::Data<R>,
+    member: AuthenticatedMember<R>,
+) -> ApiResult {
+    let holds = repo.active_holds(member.member_id).await?;
+
+    Ok(HttpResponse::Ok().json(holds))
+}
+
+#[utoipa::path(
+    delete, path = "/api/members/{member_id}/holds/{hold_id}", tag = "members",
+    params(("member_id" = i32, Path, description = "Must match the member in the bearer token"), ("hold_id" = i32, Path)),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Hold cancelled", body = Object),
+        (status = 404, description = "No such active hold", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(member_id = member.member_id, hold_id = path.1))]
+pub async fn cancel_hold<R: LibraryRepository>(
+    repo: web::Data<R>,
+    hold_policy: web::Data<HoldPolicy>,
+    member: AuthenticatedMember<R>,
+    path: web::Path<(i32, i32)>,
+) -> ApiResult {
+    let (_, hold_id) = path.into_inner();
+    repo.cancel_hold(
+        member.member_id,
+        hold_id,
+        Utc::now().naive_utc(),
+        &hold_policy,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok().json(json!({"message": "Hold cancelled"})))
+}
+
+// ── Librarian: Login ───────────────────────────────────────────────────
+
+#[utoipa::path(
+    post, path = "/api/librarian/login", tag = "auth",
+    request_body = LibrarianLoginRequest,
+    responses(
+        (status = 200, description = "Session token for the librarian", body = Object),
+        (status = 401, description = "Unknown username or wrong password", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(username = %body.username))]
+pub async fn librarian_login<R: LibraryRepository>(
+    repo: web::Data<R>,
+    keys: web::Data<AuthKeys>,
+    body: web::Json<LibrarianLoginRequest>,
+) -> ApiResult {
+    let librarian = repo.find_librarian(&body.username).await?;
+
+    // Unknown usernames wait for a hash check too, so they take as long.
+    let password = body.password.clone();
+    let hash = librarian.as_ref().map(|l| l.password_hash.clone());
+    let verified =
+        web::block(move || auth::verify_password_or_dummy(&password, hash.as_deref())).await?;
+    let librarian = match librarian {
+        Some(librarian) if verified => librarian,
+        _ => return Err(ApiError::InvalidCredentials),
+    };
+
+    let token = keys.issue(librarian.librarian_id, Role::Librarian)?;
+    Ok(HttpResponse::Ok().json(json!({
+        "librarian_id": librarian.librarian_id,
+        "username": librarian.username,
+        "token": token,
+        "token_type": "Bearer",
+        "expires_in": auth::SESSION_TTL.num_seconds(),
+        "message": "Login successful"
+    })))
 }
 
 // ── Librarian: List Books ──────────────────────────────────────────────
 
-pub async fn list_books(pool: web::Data<PgPool>) -> impl Responder {
-    let result = sqlx::query_as::<_, Book>(
-        "SELECT book_id, name, author, number_of_copies, publication_year, edition FROM books ORDER BY name",
+#[utoipa::path(
+    get, path = "/api/books", tag = "books",
+    params(ListBooksQuery),
+    responses(
+        (status = 200, description = "One page of books", body = Page<Book>),
+        (status = 400, description = "Cursor is invalid or from another sort", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub async fn list_books<R: LibraryRepository>(
+    repo: web::Data<R>,
+    query: web::Query<ListBooksQuery>,
+) -> ApiResult {
+    let sort = query.sort;
+    let order = query.order;
+
+    let cursor = match query.cursor.as_deref().map(Cursor::decode) {
+        None => None,
+        Some(Some(c)) if c.sort == sort.as_str() && c.order == order => Some(c),
+        Some(_) => {
+            return Err(ApiError::BadRequest(
+                "cursor is invalid or was issued for a different sort".into(),
+            ))
+        }
+    };
+    let request = PageRequest::new(query.limit, query.page, cursor.is_some());
+    let (books, total) = repo
+        .list_books(&query, cursor.as_ref(), request.offset, request.fetch_limit())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(request.page(books, total, |last| {
+        let cursor = Cursor {
+            sort: sort.as_str().to_string(),
+            order,
+            key: sort.key(last),
+            id: last.book_id,
+        };
+        Some(cursor.encode())
+    })))
+}
+
+// ── Librarian: Loans ───────────────────────────────────────────────────
+
+#[utoipa::path(
+    get, path = "/api/loans", tag = "loans",
+    params(LedgerQuery, LoanQuery),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "One page of every member's loans, open and returned, newest first; or all of them as CSV",
+            content((Page<BorrowLedger> = "application/json"), (String = "text/csv"))),
+        (status = 400, description = "Cursor is invalid or the date range is reversed", body = Problem),
+        (status = 403, description = "Not a librarian token", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(librarian_id = librarian.librarian_id))]
+pub async fn list_loans<R: LibraryRepository>(
+    repo: web::Data<R>,
+    librarian: AuthenticatedLibrarian,
+    ledger: web::Query<LedgerQuery>,
+    query: web::Query<LoanQuery>,
+) -> ApiResult {
+    loan_listing(repo.get_ref(), ledger.member_id, &query, "loans.csv").await
+}
+
+// ── Librarian: Member Blocks ───────────────────────────────────────────
+
+/// Suspends a member's borrowing until a librarian lifts the block. They can
+/// still log in, return books, pay fines and manage holds.
+#[utoipa::path(
+    put, path = "/api/librarian/members/{member_id}/block", tag = "loans",
+    params(("member_id" = i32, Path)),
+    request_body = BlockMemberRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The member, now blocked", body = Member),
+        (status = 403, description = "Not a librarian token", body = Problem),
+        (status = 404, description = "Member does not exist or was erased", body = Problem),
+        (status = 422, description = "Missing or overlong reason", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(librarian_id = librarian.librarian_id, member_id = *path))]
+pub async fn block_member<R: LibraryRepository>(
+    repo: web::Data<R>,
+    librarian: AuthenticatedLibrarian,
+    path: web::Path<i32>,
+    body: ValidJson<BlockMemberRequest>,
+) -> ApiResult {
+    let now = Utc::now().naive_utc();
+    let member = repo
+        .set_member_block(path.into_inner(), Some((body.reason.trim(), now)))
+        .await?;
+    Ok(HttpResponse::Ok().json(member))
+}
+
+#[utoipa::path(
+    delete, path = "/api/librarian/members/{member_id}/block", tag = "loans",
+    params(("member_id" = i32, Path)),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "The member, free to borrow again", body = Member),
+        (status = 403, description = "Not a librarian token", body = Problem),
+        (status = 404, description = "Member does not exist or was erased", body = Problem),
     )
-    .fetch_all(pool.get_ref())
-    .await;
+)]
+#[tracing::instrument(skip_all, fields(librarian_id = librarian.librarian_id, member_id = *path))]
+pub async fn unblock_member<R: LibraryRepository>(
+    repo: web::Data<R>,
+    librarian: AuthenticatedLibrarian,
+    path: web::Path<i32>,
+) -> ApiResult {
+    let member = repo.set_member_block(path.into_inner(), None).await?;
+    Ok(HttpResponse::Ok().json(member))
+}
+
+// ── Librarian: Book Detail ─────────────────────────────────────────────
 
-    match result {
-        Ok(books) => HttpResponse::Ok().json(books),
-        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+fn etag(version: i32) -> String {
+    format!("\"{}\"", version)
+}
+
+/// The version a client expects from `If-Match`; `Ok(None)` for `*`.
+fn if_match_version(req: &HttpRequest) -> Result<Option<i32>, ApiError> {
+    let malformed = || ApiError::BadRequest("If-Match must be an ETag returned by this API".into());
+    let value = req
+        .headers()
+        .get(header::IF_MATCH)
+        .ok_or(ApiError::PreconditionRequired)?
+        .to_str()
+        .map_err(|_| malformed())?
+        .trim();
+    if value == "*" {
+        return Ok(None);
     }
+    value
+        .trim_start_matches("W/")
+        .trim_matches('"')
+        .parse()
+        .map(Some)
+        .map_err(|_| malformed())
 }
 
-// ── Librarian: Add Book ────────────────────────────────────────────────
+#[utoipa::path(
+    get, path = "/api/books/{book_id}", tag = "books",
+    params(("book_id" = i32, Path)),
+    responses(
+        (status = 200, description = "The book; its version is also sent as the ETag", body = Book),
+        (status = 404, description = "No such book", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(book_id = *path))]
+pub async fn get_book<R: LibraryRepository>(repo: web::Data<R>, path: web::Path<i32>) -> ApiResult {
+    let book_id = path.into_inner();
+    let book = repo
+        .find_book(book_id)
+        .await?
+        .ok_or(ApiError::BookNotFound(book_id))?;
 
-pub async fn add_book(
-    pool: web::Data<PgPool>,
-    body: web::Json<AddBookRequest>,
-) -> impl Responder {
-    let result = sqlx::query_scalar::<_, i32>(
-        "INSERT INTO books (name, author, number_of_copies, publication_year, edition) VALUES ($1, $2, $3, $4, $5) RETURNING book_id",
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag(book.version)))
+        .json(book))
+}
+
+// ── Librarian: Update Book ─────────────────────────────────────────────
+
+#[utoipa::path(
+    patch, path = "/api/books/{book_id}", tag = "books",
+    params(("book_id" = i32, Path), ("If-Match" = String, Header, description = "ETag from the last read, or `*`")),
+    request_body = UpdateBookRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Updated book with its new ETag", body = Book),
+        (status = 404, description = "No such book", body = Problem),
+        (status = 412, description = "Book changed since it was read", body = Problem),
+        (status = 422, description = "Invalid fields", body = Problem),
+        (status = 428, description = "If-Match header missing", body = Problem),
     )
-    .bind(&body.name)
-    .bind(&body.author)
-    .bind(body.number_of_copies)
-    .bind(body.publication_year)
-    .bind(&body.edition)
-    .fetch_one(pool.get_ref())
-    .await;
-
-    match result {
-        Ok(book_id) => HttpResponse::Created().json(json!({
-            "book_id": book_id,
-            "message": "Book added successfully"
-        })),
-        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+)]
+#[tracing::instrument(skip_all, fields(book_id = *path))]
+pub async fn update_book<R: LibraryRepository>(
+    repo: web::Data<R>,
+    hold_policy: web::Data<HoldPolicy>,
+    req: HttpRequest,
+    path: web::Path<i32>,
+    body: ValidJson<UpdateBookRequest>,
+) -> ApiResult {
+    let book_id = path.into_inner();
+    let expected = if_match_version(&req)?;
+    if body.is_empty() {
+        return Err(ApiError::BadRequest("no fields to update".into()));
     }
+
+    let book = repo
+        .update_book(
+            book_id,
+            expected,
+            &body,
+            Utc::now().naive_utc(),
+            &hold_policy,
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag(book.version)))
+        .json(book))
+}
+
+// ── Librarian: Bulk Stock Update ───────────────────────────────────────
+
+/// Applies every stock change or none of them.
+#[utoipa::path(
+    patch, path = "/api/books", tag = "books",
+    request_body = BulkStockRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Every update applied", body = Object),
+        (status = 404, description = "A book does not exist; nothing was applied", body = Problem),
+        (status = 409, description = "A title would go below zero or above the copy limit; nothing was applied", body = Problem),
+        (status = 412, description = "A version did not match; nothing was applied", body = Problem),
+        (status = 422, description = "Empty list, a book listed twice, or an update without exactly one of number_of_copies and adjust_by", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(updates = body.updates.len()))]
+pub async fn bulk_update_stock<R: LibraryRepository>(
+    repo: web::Data<R>,
+    hold_policy: web::Data<HoldPolicy>,
+    body: ValidJson<BulkStockRequest>,
+) -> ApiResult {
+    let mut updates: Vec<&StockUpdate> = body.updates.iter().collect();
+    // Lock rows in a fixed order so concurrent bulk edits cannot deadlock.
+    updates.sort_by_key(|u| u.book_id);
+
+    let updated = repo
+        .update_stock(&updates, Utc::now().naive_utc(), &hold_policy)
+        .await?;
+    Ok(HttpResponse::Ok().json(json!({"updated": updated})))
+}
+
+// ── Librarian: Add Book ────────────────────────────────────────────────
+
+#[utoipa::path(
+    post, path = "/api/books", tag = "books",
+    request_body = AddBookRequest,
+    security(("bearer" = [])),
+    responses(
+        (status = 201, description = "Book created", body = Object),
+        (status = 422, description = "Invalid fields", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(book_id))]
+pub async fn add_book<R: LibraryRepository>(
+    repo: web::Data<R>,
+    body: ValidJson<AddBookRequest>,
+) -> ApiResult {
+    let book_id = repo.add_book(&body).await?;
+    tracing::Span::current().record("book_id", book_id);
+
+    Ok(HttpResponse::Created().json(json!({
+        "book_id": book_id,
+        "message": "Book added successfully"
+    })))
 }
 
 // ── Librarian: Remove Book ─────────────────────────────────────────────
 
-pub async fn remove_book(
-    pool: web::Data<PgPool>,
+/// Archives the book instead of deleting it, so the borrow ledger keeps its
+/// history. Refused while any copy is still out on loan.
+#[utoipa::path(
+    delete, path = "/api/books/{book_id}", tag = "books",
+    params(("book_id" = i32, Path)),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Book archived and its holds cancelled", body = Object),
+        (status = 404, description = "No such book", body = Problem),
+        (status = 409, description = "Copies still on loan, or already archived", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(book_id = *path))]
+pub async fn remove_book<R: LibraryRepository>(
+    repo: web::Data<R>,
     path: web::Path<i32>,
-) -> impl Responder {
-    let book_id = path.into_inner();
+) -> ApiResult {
+    let cancelled = repo
+        .archive_book(path.into_inner(), Utc::now().naive_utc())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "message": "Book archived successfully",
+        "holds_cancelled": cancelled
+    })))
+}
+
+// ── Librarian: Restore Book ────────────────────────────────────────────
+
+#[utoipa::path(
+    post, path = "/api/books/{book_id}/restore", tag = "books",
+    params(("book_id" = i32, Path)),
+    security(("bearer" = [])),
+    responses(
+        (status = 200, description = "Book is back in the catalogue", body = Book),
+        (status = 404, description = "No such book", body = Problem),
+        (status = 409, description = "Book is not archived", body = Problem),
+    )
+)]
+#[tracing::instrument(skip_all, fields(book_id = *path))]
+pub async fn restore_book<R: LibraryRepository>(
+    repo: web::Data<R>,
+    path: web::Path<i32>,
+) -> ApiResult {
+    let book = repo.restore_book(path.into_inner()).await?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::ETAG, etag(book.version)))
+        .json(book))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Role;
+    use crate::repo::PgRepository;
+    use actix_web::{http::StatusCode, test, App};
+    use sqlx::postgres::PgPoolOptions;
+    use sqlx::PgPool;
+
+    async fn test_pool() -> PgPool {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must point at a test database");
+        let pool = PgPoolOptions::new()
+            .max_connections(20)
+            .connect(&url)
+            .await
+            .expect("Failed to connect to Postgres");
+        crate::db::migrate(&pool).await.expect("Migration failed");
+        pool
+    }
+
+    async fn insert_member(pool: &PgPool, email: &str) -> i32 {
+        sqlx::query_scalar("INSERT INTO members (name, email) VALUES ('Test Member', $1) RETURNING member_id")
+            .bind(email)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn parallel_checkouts_of_last_copy_succeed_exactly_once() {
+        const BORROWERS: usize = 16;
+
+        let pool = test_pool().await;
+        let keys = web::Data::new(AuthKeys::from_secret(b"test-secret"));
+        let run = Utc::now().timestamp_nanos_opt().unwrap();
+
+        let book_id: i32 = sqlx::query_scalar(
+            "INSERT INTO books (name, author, number_of_copies) VALUES ('Race Condition', 'Tester', 1) RETURNING book_id",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+
+        let mut member_ids = Vec::new();
+        for i in 0..BORROWERS {
+            member_ids.push(insert_member(&pool, &format!("race-{}-{}@example.com", run, i)).await);
+        }
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(PgRepository::new(pool.clone())))
+                .app_data(keys.clone())
+                .app_data(web::Data::new(FinePolicy::default()))
+                .app_data(web::Data::new(LoanPolicy::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .route(
+                    "/api/members/{member_id}/checkout",
+                    web::post().to(checkout::<PgRepository>),
+                ),
+        )
+        .await;
 
-    let result = sqlx::query("DELETE FROM books WHERE book_id = $1")
+        let requests = member_ids.iter().map(|&member_id| {
+            let token = keys.issue(member_id, Role::Member).unwrap();
+            let req = test::TestRequest::post()
+                .uri(&format!("/api/members/{}/checkout", member_id))
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .set_json(json!({"book_ids": [book_id]}))
+                .to_request();
+            test::call_service(&app, req)
+        });
+        let statuses: Vec<StatusCode> = futures_util::future::join_all(requests)
+            .await
+            .iter()
+            .map(|res| res.status())
+            .collect();
+
+        assert_eq!(
+            statuses.iter().filter(|s| **s == StatusCode::OK).count(),
+            1,
+            "{:?}",
+            statuses
+        );
+        assert_eq!(
+            statuses
+                .iter()
+                .filter(|s| **s == StatusCode::CONFLICT)
+                .count(),
+            BORROWERS - 1,
+            "{:?}",
+            statuses
+        );
+
+        let copies: i32 = sqlx::query_scalar("SELECT number_of_copies FROM books WHERE book_id = $1")
+            .bind(book_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(copies, 0);
+
+        let loans: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM book_borrow_ledger WHERE book_id = $1")
+            .bind(book_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(loans, 1);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn parallel_checkouts_by_one_member_stay_within_the_loan_limit() {
+        const TITLES: usize = 8;
+
+        let pool = test_pool().await;
+        let repo = PgRepository::new(pool.clone());
+        let run = Utc::now().timestamp_nanos_opt().unwrap();
+        let member_id = insert_member(&pool, &format!("limit-{}@example.com", run)).await;
+        let mut book_ids = Vec::new();
+        for _ in 0..TITLES {
+            let book_id: i32 = sqlx::query_scalar(
+                "INSERT INTO books (name, author, number_of_copies) VALUES ('Limited', 'Tester', 1) RETURNING book_id",
+            )
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            book_ids.push(book_id);
+        }
+        let (fines, loans) = (
+            FinePolicy::default(),
+            LoanPolicy {
+                max_open_loans: 2,
+                ..LoanPolicy::default()
+            },
+        );
+
+        let now = Utc::now().naive_utc();
+        let results = futures_util::future::join_all(
+            book_ids
+                .iter()
+                .map(|book_id| repo.checkout(member_id, std::slice::from_ref(book_id), now, &fines, &loans)),
+        )
+        .await;
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 2, "{:?}", results);
+        assert!(
+            results
+                .iter()
+                .filter_map(|r| r.as_ref().err())
+                .all(|e| matches!(e, ApiError::LoanLimitReached { max_open_loans: 2, open_loans: 2 })),
+            "{:?}",
+            results
+        );
+
+        // A member without a recorded age cannot borrow a restricted title.
+        let restricted: i32 = sqlx::query_scalar(
+            "INSERT INTO books (name, author, number_of_copies, min_age) VALUES ('Restricted', 'Tester', 1, 18) RETURNING book_id",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let ageless = insert_member(&pool, &format!("ageless-{}@example.com", run)).await;
+        let err = repo.checkout(ageless, &[restricted], now, &fines, &loans).await.unwrap_err();
+        assert!(matches!(err, ApiError::AgeRestricted { min_age: 18, .. }), "{:?}", err);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn lost_return_records_incident_without_restocking() {
+        let pool = test_pool().await;
+        let keys = web::Data::new(AuthKeys::from_secret(b"test-secret"));
+        let run = Utc::now().timestamp_nanos_opt().unwrap();
+
+        let book_id: i32 = sqlx::query_scalar(
+            "INSERT INTO books (name, author, number_of_copies) VALUES ('Lost Cause', 'Tester', 0) RETURNING book_id",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let member_id = insert_member(&pool, &format!("lost-{}@example.com", run)).await;
+        sqlx::query("INSERT INTO book_borrow_ledger (book_id, member_id, expected_return) VALUES ($1, $2, NOW())")
+            .bind(book_id)
+            .bind(member_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(PgRepository::new(pool.clone())))
+                .app_data(keys.clone())
+                .app_data(web::Data::new(FinePolicy::default()))
+                .app_data(web::Data::new(HoldPolicy::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .route(
+                    "/api/members/{member_id}/return",
+                    web::post().to(return_book::<PgRepository>),
+                ),
+        )
+        .await;
+
+        let token = keys.issue(member_id, Role::Member).unwrap();
+        let req = test::TestRequest::post()
+            .uri(&format!("/api/members/{}/return", member_id))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(json!({"book_id": book_id, "condition": "lost", "notes": "left on a train"}))
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res["condition"], "lost");
+        assert!(res["incident_id"].is_i64());
+
+        let copies: i32 =
+            sqlx::query_scalar("SELECT number_of_copies FROM books WHERE book_id = $1")
+                .bind(book_id)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(copies, 0);
+
+        let condition: Option<String> = sqlx::query_scalar(
+            "SELECT return_condition FROM book_borrow_ledger WHERE book_id = $1",
+        )
+        .bind(book_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(condition.as_deref(), Some("lost"));
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn loan_history_includes_returns_and_pages_through_equal_borrow_times() {
+        let pool = test_pool().await;
+        let keys = web::Data::new(AuthKeys::from_secret(b"test-secret"));
+        let run = Utc::now().timestamp_nanos_opt().unwrap();
+
+        let book_id: i32 = sqlx::query_scalar(
+            "INSERT INTO books (name, author, number_of_copies) VALUES ('Well Read', 'Tester', 0) RETURNING book_id",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let member_id = insert_member(&pool, &format!("history-{}@example.com", run)).await;
+        // Three loans borrowed in the same instant: one returned damaged, one
+        // overdue and one still due.
+        sqlx::query(
+            "INSERT INTO book_borrow_ledger (book_id, member_id, borrow_date, expected_return, actual_return, return_condition) \
+             VALUES ($1, $2, '2024-03-01 10:00', '2024-03-15 10:00', '2024-03-10 10:00', 'damaged'), \
+                    ($1, $2, '2024-03-01 10:00', '2024-03-15 10:00', NULL, NULL), \
+                    ($1, $2, '2024-03-01 10:00', NOW() + INTERVAL '1 day', NULL, NULL)",
+        )
         .bind(book_id)
-        .execute(pool.get_ref())
+        .bind(member_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(PgRepository::new(pool.clone())))
+                .app_data(keys.clone())
+                .route(
+                    "/api/members/{member_id}/history",
+                    web::get().to(loan_history::<PgRepository>),
+                ),
+        )
         .await;
+        let token = keys.issue(member_id, Role::Member).unwrap();
+        let history = |query: &str| {
+            test::TestRequest::get()
+                .uri(&format!("/api/members/{}/history?{}", member_id, query))
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .to_request()
+        };
 
-    match result {
-        Ok(r) if r.rows_affected() == 0 => {
-            HttpResponse::NotFound().json(json!({"error": "book not found"}))
+        let mut ids = Vec::new();
+        let mut query = "limit=2".to_string();
+        loop {
+            let page: serde_json::Value = test::call_and_read_body_json(&app, history(&query)).await;
+            assert_eq!(page["total"], 3);
+            ids.extend(page["items"].as_array().unwrap().iter().map(|l| l["id"].as_i64().unwrap()));
+            match page["next_cursor"].as_str() {
+                Some(cursor) => query = format!("limit=2&cursor={}", cursor),
+                None => break,
+            }
         }
-        Ok(_) => HttpResponse::Ok().json(json!({"message": "Book removed successfully"})),
-        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+        assert_eq!(ids.len(), 3);
+        assert!(ids.windows(2).all(|w| w[0] > w[1]), "{:?}", ids);
+
+        let page: serde_json::Value =
+            test::call_and_read_body_json(&app, history("condition=damaged&from=2024-03-01&to=2024-03-01")).await;
+        assert_eq!(page["total"], 1);
+        assert_eq!(page["items"][0]["book_name"], "Well Read");
+        let page: serde_json::Value = test::call_and_read_body_json(&app, history("overdue=true")).await;
+        assert_eq!(page["total"], 1);
+        assert_eq!(page["items"][0]["actual_return"], serde_json::Value::Null);
+        let page: serde_json::Value = test::call_and_read_body_json(&app, history("to=2024-02-29")).await;
+        assert_eq!(page["total"], 0);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn erasing_a_member_anonymizes_them_and_keeps_loan_history() {
+        let pool = test_pool().await;
+        let keys = web::Data::new(AuthKeys::from_secret(b"test-secret"));
+        let run = Utc::now().timestamp_nanos_opt().unwrap();
+
+        let book_id: i32 = sqlx::query_scalar(
+            "INSERT INTO books (name, author, number_of_copies) VALUES ('Forgotten', 'Tester', 0) RETURNING book_id",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let member_id = insert_member(&pool, &format!("erase-{}@example.com", run)).await;
+        let ledger_id: i32 = sqlx::query_scalar(
+            r#"INSERT INTO book_borrow_ledger (book_id, member_id, expected_return, actual_return, return_condition)
+               VALUES ($1, $2, NOW(), NOW(), 'damaged') RETURNING id"#,
+        )
+        .bind(book_id)
+        .bind(member_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        sqlx::query("INSERT INTO loan_incidents (ledger_id, member_id, book_id, condition, notes) VALUES ($1, $2, $3, 'damaged', 'coffee stain from flat 4B')")
+            .bind(ledger_id)
+            .bind(member_id)
+            .bind(book_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        // A copy set aside for the member goes back on the shelf.
+        sqlx::query("INSERT INTO holds (book_id, member_id, status, ready_at, expires_at) VALUES ($1, $2, 'ready', NOW(), NOW() + INTERVAL '1 day')")
+            .bind(book_id)
+            .bind(member_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(PgRepository::new(pool.clone())))
+                .app_data(keys.clone())
+                .app_data(web::Data::new(FinePolicy::default()))
+                .app_data(web::Data::new(HoldPolicy::default()))
+                .app_data(web::Data::new(LoanPolicy::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .route("/api/members/{member_id}", web::delete().to(erase_member::<PgRepository>))
+                .route(
+                    "/api/members/{member_id}/checkout",
+                    web::post().to(checkout::<PgRepository>),
+                ),
+        )
+        .await;
+        let token = format!("Bearer {}", keys.issue(member_id, Role::Member).unwrap());
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/api/members/{}", member_id))
+            .insert_header(("Authorization", token.clone()))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let (name, email, address, erased): (String, String, Option<String>, bool) = sqlx::query_as(
+            "SELECT name, email, address, erased_at IS NOT NULL AND deactivated_at IS NOT NULL FROM members WHERE member_id = $1",
+        )
+        .bind(member_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(name, "Erased member");
+        assert_eq!(email, format!("erased-{}@erased.invalid", member_id));
+        assert_eq!(address, None);
+        assert!(erased);
+
+        let (loans, notes): (i64, Option<String>) = sqlx::query_as(
+            r#"SELECT (SELECT COUNT(*) FROM book_borrow_ledger WHERE member_id = $1),
+                      (SELECT notes FROM loan_incidents WHERE member_id = $1)"#,
+        )
+        .bind(member_id)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(loans, 1, "loan history is kept");
+        assert_eq!(notes, None);
+        let copies: i32 = sqlx::query_scalar("SELECT number_of_copies FROM books WHERE book_id = $1")
+            .bind(book_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(copies, 1);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/api/members/{}/checkout", member_id))
+            .insert_header(("Authorization", token))
+            .set_json(json!({"book_ids": [book_id]}))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "account_deactivated");
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn late_return_charges_fine_that_blocks_checkout() {
+        let pool = test_pool().await;
+        let keys = web::Data::new(AuthKeys::from_secret(b"test-secret"));
+        let policy = FinePolicy {
+            daily_rate_cents: 25,
+            block_threshold_cents: 50,
+        };
+        let run = Utc::now().timestamp_nanos_opt().unwrap();
+
+        let book_id: i32 = sqlx::query_scalar(
+            "INSERT INTO books (name, author, number_of_copies) VALUES ('Overdue', 'Tester', 1) RETURNING book_id",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let member_id = insert_member(&pool, &format!("late-{}@example.com", run)).await;
+        sqlx::query(
+            "INSERT INTO book_borrow_ledger (book_id, member_id, borrow_date, expected_return) VALUES ($1, $2, NOW() - INTERVAL '17 days', NOW() - INTERVAL '3 days')",
+        )
+        .bind(book_id)
+        .bind(member_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(PgRepository::new(pool.clone())))
+                .app_data(keys.clone())
+                .app_data(web::Data::new(policy))
+                .app_data(web::Data::new(HoldPolicy::default()))
+                .app_data(web::Data::new(LoanPolicy::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .route(
+                    "/api/members/{member_id}/checkout",
+                    web::post().to(checkout::<PgRepository>),
+                )
+                .route(
+                    "/api/members/{member_id}/return",
+                    web::post().to(return_book::<PgRepository>),
+                )
+                .route(
+                    "/api/members/{member_id}/fines",
+                    web::get().to(member_fines::<PgRepository>),
+                ),
+        )
+        .await;
+        let auth = ("Authorization", format!("Bearer {}", keys.issue(member_id, Role::Member).unwrap()));
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/api/members/{}/return", member_id))
+            .insert_header(auth.clone())
+            .set_json(json!({"book_id": book_id}))
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res["late_fee_cents"], 75);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/api/members/{}/fines", member_id))
+            .insert_header(auth.clone())
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res["balance_cents"], 75);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/api/members/{}/checkout", member_id))
+            .insert_header(auth)
+            .set_json(json!({"book_ids": [book_id]}))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn returned_copy_goes_to_first_hold_and_expires_to_the_next() {
+        let pool = test_pool().await;
+        let keys = web::Data::new(AuthKeys::from_secret(b"test-secret"));
+        let hold_policy = HoldPolicy::default();
+        let run = Utc::now().timestamp_nanos_opt().unwrap();
+
+        let book_id: i32 = sqlx::query_scalar(
+            "INSERT INTO books (name, author, number_of_copies) VALUES ('Popular', 'Tester', 0) RETURNING book_id",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let borrower = insert_member(&pool, &format!("holder-0-{}@example.com", run)).await;
+        let first = insert_member(&pool, &format!("holder-1-{}@example.com", run)).await;
+        let second = insert_member(&pool, &format!("holder-2-{}@example.com", run)).await;
+        sqlx::query("INSERT INTO book_borrow_ledger (book_id, member_id, expected_return) VALUES ($1, $2, NOW() + INTERVAL '1 day')")
+            .bind(book_id)
+            .bind(borrower)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(PgRepository::new(pool.clone())))
+                .app_data(keys.clone())
+                .app_data(web::Data::new(FinePolicy::default()))
+                .app_data(web::Data::new(hold_policy.clone()))
+                .app_data(web::Data::new(Metrics::new()))
+                .route(
+                    "/api/members/{member_id}/return",
+                    web::post().to(return_book::<PgRepository>),
+                )
+                .route(
+                    "/api/members/{member_id}/holds",
+                    web::post().to(place_hold::<PgRepository>),
+                )
+                .route(
+                    "/api/members/{member_id}/holds",
+                    web::get().to(list_holds::<PgRepository>),
+                ),
+        )
+        .await;
+        let auth = |member_id: i32| {
+            (
+                "Authorization",
+                format!("Bearer {}", keys.issue(member_id, Role::Member).unwrap()),
+            )
+        };
+
+        for member_id in [first, second] {
+            let req = test::TestRequest::post()
+                .uri(&format!("/api/members/{}/holds", member_id))
+                .insert_header(auth(member_id))
+                .set_json(json!({"book_id": book_id}))
+                .to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+        }
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/api/members/{}/holds", second))
+            .insert_header(auth(second))
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res[0]["queue_position"], 2);
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/api/members/{}/return", borrower))
+            .insert_header(auth(borrower))
+            .set_json(json!({"book_id": book_id}))
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert!(res["reserved_for_hold"].is_i64());
+
+        let status_of = |member_id: i32| {
+            sqlx::query_scalar::<_, String>(
+                "SELECT status FROM holds WHERE book_id = $1 AND member_id = $2",
+            )
+            .bind(book_id)
+            .bind(member_id)
+            .fetch_one(&pool)
+        };
+        assert_eq!(status_of(first).await.unwrap(), "ready");
+        assert_eq!(status_of(second).await.unwrap(), "waiting");
+
+        sqlx::query("UPDATE holds SET expires_at = NOW() - INTERVAL '1 minute' WHERE book_id = $1 AND member_id = $2")
+            .bind(book_id)
+            .bind(first)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let repo = PgRepository::new(pool.clone());
+        assert!(
+            repo.expire_holds(Utc::now().naive_utc(), &hold_policy)
+                .await
+                .unwrap()
+                >= 1
+        );
+
+        assert_eq!(status_of(first).await.unwrap(), "expired");
+        assert_eq!(status_of(second).await.unwrap(), "ready");
+        let copies: i32 = sqlx::query_scalar("SELECT number_of_copies FROM books WHERE book_id = $1")
+            .bind(book_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(copies, 0);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn renewal_respects_book_limit_and_waiting_holds() {
+        let pool = test_pool().await;
+        let keys = web::Data::new(AuthKeys::from_secret(b"test-secret"));
+        let run = Utc::now().timestamp_nanos_opt().unwrap();
+
+        let book_id: i32 = sqlx::query_scalar(
+            "INSERT INTO books (name, author, number_of_copies, max_renewals) VALUES ('Renewable', 'Tester', 1, 1) RETURNING book_id",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let member_id = insert_member(&pool, &format!("renew-{}@example.com", run)).await;
+        let waiter = insert_member(&pool, &format!("renew-wait-{}@example.com", run)).await;
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(PgRepository::new(pool.clone())))
+                .app_data(keys.clone())
+                .app_data(web::Data::new(FinePolicy::default()))
+                .app_data(web::Data::new(LoanPolicy::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .route(
+                    "/api/members/{member_id}/checkout",
+                    web::post().to(checkout::<PgRepository>),
+                )
+                .route(
+                    "/api/members/{member_id}/loans/{ledger_id}/renew",
+                    web::post().to(renew_loan::<PgRepository>),
+                ),
+        )
+        .await;
+        let auth = ("Authorization", format!("Bearer {}", keys.issue(member_id, Role::Member).unwrap()));
+
+        let req = test::TestRequest::post()
+            .uri(&format!("/api/members/{}/checkout", member_id))
+            .insert_header(auth.clone())
+            .set_json(json!({"book_ids": [book_id]}))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let ledger_id: i32 = sqlx::query_scalar("SELECT id FROM book_borrow_ledger WHERE book_id = $1")
+            .bind(book_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        let renew = || {
+            test::TestRequest::post()
+                .uri(&format!("/api/members/{}/loans/{}/renew", member_id, ledger_id))
+                .insert_header(auth.clone())
+                .to_request()
+        };
+
+        sqlx::query("INSERT INTO holds (book_id, member_id) VALUES ($1, $2)")
+            .bind(book_id)
+            .bind(waiter)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(test::call_service(&app, renew()).await.status(), StatusCode::CONFLICT);
+
+        sqlx::query("UPDATE holds SET status = 'cancelled' WHERE book_id = $1")
+            .bind(book_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, renew()).await;
+        assert_eq!(res["renewal_count"], 1);
+        assert_eq!(res["renewals_left"], 0);
+
+        assert_eq!(test::call_service(&app, renew()).await.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn book_search_pages_through_results_with_cursors() {
+        let pool = test_pool().await;
+        let run = Utc::now().timestamp_nanos_opt().unwrap();
+        let tag = format!("quixote{}", run);
+
+        for year in [1990, 1985, 2001, 1985, 1970] {
+            sqlx::query("INSERT INTO books (name, author, number_of_copies, publication_year) VALUES ($1, 'Cervantes', 1, $2)")
+                .bind(format!("{} {}", tag, year))
+                .bind(year)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(PgRepository::new(pool.clone())))
+                .route("/api/books", web::get().to(list_books::<PgRepository>)),
+        )
+        .await;
+
+        let mut years = Vec::new();
+        let mut uri = format!("/api/books?q={}&sort=publication_year&order=desc&limit=2", tag);
+        loop {
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(res["total"], 5);
+            years.extend(res["items"].as_array().unwrap().iter().map(|b| b["publication_year"].as_i64().unwrap()));
+            match res["next_cursor"].as_str() {
+                Some(cursor) => {
+                    uri = format!("/api/books?q={}&sort=publication_year&order=desc&limit=2&cursor={}", tag, cursor)
+                }
+                None => break,
+            }
+        }
+        assert_eq!(years, vec![2001, 1990, 1985, 1985, 1970]);
+
+        let req = test::TestRequest::get()
+            .uri(&format!("/api/books?q={}&publication_year=1985&page=1", tag))
+            .to_request();
+        let res: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res["total"], 2);
+        assert_eq!(res["page"], 1);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn book_edits_need_current_etag_and_bulk_stock_serves_holds() {
+        let pool = test_pool().await;
+        let run = Utc::now().timestamp_nanos_opt().unwrap();
+
+        let book_id: i32 = sqlx::query_scalar(
+            "INSERT INTO books (name, author, number_of_copies, edition) VALUES ('Teh Hobbit', 'Tolkien', 1, '1st') RETURNING book_id",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let queued_id: i32 = sqlx::query_scalar(
+            "INSERT INTO books (name, author, number_of_copies) VALUES ('Popular', 'Tester', 0) RETURNING book_id",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let member_id = insert_member(&pool, &format!("stock-{}@example.com", run)).await;
+        sqlx::query("INSERT INTO holds (book_id, member_id) VALUES ($1, $2)")
+            .bind(queued_id)
+            .bind(member_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(PgRepository::new(pool.clone())))
+                .app_data(web::Data::new(HoldPolicy::default()))
+                .route(
+                    "/api/books",
+                    web::patch().to(bulk_update_stock::<PgRepository>),
+                )
+                .route(
+                    "/api/books/{book_id}",
+                    web::get().to(get_book::<PgRepository>),
+                )
+                .route(
+                    "/api/books/{book_id}",
+                    web::patch().to(update_book::<PgRepository>),
+                ),
+        )
+        .await;
+        let uri = format!("/api/books/{}", book_id);
+        let patch = |etag: Option<&str>, body: serde_json::Value| {
+            let mut req = test::TestRequest::patch().uri(&uri).set_json(body);
+            if let Some(etag) = etag {
+                req = req.insert_header((header::IF_MATCH, etag.to_string()));
+            }
+            req.to_request()
+        };
+
+        let res = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(res.headers().get(header::ETAG).unwrap(), "\"1\"");
+
+        let res = test::call_service(&app, patch(None, json!({"name": "The Hobbit"}))).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_REQUIRED);
+
+        let res = test::call_service(&app, patch(Some("\"1\""), json!({"name": "The Hobbit", "edition": null}))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::ETAG).unwrap(), "\"2\"");
+        let book: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(book["name"], "The Hobbit");
+        assert_eq!(book["author"], "Tolkien");
+        assert_eq!(book["edition"], serde_json::Value::Null);
+
+        let res = test::call_service(&app, patch(Some("\"1\""), json!({"author": "J. R. R. Tolkien"}))).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+
+        // A change that would take one title negative rolls back the whole batch.
+        let bulk = |body: serde_json::Value| {
+            test::TestRequest::patch()
+                .uri("/api/books")
+                .set_json(body)
+                .to_request()
+        };
+        let res = test::call_service(
+            &app,
+            bulk(json!({"updates": [
+                {"book_id": queued_id, "adjust_by": 2},
+                {"book_id": book_id, "adjust_by": -5}
+            ]})),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        let res = test::call_service(
+            &app,
+            bulk(json!({"updates": [
+                {"book_id": queued_id, "adjust_by": 2},
+                {"book_id": book_id, "number_of_copies": 4, "version": 2}
+            ]})),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let copies = |id: i32| {
+            sqlx::query_scalar::<_, i32>("SELECT number_of_copies FROM books WHERE book_id = $1")
+                .bind(id)
+                .fetch_one(&pool)
+        };
+        assert_eq!(copies(book_id).await.unwrap(), 4);
+        assert_eq!(copies(queued_id).await.unwrap(), 1, "one new copy is reserved for the hold");
+        let status: String = sqlx::query_scalar("SELECT status FROM holds WHERE book_id = $1")
+            .bind(queued_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(status, "ready");
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn removing_a_book_archives_it_once_loans_are_back() {
+        let pool = test_pool().await;
+        let keys = web::Data::new(AuthKeys::from_secret(b"test-secret"));
+        let run = Utc::now().timestamp_nanos_opt().unwrap();
+        let tag = format!("archive{}", run);
+
+        let book_id: i32 = sqlx::query_scalar(
+            "INSERT INTO books (name, author, number_of_copies) VALUES ($1, 'Tester', 2) RETURNING book_id",
+        )
+        .bind(&tag)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        let member_id = insert_member(&pool, &format!("archive-{}@example.com", run)).await;
+        let token = keys.issue(member_id, Role::Member).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(PgRepository::new(pool.clone())))
+                .app_data(keys.clone())
+                .app_data(web::Data::new(FinePolicy::default()))
+                .app_data(web::Data::new(LoanPolicy::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .route(
+                    "/api/members/{member_id}/checkout",
+                    web::post().to(checkout::<PgRepository>),
+                )
+                .route("/api/books", web::get().to(list_books::<PgRepository>))
+                .route(
+                    "/api/books/{book_id}",
+                    web::delete().to(remove_book::<PgRepository>),
+                )
+                .route(
+                    "/api/books/{book_id}/restore",
+                    web::post().to(restore_book::<PgRepository>),
+                ),
+        )
+        .await;
+        let checkout_req = || {
+            test::TestRequest::post()
+                .uri(&format!("/api/members/{}/checkout", member_id))
+                .insert_header(("Authorization", format!("Bearer {}", token)))
+                .set_json(json!({"book_ids": [book_id]}))
+                .to_request()
+        };
+        let remove = || {
+            test::TestRequest::delete()
+                .uri(&format!("/api/books/{}", book_id))
+                .to_request()
+        };
+        let restore = || {
+            test::TestRequest::post()
+                .uri(&format!("/api/books/{}/restore", book_id))
+                .to_request()
+        };
+        let listed = |extra: &str| {
+            test::TestRequest::get()
+                .uri(&format!("/api/books?q={}{}", tag, extra))
+                .to_request()
+        };
+
+        assert_eq!(test::call_service(&app, checkout_req()).await.status(), StatusCode::OK);
+        let res = test::call_service(&app, remove()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "book_on_loan");
+        assert_eq!(body["copies_on_loan"], 1);
+
+        sqlx::query("UPDATE book_borrow_ledger SET actual_return = NOW() WHERE book_id = $1")
+            .bind(book_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(test::call_service(&app, remove()).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&app, remove()).await.status(), StatusCode::CONFLICT);
+
+        let page: serde_json::Value = test::call_and_read_body_json(&app, listed("")).await;
+        assert_eq!(page["total"], 0);
+        let page: serde_json::Value = test::call_and_read_body_json(&app, listed("&include_archived=true")).await;
+        assert_eq!(page["total"], 1);
+        assert!(page["items"][0]["archived_at"].is_string());
+        assert_eq!(test::call_service(&app, checkout_req()).await.status(), StatusCode::NOT_FOUND);
+
+        let history: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM book_borrow_ledger WHERE book_id = $1")
+            .bind(book_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(history, 1, "loan history survives archiving");
+
+        assert_eq!(test::call_service(&app, restore()).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&app, restore()).await.status(), StatusCode::CONFLICT);
+        let page: serde_json::Value = test::call_and_read_body_json(&app, listed("")).await;
+        assert_eq!(page["total"], 1);
+        assert_eq!(test::call_service(&app, checkout_req()).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn invalid_requests_get_field_errors_and_emails_are_case_insensitive() {
+        let pool = test_pool().await;
+        let keys = web::Data::new(AuthKeys::from_secret(b"test-secret"));
+        let run = Utc::now().timestamp_nanos_opt().unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(PgRepository::new(pool.clone())))
+                .app_data(keys.clone())
+                .app_data(web::Data::new(FinePolicy::default()))
+                .app_data(web::Data::new(LoanPolicy::default()))
+                .app_data(web::Data::new(Metrics::new()))
+                .route("/api/register", web::post().to(register::<PgRepository>))
+                .route(
+                    "/api/members/{member_id}/checkout",
+                    web::post().to(checkout::<PgRepository>),
+                ),
+        )
+        .await;
+        let register_req = |body: serde_json::Value| {
+            test::TestRequest::post()
+                .uri("/api/register")
+                .set_json(body)
+                .to_request()
+        };
+
+        let res = test::call_service(
+            &app,
+            register_req(json!({
+                "first_name": " ", "last_name": "Lovelace", "age": -3,
+                "email": "not-an-email", "password": "short"
+            })),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "validation_failed");
+        let fields: Vec<&str> = body["errors"].as_array().unwrap().iter().map(|e| e["field"].as_str().unwrap()).collect();
+        assert_eq!(fields, vec!["first_name", "age", "email", "password"]);
+
+        let email = format!("Ada-{}@Example.com", run);
+        let valid = |email: &str| {
+            json!({
+                "first_name": "Ada", "last_name": "Lovelace", "age": 36,
+                "email": email, "password": "analytical"
+            })
+        };
+        let res = test::call_service(&app, register_req(valid(&email))).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let member_id = test::read_body_json::<serde_json::Value, _>(res).await["member_id"].as_i64().unwrap() as i32;
+
+        let res = test::call_service(&app, register_req(valid(&email.to_lowercase()))).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "email_taken");
+
+        let token = keys.issue(member_id, Role::Member).unwrap();
+        let req = test::TestRequest::post()
+            .uri(&format!("/api/members/{}/checkout", member_id))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(json!({"book_ids": [4, 4]}))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["errors"][0]["code"], "duplicate");
     }
 }
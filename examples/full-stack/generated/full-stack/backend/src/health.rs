@@ -0,0 +1,91 @@
+use actix_web::{web, HttpResponse};
+use serde_json::json;
+use sqlx::Pool;
+use std::time::Duration;
+
+use crate::db::{self, Backend};
+
+/// Readiness checks must answer well inside a probe's own timeout, so a
+/// saturated pool reports "not ready" instead of hanging.
+const READY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Liveness: the process is up and serving. Deliberately touches nothing
+/// else, so a database outage does not get the container restarted.
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// Readiness: the pool can run a query and every migration this binary
+/// knows about has been applied. 503 lists which check failed.
+pub async fn readyz<DB: Backend>(pool: web::Data<Pool<DB>>) -> HttpResponse {
+    let database = match tokio::time::timeout(READY_TIMEOUT, DB::ping(&pool)).await {
+        Ok(Ok(())) => "ok".to_string(),
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "readiness: database query failed");
+            "unreachable".to_string()
+        }
+        Err(_) => "timed out".to_string(),
+    };
+
+    let migrations = if database != "ok" {
+        "unknown".to_string()
+    } else {
+        match tokio::time::timeout(READY_TIMEOUT, db::pending_versions(&pool)).await {
+            Ok(Ok(pending)) if pending.is_empty() => "ok".to_string(),
+            Ok(Ok(pending)) => format!("{} pending", pending.len()),
+            Ok(Err(e)) => {
+                tracing::warn!(error = %e, "readiness: cannot read schema_migrations");
+                "unknown".to_string()
+            }
+            Err(_) => "timed out".to_string(),
+        }
+    };
+
+    let ready = database == "ok" && migrations == "ok";
+    let body = json!({
+        "status": if ready { "ready" } else { "not_ready" },
+        "checks": { "database": database, "migrations": migrations },
+    });
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
+
+/// Registers the probes; readiness checks the database the pool talks to.
+pub fn configure<DB: Backend>(cfg: &mut web::ServiceConfig) {
+    cfg.route("/healthz", web::get().to(healthz))
+        .route("/readyz", web::get().to(readyz::<DB>));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use sqlx::postgres::PgPoolOptions;
+    use sqlx::Postgres;
+
+    #[actix_web::test]
+    async fn liveness_needs_no_database() {
+        let app = test::init_service(App::new().configure(configure::<Postgres>)).await;
+        let res = test::call_service(&app, test::TestRequest::get().uri("/healthz").to_request()).await;
+        assert!(res.status().is_success());
+    }
+
+    #[actix_web::test]
+    async fn unreachable_database_is_not_ready() {
+        // Nothing listens on port 1; connections fail immediately.
+        let pool = PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(500))
+            .connect_lazy("postgres://nobody@127.0.0.1:1/none")
+            .unwrap();
+        let app = test::init_service(App::new().app_data(web::Data::new(pool)).configure(configure::<Postgres>)).await;
+
+        let res = test::call_service(&app, test::TestRequest::get().uri("/readyz").to_request()).await;
+        assert_eq!(res.status(), 503);
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["checks"]["database"], "unreachable");
+        assert_eq!(body["checks"]["migrations"], "unknown");
+    }
+}
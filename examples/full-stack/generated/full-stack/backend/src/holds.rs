@@ -0,0 +1,44 @@
+use actix_web::web;
+use chrono::Utc;
+use std::time::Duration;
+use tracing::{error, info};
+
+use crate::repo::LibraryRepository;
+
+/// How long a returned copy stays reserved for the next member in line, and
+/// how often the server looks for reservations that were never picked up.
+#[derive(Debug, Clone)]
+pub struct HoldPolicy {
+    pub pickup_window: chrono::Duration,
+    pub sweep_interval: Duration,
+}
+
+impl Default for HoldPolicy {
+    fn default() -> Self {
+        Self {
+            pickup_window: chrono::Duration::hours(72),
+            sweep_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Expires uncollected reservations on the policy's interval for the life
+/// of the server.
+pub fn spawn_expiry_task<R: LibraryRepository>(repo: web::Data<R>, policy: HoldPolicy) {
+    actix_web::rt::spawn(async move {
+        let mut ticker = actix_web::rt::time::interval(policy.sweep_interval);
+        loop {
+            ticker.tick().await;
+            // Closed during shutdown; stop instead of logging a failure per tick.
+            if repo.is_closed() {
+                break;
+            }
+            match repo.expire_holds(Utc::now().naive_utc(), &policy).await {
+                Ok(0) => {}
+                Ok(n) => info!(expired = n, "expired uncollected holds"),
+                // Debug, since Display hides database details meant for logs only.
+                Err(e) => error!(error = ?e, "hold expiry sweep failed"),
+            }
+        }
+    });
+}
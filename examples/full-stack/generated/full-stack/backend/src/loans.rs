@@ -0,0 +1,141 @@
+use crate::error::ApiError;
+
+/// Loan length, how many times a loan may be extended and how much a member
+/// may have out at once. Individual books can lower or raise the renewal
+/// limit through `books.max_renewals`.
+#[derive(Debug, Clone)]
+pub struct LoanPolicy {
+    pub loan_period: chrono::Duration,
+    pub max_renewals: i32,
+    /// Open loans a member may hold, counting the ones being checked out.
+    pub max_open_loans: i64,
+    /// Copies of one title a member may hold at the same time.
+    pub max_copies_per_title: i64,
+}
+
+impl Default for LoanPolicy {
+    fn default() -> Self {
+        Self {
+            loan_period: chrono::Duration::days(14),
+            max_renewals: 2,
+            max_open_loans: 10,
+            max_copies_per_title: 1,
+        }
+    }
+}
+
+/// What the checkout rules look at, read before any copy is taken.
+#[derive(Debug, Clone, Default)]
+pub struct Borrower {
+    pub age: Option<i32>,
+    pub blocked: bool,
+    pub blocked_reason: Option<String>,
+    /// Book ids of the member's open loans, once per copy.
+    pub open_loans: Vec<i32>,
+}
+
+impl LoanPolicy {
+    /// Decides whether `borrower` may take one copy of each requested
+    /// `(book_id, min_age)`. Titles that do not exist are left for the
+    /// checkout itself to report. Rules are checked in a fixed order, so the
+    /// same request always fails with the same code.
+    pub fn check_checkout(&self, borrower: &Borrower, requested: &[(i32, Option<i32>)]) -> Result<(), ApiError> {
+        if borrower.blocked {
+            return Err(ApiError::MemberBlocked {
+                reason: borrower.blocked_reason.clone(),
+            });
+        }
+
+        let open_loans = borrower.open_loans.len() as i64;
+        if open_loans + requested.len() as i64 > self.max_open_loans {
+            return Err(ApiError::LoanLimitReached {
+                max_open_loans: self.max_open_loans,
+                open_loans,
+            });
+        }
+
+        for &(book_id, min_age) in requested {
+            let copies = borrower.open_loans.iter().filter(|&&id| id == book_id).count() as i64;
+            if copies + 1 > self.max_copies_per_title {
+                return Err(ApiError::TitleLimitReached {
+                    book_id,
+                    max_copies_per_title: self.max_copies_per_title,
+                });
+            }
+            // A member who never gave an age cannot borrow restricted titles.
+            if let Some(min_age) = min_age {
+                if borrower.age.is_none_or(|age| age < min_age) {
+                    return Err(ApiError::AgeRestricted { book_id, min_age });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reader(age: i32, open_loans: &[i32]) -> Borrower {
+        Borrower {
+            age: Some(age),
+            open_loans: open_loans.to_vec(),
+            ..Borrower::default()
+        }
+    }
+
+    #[test]
+    fn open_loans_count_towards_the_limit() {
+        let policy = LoanPolicy {
+            max_open_loans: 3,
+            ..LoanPolicy::default()
+        };
+        assert!(policy.check_checkout(&reader(30, &[1]), &[(2, None), (3, None)]).is_ok());
+        let err = policy
+            .check_checkout(&reader(30, &[1, 2]), &[(3, None), (4, None)])
+            .unwrap_err();
+        assert!(matches!(err, ApiError::LoanLimitReached { max_open_loans: 3, open_loans: 2 }), "{err:?}");
+    }
+
+    #[test]
+    fn copies_of_one_title_are_capped() {
+        let policy = LoanPolicy::default();
+        let err = policy.check_checkout(&reader(30, &[7]), &[(7, None)]).unwrap_err();
+        assert!(matches!(err, ApiError::TitleLimitReached { book_id: 7, max_copies_per_title: 1 }), "{err:?}");
+
+        let two = LoanPolicy {
+            max_copies_per_title: 2,
+            ..LoanPolicy::default()
+        };
+        assert!(two.check_checkout(&reader(30, &[7]), &[(7, None)]).is_ok());
+    }
+
+    #[test]
+    fn restricted_titles_need_a_known_old_enough_age() {
+        let policy = LoanPolicy::default();
+        assert!(policy.check_checkout(&reader(18, &[]), &[(5, Some(18))]).is_ok());
+        let err = policy.check_checkout(&reader(17, &[]), &[(5, Some(18))]).unwrap_err();
+        assert!(matches!(err, ApiError::AgeRestricted { book_id: 5, min_age: 18 }), "{err:?}");
+
+        let unknown = Borrower::default();
+        assert!(policy.check_checkout(&unknown, &[(6, None)]).is_ok());
+        assert!(policy.check_checkout(&unknown, &[(5, Some(18))]).is_err());
+    }
+
+    #[test]
+    fn a_block_wins_over_every_other_rule() {
+        let borrower = Borrower {
+            blocked: true,
+            blocked_reason: Some("lost three books".into()),
+            ..reader(12, &[1; 20])
+        };
+        let err = LoanPolicy::default()
+            .check_checkout(&borrower, &[(1, Some(18))])
+            .unwrap_err();
+        match err {
+            ApiError::MemberBlocked { reason } => assert_eq!(reason.as_deref(), Some("lost three books")),
+            other => panic!("expected a block, got {other:?}"),
+        }
+    }
+}
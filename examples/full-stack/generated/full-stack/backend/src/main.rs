@@ -1,62 +1,280 @@
+mod auth;
+mod config;
 mod db;
+mod error;
+mod fines;
 mod handlers;
+mod health;
+mod holds;
+mod loans;
+mod metrics;
 mod models;
+mod notify;
+mod openapi;
+mod pagination;
+mod repo;
+mod routes;
+mod telemetry;
+mod validation;
 
 use actix_cors::Cors;
-use actix_web::{web, App, HttpServer, middleware};
+use actix_web::middleware::from_fn;
+use actix_web::{web, App, HttpServer};
 use sqlx::postgres::PgPoolOptions;
+use sqlx::Pool;
 use std::env;
+use std::path::PathBuf;
+use std::process;
+
+use config::{Config, NotificationConfig, NotifyChannel};
+use db::Backend;
+use notify::{FileNotifier, LogNotifier, SmtpNotifier};
+use repo::{LibraryRepository, PgRepository};
+#[cfg(feature = "sqlite")]
+use repo::SqliteRepository;
+
+const USAGE: &str = "usage: book-library [--config <file>] \
+                     [--print-config | --migrate-only | --migrate-status | --migrate-down <version>]";
+
+/// What the binary was asked to do; anything other than `Serve` exits
+/// once the migration work is finished.
+enum Mode {
+    Serve,
+    PrintConfig,
+    MigrateOnly,
+    MigrateStatus,
+    MigrateDown(i64),
+}
+
+struct Args {
+    mode: Mode,
+    config: Option<PathBuf>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut config = env::var_os(config::CONFIG_ENV).map(PathBuf::from);
+    let mut rest = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            config = Some(args.next().ok_or(USAGE)?.into());
+        } else {
+            rest.push(arg);
+        }
+    }
+    let rest: Vec<&str> = rest.iter().map(String::as_str).collect();
+    let mode = match rest.as_slice() {
+        [] => Mode::Serve,
+        ["--print-config"] => Mode::PrintConfig,
+        ["--migrate-only"] => Mode::MigrateOnly,
+        ["--migrate-status"] => Mode::MigrateStatus,
+        ["--migrate-down", version] => version
+            .parse()
+            .map(Mode::MigrateDown)
+            .map_err(|_| format!("invalid migration version: {}", version))?,
+        _ => return Err(USAGE.to_string()),
+    };
+    Ok(Args { mode, config })
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
-
-    let db_url = env::var("DATABASE_URL").unwrap_or_else(|_| {
-        let host = env::var("DB_HOST").unwrap_or_else(|_| "localhost".into());
-        let port = env::var("DB_PORT").unwrap_or_else(|_| "5432".into());
-        let user = env::var("DB_USER").unwrap_or_else(|_| "library".into());
-        let pass = env::var("DB_PASSWORD").unwrap_or_else(|_| "library".into());
-        let name = env::var("DB_NAME").unwrap_or_else(|_| "library".into());
-        format!("postgres://{}:{}@{}:{}/{}", user, pass, host, port, name)
+    let args = parse_args().unwrap_or_else(|msg| {
+        eprintln!("{}", msg);
+        process::exit(2);
+    });
+
+    let config = Config::load(args.config.as_deref()).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        process::exit(2);
     });
 
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(&db_url)
+    if let Mode::PrintConfig = args.mode {
+        print!("{}", config.to_redacted_toml());
+        return Ok(());
+    }
+
+    telemetry::init(&config.logging);
+
+    #[cfg(feature = "sqlite")]
+    if config.database.is_sqlite() {
+        let url = config.database.url();
+        let pool = db::connect_sqlite(&url).await.unwrap_or_else(|e| {
+            tracing::error!(%url, error = %e, "cannot open SQLite database");
+            process::exit(1);
+        });
+        tracing::info!(%url, "opened SQLite database");
+        let repo = SqliteRepository::new(pool.clone());
+        return run(args.mode, config, pool, repo).await;
+    }
+
+    let db = &config.database;
+    let options = PgPoolOptions::new()
+        .max_connections(db.max_connections)
+        .min_connections(db.min_connections)
+        .acquire_timeout(db.acquire_timeout())
+        .idle_timeout(db.idle_timeout())
+        .max_lifetime(db.max_lifetime());
+    let pool = db::connect(options, &db.url(), &db.connect_retry())
         .await
-        .expect("Failed to connect to Postgres");
+        .unwrap_or_else(|e| {
+            tracing::error!(attempts = db.connect_attempts, error = %e, "giving up on Postgres");
+            process::exit(1);
+        });
+
+    tracing::info!("connected to PostgreSQL");
+    let repo = PgRepository::new(pool.clone());
+    run(args.mode, config, pool, repo).await
+}
 
-    log::info!("✔ Connected to PostgreSQL");
+/// Everything after the connection is made, the same for every backend.
+async fn run<DB: Backend, R: LibraryRepository>(
+    mode: Mode,
+    config: Config,
+    pool: Pool<DB>,
+    repo: R,
+) -> std::io::Result<()> {
+    match mode {
+        Mode::Serve | Mode::PrintConfig => {}
+        Mode::MigrateOnly => {
+            db::migrate(&pool).await.expect("Migration failed");
+            return Ok(());
+        }
+        Mode::MigrateStatus => {
+            let statuses = db::status(&pool).await.expect("Failed to read migration status");
+            for m in statuses {
+                let state = match (m.applied_at, m.modified) {
+                    (Some(at), false) => format!("applied {}", at),
+                    (Some(at), true) => format!("applied {} (MODIFIED since)", at),
+                    (None, _) => "pending".to_string(),
+                };
+                println!("{:>4}  {:<32} {}", m.version, m.name, state);
+            }
+            return Ok(());
+        }
+        Mode::MigrateDown(version) => {
+            db::rollback(&pool, version).await.expect("Rollback failed");
+            return Ok(());
+        }
+    }
 
     db::migrate(&pool).await.expect("Migration failed");
+    let repo = web::Data::new(repo);
+
+    let auth = &config.auth;
+    if let Some(password) = &auth.librarian_password {
+        let hash = auth::hash_password(password).expect("Failed to hash librarian password");
+        repo.ensure_librarian(&auth.librarian_username, &hash)
+            .await
+            .expect("Failed to create librarian account");
+    }
+
+    let auth_keys = web::Data::new(match &auth.secret {
+        Some(secret) => auth::AuthKeys::from_secret(secret.as_bytes()),
+        None => {
+            tracing::warn!("auth.secret not set; using a random key, sessions will not survive a restart");
+            auth::AuthKeys::ephemeral()
+        }
+    });
 
-    let listen_addr = env::var("LISTEN_ADDR").unwrap_or_else(|_| "0.0.0.0:8080".into());
-    log::info!("🚀 Book Library API listening on {}", listen_addr);
+    let fine_policy = web::Data::new(config.fines.policy());
+    let hold_policy = config.holds.policy();
+    holds::spawn_expiry_task(repo.clone(), hold_policy.clone());
+    let hold_policy = web::Data::new(hold_policy);
+    let loan_policy = web::Data::new(config.loans.policy());
+    spawn_reminders(repo.clone(), &config.notifications);
 
-    HttpServer::new(move || {
-        let cors = Cors::default()
-            .allowed_origin("http://localhost:3000")
-            .allowed_origin("http://localhost:5173")
-            .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
+    let metrics = web::Data::new(metrics::Metrics::new().with_overdue_refresh(config.metrics.overdue_refresh()));
+    // Health and metrics look at the pool itself rather than the repository.
+    let pool_data = web::Data::new(pool.clone());
+
+    let listen_addr = config.server.listen_addr.clone();
+    let metrics_addr = config.metrics.listen_addr.clone();
+    let metrics_on_api = metrics_addr.is_none();
+    let (scrape_pool, scrape_metrics) = (pool_data.clone(), metrics.clone());
+    let allowed_origins = config.cors.allowed_origins.clone();
+    tracing::info!(%listen_addr, "Book Library API listening");
+
+    let api = HttpServer::new(move || {
+        let cors = allowed_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+            .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
             .allowed_header(actix_web::http::header::CONTENT_TYPE)
+            .allowed_header(actix_web::http::header::AUTHORIZATION)
+            .allowed_header(actix_web::http::header::IF_MATCH)
+            .allowed_header(telemetry::REQUEST_ID)
+            .expose_headers(vec![actix_web::http::header::ETAG, telemetry::REQUEST_ID])
             .supports_credentials();
 
         App::new()
-            .app_data(web::Data::new(pool.clone()))
+            .app_data(repo.clone())
+            .app_data(pool_data.clone())
+            .app_data(auth_keys.clone())
+            .app_data(fine_policy.clone())
+            .app_data(hold_policy.clone())
+            .app_data(loan_policy.clone())
+            .app_data(metrics.clone())
+            .app_data(web::JsonConfig::default().error_handler(|e, _| error::bad_request(e)))
+            .app_data(web::QueryConfig::default().error_handler(|e, _| error::bad_request(e)))
+            .app_data(web::PathConfig::default().error_handler(|e, _| error::bad_request(e)))
+            .wrap(from_fn(metrics::track))
             .wrap(cors)
-            .wrap(middleware::Logger::default())
-            // Member routes
-            .route("/api/register", web::post().to(handlers::register))
-            .route("/api/login", web::post().to(handlers::login))
-            .route("/api/members/{member_id}/checkout", web::post().to(handlers::checkout))
-            .route("/api/members/{member_id}/borrowed", web::get().to(handlers::borrowed_books))
-            .route("/api/members/{member_id}/return", web::post().to(handlers::return_book))
-            // Book / Librarian routes
-            .route("/api/books", web::get().to(handlers::list_books))
-            .route("/api/books", web::post().to(handlers::add_book))
-            .route("/api/books/{book_id}", web::delete().to(handlers::remove_book))
+            .wrap(from_fn(telemetry::trace_request))
+            .configure(routes::configure::<R>)
+            .configure(openapi::configure)
+            .configure(health::configure::<DB>)
+            .configure(|cfg| {
+                if metrics_on_api {
+                    metrics::configure::<DB>(cfg);
+                }
+            })
     })
     .bind(&listen_addr)?
-    .run()
-    .await
+    // On SIGTERM/SIGINT actix stops accepting, lets in-flight requests (and
+    // their transactions) finish for up to this long, then stops workers.
+    .shutdown_timeout(config.server.shutdown_timeout_secs)
+    .run();
+
+    match metrics_addr {
+        None => api.await?,
+        Some(metrics_addr) => {
+            tracing::info!(%metrics_addr, "metrics listening");
+            let scrape = HttpServer::new(move || {
+                App::new()
+                    .app_data(scrape_pool.clone())
+                    .app_data(scrape_metrics.clone())
+                    .configure(metrics::configure::<DB>)
+            })
+            .workers(1)
+            .bind(&metrics_addr)?
+            .run();
+            // Both servers stop on the same signal.
+            tokio::try_join!(api, scrape)?;
+        }
+    }
+
+    tracing::info!("HTTP server stopped; closing database pool");
+    pool.close().await;
+    tracing::info!("shutdown complete");
+    Ok(())
+}
+
+/// Starts the reminder job on the configured channel, unless it is off.
+fn spawn_reminders<R: LibraryRepository>(repo: web::Data<R>, config: &NotificationConfig) {
+    let policy = config.policy();
+    match config.channel {
+        NotifyChannel::Off => {
+            tracing::info!("loan reminders are off; set notifications.channel to send them")
+        }
+        NotifyChannel::Log => notify::spawn_reminder_task(repo, LogNotifier, policy),
+        NotifyChannel::File => notify::spawn_reminder_task(repo, FileNotifier::new(&config.file), policy),
+        NotifyChannel::Smtp => {
+            let notifier = SmtpNotifier::new(&config.smtp.settings()).unwrap_or_else(|e| {
+                tracing::error!(error = %e, "cannot set up SMTP delivery");
+                process::exit(1);
+            });
+            notify::spawn_reminder_task(repo, notifier, policy);
+        }
+    }
 }
@@ -0,0 +1,285 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use sqlx::Pool;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::db::Backend;
+use crate::models::ReturnCondition;
+
+/// Label for requests no route matched, so scanners probing random paths
+/// cannot blow up the number of series.
+const UNMATCHED: &str = "unmatched";
+
+/// How long a counted `library_overdue_loans` is reused before the ledger is
+/// counted again.
+pub const OVERDUE_REFRESH: Duration = Duration::from_secs(60);
+
+/// Every series the server exports. Each instance owns its registry, so
+/// tests can build one without clashing with another test's counters.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+    pool_connections: IntGaugeVec,
+    pool_max_connections: IntGauge,
+    overdue_loans: IntGauge,
+    overdue_refresh: Duration,
+    /// When the ledger was last counted, whether or not the count succeeded.
+    overdue_counted: Mutex<Option<Instant>>,
+    pub checkouts: IntCounter,
+    pub returns: IntCounterVec,
+    pub renewals: IntCounter,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_duration = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency by route"),
+            &["method", "route"],
+        )
+        .unwrap();
+        let pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database pool connections by state"),
+            &["state"],
+        )
+        .unwrap();
+        let pool_max_connections =
+            IntGauge::new("db_pool_max_connections", "Configured database pool size").unwrap();
+        let overdue_loans =
+            IntGauge::new("library_overdue_loans", "Open loans past their expected return").unwrap();
+        let checkouts = IntCounter::new("library_checkouts_total", "Books checked out").unwrap();
+        let returns = IntCounterVec::new(
+            Opts::new("library_returns_total", "Books returned, by condition"),
+            &["condition"],
+        )
+        .unwrap();
+        let renewals = IntCounter::new("library_renewals_total", "Loans renewed").unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry.register(Box::new(http_duration.clone())).unwrap();
+        registry.register(Box::new(pool_connections.clone())).unwrap();
+        registry.register(Box::new(pool_max_connections.clone())).unwrap();
+        registry.register(Box::new(overdue_loans.clone())).unwrap();
+        registry.register(Box::new(checkouts.clone())).unwrap();
+        registry.register(Box::new(returns.clone())).unwrap();
+        registry.register(Box::new(renewals.clone())).unwrap();
+
+        Self {
+            registry,
+            http_requests,
+            http_duration,
+            pool_connections,
+            pool_max_connections,
+            overdue_loans,
+            overdue_refresh: OVERDUE_REFRESH,
+            overdue_counted: Mutex::new(None),
+            checkouts,
+            returns,
+            renewals,
+        }
+    }
+
+    /// Counts overdue loans at most once per `refresh` instead of on every
+    /// scrape.
+    pub fn with_overdue_refresh(mut self, refresh: Duration) -> Self {
+        self.overdue_refresh = refresh;
+        self
+    }
+
+    pub fn record_return(&self, condition: ReturnCondition) {
+        self.returns.with_label_values(&[condition.as_str()]).inc();
+    }
+
+    fn observe_pool<DB: Backend>(&self, pool: &Pool<DB>) {
+        let size = i64::from(pool.size());
+        let idle = pool.num_idle() as i64;
+        self.pool_connections.with_label_values(&["idle"]).set(idle);
+        self.pool_connections.with_label_values(&["in_use"]).set(size - idle);
+        self.pool_max_connections
+            .set(i64::from(pool.options().get_max_connections()));
+    }
+
+    /// True when the overdue count is stale. The caller that gets `true`
+    /// does the counting; scrapes arriving meanwhile keep the old value
+    /// rather than queue up behind the same query.
+    fn claim_overdue_count(&self) -> bool {
+        let mut counted = self.overdue_counted.lock().unwrap();
+        if counted.is_some_and(|at| at.elapsed() < self.overdue_refresh) {
+            return false;
+        }
+        *counted = Some(Instant::now());
+        true
+    }
+
+    fn render(&self) -> String {
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .expect("text encoding cannot fail");
+        String::from_utf8(buf).expect("prometheus text is UTF-8")
+    }
+}
+
+/// Middleware counting and timing every request under its route pattern
+/// (`/api/books/{book_id}`), not the concrete path.
+pub async fn track(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let method = req.method().to_string();
+    let route = req.match_pattern().unwrap_or_else(|| UNMATCHED.to_string());
+    let started = Instant::now();
+
+    let res = next.call(req).await;
+
+    if let Some(metrics) = metrics {
+        let status = match &res {
+            Ok(res) => res.status(),
+            Err(e) => e.as_response_error().status_code(),
+        };
+        metrics
+            .http_requests
+            .with_label_values(&[&method, &route, status.as_str()])
+            .inc();
+        metrics
+            .http_duration
+            .with_label_values(&[&method, &route])
+            .observe(started.elapsed().as_secs_f64());
+    }
+    res
+}
+
+/// Prometheus scrape endpoint. Pool gauges are read at scrape time; the
+/// overdue count is refreshed at most once per `overdue_refresh`, and if the
+/// query fails the last value is kept until the next refresh.
+pub async fn metrics<DB: Backend>(pool: web::Data<Pool<DB>>, metrics: web::Data<Metrics>) -> HttpResponse {
+    metrics.observe_pool(&pool);
+    if metrics.claim_overdue_count() {
+        match DB::overdue_loans(&pool, Utc::now().naive_utc()).await {
+            Ok(overdue) => metrics.overdue_loans.set(overdue),
+            Err(e) => tracing::warn!(error = %e, "metrics: cannot count overdue loans"),
+        }
+    }
+
+    HttpResponse::Ok()
+        .content_type(TextEncoder::new().format_type())
+        .body(metrics.render())
+}
+
+/// Registers `/metrics` for the database the pool talks to.
+pub fn configure<DB: Backend>(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(metrics::<DB>));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn requests_are_counted_by_route_pattern() {
+        let metrics = web::Data::new(Metrics::new());
+        let app = test::init_service(
+            App::new()
+                .app_data(metrics.clone())
+                .wrap(from_fn(track))
+                .route("/api/books/{book_id}", web::get().to(ok)),
+        )
+        .await;
+
+        for uri in ["/api/books/1", "/api/books/2", "/nowhere"] {
+            test::call_service(&app, test::TestRequest::get().uri(uri).to_request()).await;
+        }
+
+        let text = metrics.render();
+        assert!(
+            text.contains(r#"http_requests_total{method="GET",route="/api/books/{book_id}",status="200"} 2"#),
+            "{text}"
+        );
+        assert!(
+            text.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#),
+            "{text}"
+        );
+        assert!(text.contains("http_request_duration_seconds_bucket"), "{text}");
+    }
+
+    #[actix_web::test]
+    async fn domain_counters_are_exported() {
+        let metrics = Metrics::new();
+        metrics.checkouts.inc_by(2);
+        metrics.record_return(ReturnCondition::Damaged);
+        let text = metrics.render();
+        assert!(text.contains("library_checkouts_total 2"), "{text}");
+        assert!(text.contains(r#"library_returns_total{condition="damaged"} 1"#), "{text}");
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[actix_web::test]
+    async fn overdue_loans_are_counted_once_per_refresh() {
+        let pool = crate::db::connect_sqlite("sqlite::memory:").await.unwrap();
+        crate::db::migrate(&pool).await.unwrap();
+        let scrape = |metrics: web::Data<Metrics>| {
+            let pool = web::Data::new(pool.clone());
+            async move {
+                let app = test::init_service(
+                    App::new()
+                        .app_data(pool)
+                        .app_data(metrics)
+                        .configure(configure::<sqlx::Sqlite>),
+                )
+                .await;
+                let res = test::call_service(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+                String::from_utf8(test::read_body(res).await.to_vec()).unwrap()
+            }
+        };
+
+        let cached = web::Data::new(Metrics::new());
+        let fresh = web::Data::new(Metrics::new().with_overdue_refresh(Duration::ZERO));
+        assert!(scrape(cached.clone()).await.contains("library_overdue_loans 0"));
+
+        sqlx::query("INSERT INTO members (name, email) VALUES ('Ada', 'ada@example.com')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO books (name, author, number_of_copies) VALUES ('Sketch', 'Menabrea', 0)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO book_borrow_ledger (book_id, member_id, expected_return) VALUES (1, 1, $1)")
+            .bind(Utc::now().naive_utc() - chrono::Duration::days(3))
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let text = scrape(cached).await;
+        assert!(text.contains("library_overdue_loans 0"), "still the cached count: {text}");
+        let text = scrape(fresh).await;
+        assert!(text.contains("library_overdue_loans 1"), "{text}");
+    }
+}
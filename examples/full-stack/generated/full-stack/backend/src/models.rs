@@ -1,9 +1,19 @@
-use serde::{Deserialize, Serialize};
-use chrono::NaiveDateTime;
+use serde::{Deserialize, Deserializer, Serialize};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Utc};
+
+use utoipa::{IntoParams, ToSchema};
+
+use crate::pagination::{SortKey, SortOrder};
+use crate::validation::{FieldError, Validate, Validator};
+
+pub const MIN_PASSWORD_LEN: usize = 8;
+/// Most copies of one title the catalogue will hold.
+pub const MAX_COPIES: i32 = 10_000;
+const MAX_NAME_LEN: usize = 200;
 
 // ── Book ────────────────────────────────────────────────────────────────
 
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
 pub struct Book {
     pub book_id: i32,
     pub name: String,
@@ -11,22 +21,88 @@ pub struct Book {
     pub number_of_copies: i32,
     pub publication_year: Option<i32>,
     pub edition: Option<String>,
+    pub max_renewals: Option<i32>,
+    /// Members younger than this, or without a recorded age, cannot borrow it.
+    pub min_age: Option<i32>,
+    pub version: i32,
+    pub archived_at: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BookSort {
+    #[default]
+    Name,
+    Author,
+    PublicationYear,
+    BookId,
+}
+
+impl BookSort {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BookSort::Name => "name",
+            BookSort::Author => "author",
+            BookSort::PublicationYear => "publication_year",
+            BookSort::BookId => "book_id",
+        }
+    }
+
+    /// SQL expression to order by. Unknown years sort as year 0 so keyset
+    /// comparisons never meet a NULL.
+    pub fn expr(self) -> &'static str {
+        match self {
+            BookSort::Name => "name",
+            BookSort::Author => "author",
+            BookSort::PublicationYear => "COALESCE(publication_year, 0)",
+            BookSort::BookId => "book_id",
+        }
+    }
+
+    pub fn key(self, book: &Book) -> SortKey {
+        match self {
+            BookSort::Name => SortKey::Text(book.name.clone()),
+            BookSort::Author => SortKey::Text(book.author.clone()),
+            BookSort::PublicationYear => SortKey::Int(book.publication_year.unwrap_or(0).into()),
+            BookSort::BookId => SortKey::Int(book.book_id.into()),
+        }
+    }
 }
 
 // ── Member ──────────────────────────────────────────────────────────────
 
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+/// A deactivated member cannot log in or borrow; an erased one has also had
+/// its personal fields overwritten. A blocked member can still log in but
+/// not check books out.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
 pub struct Member {
     pub member_id: i32,
     pub name: String,
     pub address: Option<String>,
     pub age: Option<i32>,
     pub email: String,
+    #[serde(skip)]
+    pub password_hash: Option<String>,
+    pub deactivated_at: Option<NaiveDateTime>,
+    pub erased_at: Option<NaiveDateTime>,
+    pub blocked_at: Option<NaiveDateTime>,
+    pub blocked_reason: Option<String>,
+}
+
+/// Everything stored about one member, as the data export hands it out.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct MemberExport {
+    pub member: Member,
+    pub loans: Vec<BorrowLedger>,
+    pub fines: Vec<MemberFine>,
+    pub holds: Vec<Hold>,
+    pub incidents: Vec<LoanIncident>,
+    pub notifications: Vec<LoanNotification>,
 }
 
 // ── Borrow Ledger ───────────────────────────────────────────────────────
 
-#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
 pub struct BorrowLedger {
     pub id: i32,
     pub book_id: i32,
@@ -35,42 +111,457 @@ pub struct BorrowLedger {
     pub expected_return: NaiveDateTime,
     pub actual_return: Option<NaiveDateTime>,
     pub return_condition: Option<String>,
-    #[sqlx(default)]
+    pub renewal_count: i32,
+    pub max_renewals: i32,
     pub book_name: Option<String>,
 }
 
+/// A damaged or lost return.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct LoanIncident {
+    pub incident_id: i32,
+    pub ledger_id: i32,
+    pub member_id: i32,
+    pub book_id: i32,
+    pub condition: String,
+    pub notes: Option<String>,
+    pub reported_at: NaiveDateTime,
+}
+
+// ── Member Fines ────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct MemberFine {
+    pub fine_id: i32,
+    pub member_id: i32,
+    pub ledger_id: Option<i32>,
+    pub kind: String,
+    pub amount_cents: i32,
+    pub description: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+// ── Holds ───────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct Hold {
+    pub hold_id: i32,
+    pub book_id: i32,
+    pub member_id: i32,
+    pub status: String,
+    pub placed_at: NaiveDateTime,
+    pub ready_at: Option<NaiveDateTime>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub book_name: Option<String>,
+    pub queue_position: Option<i64>,
+}
+
+// ── Notifications ───────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReminderKind {
+    /// The due date is near.
+    DueSoon,
+    /// The due date has passed and the book is still out.
+    Overdue,
+}
+
+impl ReminderKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReminderKind::DueSoon => "due_soon",
+            ReminderKind::Overdue => "overdue",
+        }
+    }
+}
+
+/// A reminder that was delivered about a loan.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, ToSchema)]
+pub struct LoanNotification {
+    pub notification_id: i32,
+    pub ledger_id: i32,
+    pub member_id: i32,
+    pub kind: String,
+    pub channel: String,
+    pub due_at: NaiveDateTime,
+    pub sent_at: NaiveDateTime,
+}
+
+/// An open loan whose member is due a reminder, with what the message needs.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct LoanReminder {
+    pub ledger_id: i32,
+    pub member_id: i32,
+    pub member_name: String,
+    pub email: String,
+    pub book_id: i32,
+    pub book_name: String,
+    pub expected_return: NaiveDateTime,
+}
+
+impl LoanReminder {
+    pub fn kind(&self, now: NaiveDateTime) -> ReminderKind {
+        if self.expected_return < now {
+            ReminderKind::Overdue
+        } else {
+            ReminderKind::DueSoon
+        }
+    }
+}
+
+// ── Librarian ───────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Librarian {
+    pub librarian_id: i32,
+    pub username: String,
+    #[serde(skip)]
+    pub password_hash: String,
+}
+
 // ── Request DTOs ────────────────────────────────────────────────────────
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct RegisterRequest {
     pub first_name: String,
     pub last_name: String,
     pub age: i32,
     pub email: String,
     pub address: Option<String>,
+    pub password: String,
 }
 
-#[derive(Debug, Deserialize)]
+/// A member's changes to their own profile. `address: null` clears it.
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct UpdateMemberRequest {
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub address: Option<Option<String>>,
+    pub age: Option<i32>,
+    pub email: Option<String>,
+}
+
+impl UpdateMemberRequest {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.address.is_none() && self.age.is_none() && self.email.is_none()
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct LoginRequest {
-    pub username: String,
     pub member_id: i32,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct LibrarianLoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+/// A librarian suspending a member's borrowing. The reason is shown to the
+/// member whenever a checkout is refused.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BlockMemberRequest {
+    pub reason: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct CheckoutRequest {
     pub book_ids: Vec<i32>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ReturnCondition {
+    #[default]
+    Good,
+    Damaged,
+    Lost,
+}
+
+impl ReturnCondition {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ReturnCondition::Good => "good",
+            ReturnCondition::Damaged => "damaged",
+            ReturnCondition::Lost => "lost",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct ReturnRequest {
     pub book_id: i32,
+    #[serde(default)]
+    pub condition: ReturnCondition,
+    pub notes: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct HoldRequest {
+    pub book_id: i32,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct FinePaymentRequest {
+    pub amount_cents: i32,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListBooksQuery {
+    /// Full-text search over title and author.
+    pub q: Option<String>,
+    pub author: Option<String>,
+    pub publication_year: Option<i32>,
+    /// Librarian view: list archived books alongside active ones.
+    #[serde(default)]
+    pub include_archived: bool,
+    #[serde(default)]
+    pub sort: BookSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LoanFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+/// Filters for a loan listing, which is always newest first. `from` and
+/// `to` bound the borrow date and are both inclusive.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LoanQuery {
+    pub book_id: Option<i32>,
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+    /// `true` keeps loans still out past their due date, `false` the rest.
+    pub overdue: Option<bool>,
+    /// Only loans returned in this condition.
+    pub condition: Option<ReturnCondition>,
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    /// `csv` returns every matching loan as a CSV download instead of a page.
+    #[serde(default)]
+    pub format: LoanFormat,
+}
+
+impl LoanQuery {
+    /// The borrow dates asked for as a half-open `[start, end)` range.
+    pub fn borrowed_between(&self) -> (Option<NaiveDateTime>, Option<NaiveDateTime>) {
+        let start = self.from.map(|day| day.and_time(chrono::NaiveTime::MIN));
+        let end = self
+            .to
+            .and_then(|day| day.succ_opt())
+            .map(|day| day.and_time(chrono::NaiveTime::MIN));
+        (start, end)
+    }
+}
+
+/// The librarian's ledger can also be narrowed to one member.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LedgerQuery {
+    pub member_id: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
 pub struct AddBookRequest {
     pub name: String,
     pub author: String,
     pub number_of_copies: i32,
     pub publication_year: Option<i32>,
     pub edition: Option<String>,
+    pub max_renewals: Option<i32>,
+    pub min_age: Option<i32>,
+}
+
+/// Lets a PATCH body tell "leave unchanged" (field absent) apart from
+/// "clear it" (field set to `null`) on nullable columns.
+fn nullable<'de, T, D>(de: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(de).map(Some)
+}
+
+#[derive(Debug, Default, Deserialize, ToSchema)]
+pub struct UpdateBookRequest {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub number_of_copies: Option<i32>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i32>)]
+    pub publication_year: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<String>)]
+    pub edition: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i32>)]
+    pub max_renewals: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[schema(value_type = Option<i32>)]
+    pub min_age: Option<Option<i32>>,
+}
+
+impl UpdateBookRequest {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.author.is_none()
+            && self.number_of_copies.is_none()
+            && self.publication_year.is_none()
+            && self.edition.is_none()
+            && self.max_renewals.is_none()
+            && self.min_age.is_none()
+    }
+}
+
+/// One title in a bulk stock edit: either an absolute `number_of_copies` or a
+/// relative `adjust_by`. `version`, when given, must match the stored one.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct StockUpdate {
+    pub book_id: i32,
+    pub number_of_copies: Option<i32>,
+    pub adjust_by: Option<i32>,
+    pub version: Option<i32>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BulkStockRequest {
+    pub updates: Vec<StockUpdate>,
+}
+
+// ── Validation ──────────────────────────────────────────────────────────
+
+/// Latest publication year accepted; publishers announce a year ahead.
+fn max_publication_year() -> i32 {
+    Utc::now().year() + 1
+}
+
+impl Validate for RegisterRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        Validator::new()
+            .not_blank("first_name", &self.first_name)
+            .max_chars("first_name", self.first_name.as_str(), MAX_NAME_LEN)
+            .not_blank("last_name", &self.last_name)
+            .max_chars("last_name", self.last_name.as_str(), MAX_NAME_LEN)
+            .range("age", self.age, 0, 150)
+            .email("email", self.email.trim())
+            .max_chars("address", self.address.as_deref(), 500)
+            .min_chars("password", &self.password, MIN_PASSWORD_LEN)
+            .max_chars("password", self.password.as_str(), 1024)
+            .finish()
+    }
+}
+
+impl Validate for UpdateMemberRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut v = Validator::new();
+        if let Some(name) = &self.name {
+            // The full name, which registration joins from a first and a last name.
+            v.not_blank("name", name).max_chars("name", name.as_str(), 2 * MAX_NAME_LEN + 1);
+        }
+        if let Some(email) = &self.email {
+            v.email("email", email.trim());
+        }
+        v.range("age", self.age, 0, 150)
+            .max_chars("address", self.address.as_ref().and_then(|a| a.as_deref()), 500)
+            .finish()
+    }
+}
+
+impl Validate for CheckoutRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        Validator::new()
+            .not_empty("book_ids", &self.book_ids)
+            .unique("book_ids", &self.book_ids)
+            .each_in_range("book_ids", &self.book_ids, 1, i32::MAX)
+            .finish()
+    }
+}
+
+impl Validate for ReturnRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut v = Validator::new();
+        if let Some(notes) = &self.notes {
+            v.not_blank("notes", notes).max_chars("notes", notes.as_str(), 1000);
+        }
+        v.finish()
+    }
+}
+
+impl Validate for BlockMemberRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        Validator::new()
+            .not_blank("reason", &self.reason)
+            .max_chars("reason", self.reason.as_str(), 500)
+            .finish()
+    }
+}
+
+impl Validate for AddBookRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        Validator::new()
+            .not_blank("name", &self.name)
+            .max_chars("name", self.name.as_str(), MAX_NAME_LEN)
+            .not_blank("author", &self.author)
+            .max_chars("author", self.author.as_str(), MAX_NAME_LEN)
+            .range("number_of_copies", self.number_of_copies, 0, MAX_COPIES)
+            .range("publication_year", self.publication_year, 0, max_publication_year())
+            .max_chars("edition", self.edition.as_deref(), 100)
+            .range("max_renewals", self.max_renewals, 0, 100)
+            .range("min_age", self.min_age, 0, 150)
+            .finish()
+    }
+}
+
+/// Where the update leaves a title once `adjust_by` is applied depends on
+/// the stored count, so that bound is checked by the repository.
+impl Validate for BulkStockRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut v = Validator::new();
+        let book_ids: Vec<i32> = self.updates.iter().map(|u| u.book_id).collect();
+        v.not_empty("updates", &self.updates).unique("updates", &book_ids);
+        for (i, u) in self.updates.iter().enumerate() {
+            let field = |name: &str| format!("updates[{}].{}", i, name);
+            v.range(&field("book_id"), u.book_id, 1, i32::MAX)
+                .exactly_one(
+                    &format!("updates[{}]", i),
+                    &[("number_of_copies", u.number_of_copies.is_some()), ("adjust_by", u.adjust_by.is_some())],
+                )
+                .range(&field("number_of_copies"), u.number_of_copies, 0, MAX_COPIES)
+                .range(&field("adjust_by"), u.adjust_by, -MAX_COPIES, MAX_COPIES);
+        }
+        v.finish()
+    }
+}
+
+impl Validate for UpdateBookRequest {
+    fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut v = Validator::new();
+        if let Some(name) = &self.name {
+            v.not_blank("name", name).max_chars("name", name.as_str(), MAX_NAME_LEN);
+        }
+        if let Some(author) = &self.author {
+            v.not_blank("author", author).max_chars("author", author.as_str(), MAX_NAME_LEN);
+        }
+        v.range("number_of_copies", self.number_of_copies, 0, MAX_COPIES)
+            .range("publication_year", self.publication_year.flatten(), 0, max_publication_year())
+            .max_chars("edition", self.edition.as_ref().and_then(|e| e.as_deref()), 100)
+            .range("max_renewals", self.max_renewals.flatten(), 0, 100)
+            .range("min_age", self.min_age.flatten(), 0, 150)
+            .finish()
+    }
 }
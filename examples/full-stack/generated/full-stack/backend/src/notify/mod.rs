@@ -0,0 +1,540 @@
+//! Reminders about loans that are nearly due or overdue. A background job
+//! asks the repository which loans need one, renders each from `Templates`
+//! and hands it to a `Notifier`. Each reminder is recorded before it is sent,
+//! so neither the next sweep nor another server sends it again; a reminder
+//! that cannot be delivered is given up and retried on the next sweep.
+
+mod sink;
+mod smtp;
+
+pub use sink::{FileNotifier, LogNotifier};
+pub use smtp::{SmtpNotifier, SmtpSecurity, SmtpSettings};
+
+use actix_web::web;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+use crate::error::ApiError;
+use crate::models::{LoanReminder, ReminderKind};
+use crate::repo::LibraryRepository;
+
+/// When members are reminded: once `remind_before` ahead of the due date,
+/// then every `overdue_repeat` while the book is late. The server looks for
+/// reminders to send every `sweep_interval`.
+#[derive(Debug, Clone)]
+pub struct NotificationPolicy {
+    pub remind_before: chrono::Duration,
+    pub overdue_repeat: chrono::Duration,
+    pub sweep_interval: Duration,
+    pub templates: Templates,
+}
+
+impl Default for NotificationPolicy {
+    fn default() -> Self {
+        Self {
+            remind_before: chrono::Duration::hours(48),
+            overdue_repeat: chrono::Duration::days(7),
+            sweep_interval: Duration::from_secs(3600),
+            templates: Templates::default(),
+        }
+    }
+}
+
+// ── Messages ───────────────────────────────────────────────────────────
+
+/// A rendered reminder, ready for any channel. The ids and `kind` say what
+/// it is about without repeating the address or text, e.g. in logs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Message {
+    pub ledger_id: i32,
+    pub member_id: i32,
+    pub kind: ReminderKind,
+    pub to_name: String,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Names a template may use, each written `{name}`.
+pub const PLACEHOLDERS: [&str; 4] = ["name", "book", "due", "days_overdue"];
+
+/// Subject and body of each kind of reminder. `{name}` is the member's name,
+/// `{book}` the title, `{due}` the due date and `{days_overdue}` the number
+/// of started days since it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Templates {
+    pub due_soon_subject: String,
+    pub due_soon_body: String,
+    pub overdue_subject: String,
+    pub overdue_body: String,
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self {
+            due_soon_subject: "\"{book}\" is due back on {due}".into(),
+            due_soon_body: "Hello {name},\n\n\"{book}\" is due back on {due}. If nobody is \
+                            waiting for it you can renew it from your account.\n\nBook Library\n"
+                .into(),
+            overdue_subject: "\"{book}\" is overdue".into(),
+            overdue_body: "Hello {name},\n\n\"{book}\" was due back on {due} and is now \
+                           {days_overdue} day(s) late. Late returns are fined for every \
+                           started day, so please bring it back soon.\n\nBook Library\n"
+                .into(),
+        }
+    }
+}
+
+impl Templates {
+    pub fn render(&self, reminder: &LoanReminder, kind: ReminderKind, now: NaiveDateTime) -> Message {
+        let (subject, body) = match kind {
+            ReminderKind::DueSoon => (&self.due_soon_subject, &self.due_soon_body),
+            ReminderKind::Overdue => (&self.overdue_subject, &self.overdue_body),
+        };
+        let late = (now - reminder.expected_return).num_seconds();
+        let days_overdue = ((late + 86_399) / 86_400).max(0).to_string();
+        let due = reminder.expected_return.format("%Y-%m-%d").to_string();
+        let values = [
+            ("name", reminder.member_name.as_str()),
+            ("book", reminder.book_name.as_str()),
+            ("due", due.as_str()),
+            ("days_overdue", days_overdue.as_str()),
+        ];
+        Message {
+            ledger_id: reminder.ledger_id,
+            member_id: reminder.member_id,
+            kind,
+            to_name: reminder.member_name.clone(),
+            to: reminder.email.clone(),
+            subject: fill(subject, &values),
+            body: fill(body, &values),
+        }
+    }
+
+    /// One problem per placeholder that is not in `PLACEHOLDERS`, named by
+    /// the template's key.
+    pub fn problems(&self) -> Vec<String> {
+        [
+            ("due_soon_subject", &self.due_soon_subject),
+            ("due_soon_body", &self.due_soon_body),
+            ("overdue_subject", &self.overdue_subject),
+            ("overdue_body", &self.overdue_body),
+        ]
+        .into_iter()
+        .flat_map(|(key, template)| {
+            placeholders(template)
+                .filter(|name| !PLACEHOLDERS.contains(name))
+                .map(move |name| {
+                    format!(
+                        "{} uses unknown placeholder {{{}}}, expected one of {}",
+                        key,
+                        name,
+                        PLACEHOLDERS.join(", ")
+                    )
+                })
+        })
+        .collect()
+    }
+}
+
+/// Names written `{like_this}` in a template. Braces around anything else
+/// are left alone.
+fn placeholders(template: &str) -> impl Iterator<Item = &str> {
+    template.split('{').skip(1).filter_map(|part| {
+        let (name, _) = part.split_once('}')?;
+        let is_name = !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_');
+        is_name.then_some(name)
+    })
+}
+
+/// Replaces each known `{name}` in one pass, so values that happen to
+/// contain braces are inserted as they are.
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let value = after.split_once('}').and_then(|(name, tail)| {
+            values.iter().find(|(key, _)| *key == name).map(|(_, value)| (*value, tail))
+        });
+        match value {
+            Some((value, tail)) => {
+                out.push_str(value);
+                rest = tail;
+            }
+            None => {
+                out.push('{');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// ── Delivery ───────────────────────────────────────────────────────────
+
+/// Why a reminder was not delivered. The job logs it and tries again on the
+/// next sweep.
+#[derive(Debug)]
+pub struct NotifyError(pub String);
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for NotifyError {}
+
+impl From<std::io::Error> for NotifyError {
+    fn from(e: std::io::Error) -> Self {
+        NotifyError(e.to_string())
+    }
+}
+
+/// A way of getting a reminder to a member.
+pub trait Notifier: Send + Sync + 'static {
+    /// Recorded with each reminder sent, e.g. `smtp`.
+    fn channel(&self) -> &'static str;
+    async fn send(&self, message: &Message) -> Result<(), NotifyError>;
+}
+
+/// Delivers every reminder that is due at `now`, claiming each one first.
+/// Returns how many were delivered.
+pub async fn send_reminders<R: LibraryRepository, N: Notifier>(
+    repo: &R,
+    notifier: &N,
+    policy: &NotificationPolicy,
+    now: NaiveDateTime,
+) -> Result<usize, ApiError> {
+    let mut sent = 0;
+    for reminder in repo.due_reminders(now, policy).await? {
+        let kind = reminder.kind(now);
+        // Someone else got there first, or the loan changed since it was listed.
+        let Some(claim) = repo
+            .claim_notification(&reminder, kind, notifier.channel(), now, policy)
+            .await?
+        else {
+            continue;
+        };
+        let message = policy.templates.render(&reminder, kind, now);
+        if let Err(e) = notifier.send(&message).await {
+            warn!(
+                ledger_id = reminder.ledger_id,
+                member_id = reminder.member_id,
+                book_id = reminder.book_id,
+                error = %e,
+                "reminder not delivered"
+            );
+            repo.release_notification(claim).await?;
+            continue;
+        }
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+/// Sends reminders on the policy's interval for the life of the server.
+pub fn spawn_reminder_task<R: LibraryRepository, N: Notifier>(
+    repo: web::Data<R>,
+    notifier: N,
+    policy: NotificationPolicy,
+) {
+    info!(channel = notifier.channel(), "sending loan reminders");
+    actix_web::rt::spawn(async move {
+        let mut ticker = actix_web::rt::time::interval(policy.sweep_interval);
+        loop {
+            ticker.tick().await;
+            // Closed during shutdown; stop instead of logging a failure per tick.
+            if repo.is_closed() {
+                break;
+            }
+            match send_reminders(repo.get_ref(), &notifier, &policy, Utc::now().naive_utc()).await {
+                Ok(0) => {}
+                Ok(n) => info!(sent = n, "sent loan reminders"),
+                // Debug, since Display hides database details meant for logs only.
+                Err(e) => error!(error = ?e, "loan reminder sweep failed"),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fines::FinePolicy;
+    use crate::loans::LoanPolicy;
+    use crate::models::AddBookRequest;
+    use crate::repo::{MemoryRepository, NewMember};
+    use chrono::{Duration, NaiveDate};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    /// Keeps what it is given, or refuses everything while `down` is set.
+    #[derive(Default)]
+    struct Outbox {
+        sent: Mutex<Vec<Message>>,
+        down: AtomicBool,
+    }
+
+    impl Notifier for Outbox {
+        fn channel(&self) -> &'static str {
+            "test"
+        }
+
+        async fn send(&self, message: &Message) -> Result<(), NotifyError> {
+            // Lets another sweep run while this one is "on the wire".
+            tokio::task::yield_now().await;
+            if self.down.load(Ordering::SeqCst) {
+                return Err(NotifyError("mail server unreachable".into()));
+            }
+            self.sent.lock().unwrap().push(message.clone());
+            Ok(())
+        }
+    }
+
+    /// Lends one copy of a new book to a new member at `t0`. Returns the
+    /// member's email, their id, the loan's ledger id and `t0`.
+    async fn lend_one<R: LibraryRepository>(repo: &R) -> (String, i32, i32, NaiveDateTime) {
+        let run = Utc::now().timestamp_nanos_opt().unwrap();
+        let email = format!("reminded-{}@example.com", run);
+        let member_id = repo
+            .create_member(NewMember {
+                name: "Ada Lovelace".into(),
+                address: None,
+                age: 36,
+                email: email.clone(),
+                password_hash: "not used".into(),
+            })
+            .await
+            .unwrap();
+        let book_id = repo
+            .add_book(&AddBookRequest {
+                name: "Sketch of the Analytical Engine".into(),
+                author: "Menabrea".into(),
+                number_of_copies: 1,
+                publication_year: None,
+                edition: None,
+                max_renewals: None,
+                min_age: None,
+            })
+            .await
+            .unwrap();
+        let t0 = Utc::now().naive_utc();
+        repo.checkout(member_id, &[book_id], t0, &FinePolicy::default(), &LoanPolicy::default())
+            .await
+            .unwrap();
+        let ledger_id = repo.open_loans(member_id).await.unwrap()[0].id;
+        (email, member_id, ledger_id, t0)
+    }
+
+    fn sent_to(outbox: &Outbox, email: &str) -> usize {
+        outbox.sent.lock().unwrap().iter().filter(|m| m.to == email).count()
+    }
+
+    /// Walks one loan through its reminders. Other loans in the database
+    /// may be reminded too, so only messages to this member are counted.
+    async fn reminders_follow_the_due_date<R: LibraryRepository>(repo: R) {
+        let (email, member_id, ledger_id, t0) = lend_one(&repo).await;
+        let loans = LoanPolicy::default();
+
+        let outbox = Outbox::default();
+        let policy = NotificationPolicy::default();
+        let sweep = |days: i64, hours: i64| {
+            send_reminders(&repo, &outbox, &policy, t0 + Duration::days(days) + Duration::hours(hours))
+        };
+        let subjects = || -> Vec<String> {
+            outbox
+                .sent
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|m| m.to == email)
+                .map(|m| m.subject.clone())
+                .collect()
+        };
+
+        sweep(0, 1).await.unwrap();
+        assert!(subjects().is_empty(), "not due for two weeks");
+        sweep(13, 0).await.unwrap();
+        sweep(13, 1).await.unwrap();
+        assert_eq!(subjects().len(), 1, "one reminder per due date");
+        let due = (t0 + Duration::days(14)).format("%Y-%m-%d").to_string();
+        assert!(subjects()[0].ends_with(&format!("is due back on {}", due)), "{:?}", subjects());
+
+        repo.renew_loan(member_id, ledger_id, t0 + Duration::days(13), &loans)
+            .await
+            .unwrap();
+        sweep(27, 0).await.unwrap();
+        assert_eq!(subjects().len(), 2, "the renewed due date gets its own reminder");
+
+        outbox.down.store(true, Ordering::SeqCst);
+        sweep(29, 0).await.unwrap();
+        assert_eq!(subjects().len(), 2);
+        outbox.down.store(false, Ordering::SeqCst);
+        sweep(29, 1).await.unwrap();
+        sweep(30, 0).await.unwrap();
+        assert_eq!(subjects().len(), 3, "a failed delivery is retried, then not repeated for a week");
+        assert!(subjects()[2].ends_with("is overdue"));
+        sweep(36, 2).await.unwrap();
+        assert_eq!(subjects().len(), 4);
+
+        let export = repo.export_member(member_id).await.unwrap();
+        let kinds: Vec<&str> = export.notifications.iter().map(|n| n.kind.as_str()).collect();
+        assert_eq!(kinds, ["overdue", "overdue", "due_soon", "due_soon"]);
+        assert!(export.notifications.iter().all(|n| n.channel == "test"));
+    }
+
+    /// Two sweeps at once, as when two servers share a database, deliver
+    /// the reminder once between them.
+    async fn overlapping_sweeps_share_the_work<R: LibraryRepository>(repo: R) {
+        let (email, _, _, t0) = lend_one(&repo).await;
+        let outbox = Outbox::default();
+        let policy = NotificationPolicy::default();
+        let now = t0 + Duration::days(13);
+
+        let (a, b) = futures_util::future::join(
+            send_reminders(&repo, &outbox, &policy, now),
+            send_reminders(&repo, &outbox, &policy, now),
+        )
+        .await;
+        a.unwrap();
+        b.unwrap();
+        assert_eq!(sent_to(&outbox, &email), 1);
+        send_reminders(&repo, &outbox, &policy, now).await.unwrap();
+        assert_eq!(sent_to(&outbox, &email), 1);
+    }
+
+    #[actix_web::test]
+    async fn memory_repository_sends_each_reminder_once() {
+        reminders_follow_the_due_date(MemoryRepository::new()).await;
+        overlapping_sweeps_share_the_work(MemoryRepository::new()).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    async fn sqlite_repo() -> crate::repo::SqliteRepository {
+        let pool = crate::db::connect_sqlite("sqlite::memory:").await.unwrap();
+        crate::db::migrate(&pool).await.unwrap();
+        crate::repo::SqliteRepository::new(pool)
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[actix_web::test]
+    async fn sqlite_repository_sends_each_reminder_once() {
+        reminders_follow_the_due_date(sqlite_repo().await).await;
+        overlapping_sweeps_share_the_work(sqlite_repo().await).await;
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a Postgres database in DATABASE_URL"]
+    async fn postgres_repository_sends_each_reminder_once() {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must point at a test database");
+        let pool = sqlx::PgPool::connect(&url).await.expect("Failed to connect to Postgres");
+        crate::db::migrate(&pool).await.expect("Migration failed");
+        reminders_follow_the_due_date(crate::repo::PgRepository::new(pool.clone())).await;
+        overlapping_sweeps_share_the_work(crate::repo::PgRepository::new(pool)).await;
+    }
+
+    /// Delivers, then leaves the database refusing to write reminders.
+    #[cfg(feature = "sqlite")]
+    struct BreaksAfterSending {
+        outbox: Outbox,
+        pool: sqlx::SqlitePool,
+    }
+
+    #[cfg(feature = "sqlite")]
+    impl Notifier for BreaksAfterSending {
+        fn channel(&self) -> &'static str {
+            "test"
+        }
+
+        async fn send(&self, message: &Message) -> Result<(), NotifyError> {
+            self.outbox.send(message).await?;
+            sqlx::query(
+                "CREATE TRIGGER notifications_read_only BEFORE INSERT ON loan_notifications \
+                 BEGIN SELECT RAISE(ABORT, 'disk I/O error'); END",
+            )
+            .execute(&self.pool)
+            .await
+            .unwrap();
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[actix_web::test]
+    async fn a_database_failure_after_delivery_does_not_resend() {
+        let pool = crate::db::connect_sqlite("sqlite::memory:").await.unwrap();
+        crate::db::migrate(&pool).await.unwrap();
+        let repo = crate::repo::SqliteRepository::new(pool.clone());
+        let (email, _, _, t0) = lend_one(&repo).await;
+        let notifier = BreaksAfterSending {
+            outbox: Outbox::default(),
+            pool: pool.clone(),
+        };
+        let policy = NotificationPolicy::default();
+        let now = t0 + Duration::days(13);
+
+        assert_eq!(send_reminders(&repo, &notifier, &policy, now).await.unwrap(), 1);
+        assert_eq!(sent_to(&notifier.outbox, &email), 1);
+
+        sqlx::query("DROP TRIGGER notifications_read_only")
+            .execute(&pool)
+            .await
+            .unwrap();
+        send_reminders(&repo, &notifier, &policy, now + Duration::hours(1))
+            .await
+            .unwrap();
+        assert_eq!(sent_to(&notifier.outbox, &email), 1, "the reminder was recorded before it went out");
+    }
+
+    fn reminder(book_name: &str) -> LoanReminder {
+        LoanReminder {
+            ledger_id: 1,
+            member_id: 1,
+            member_name: "Ada Lovelace".into(),
+            email: "ada@example.com".into(),
+            book_id: 1,
+            book_name: book_name.into(),
+            expected_return: NaiveDate::from_ymd_opt(2024, 3, 15)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap(),
+        }
+    }
+
+    #[test]
+    fn templates_fill_in_the_loan() {
+        let templates = Templates::default();
+        let loan = reminder("Notes on {due}");
+        let now = loan.expected_return + chrono::Duration::hours(30);
+
+        let message = templates.render(&loan, loan.kind(now), now);
+        assert_eq!(message.to, "ada@example.com");
+        assert_eq!(message.subject, "\"Notes on {due}\" is overdue");
+        assert!(message.body.starts_with("Hello Ada Lovelace,"), "{}", message.body);
+        assert!(message.body.contains("due back on 2024-03-15 and is now 2 day(s) late"), "{}", message.body);
+
+        let message = templates.render(&loan, ReminderKind::DueSoon, now);
+        assert_eq!(message.subject, "\"Notes on {due}\" is due back on 2024-03-15");
+    }
+
+    #[test]
+    fn unknown_placeholders_are_reported() {
+        let templates = Templates {
+            overdue_body: "Dear {member}, {book} {is} late {}".into(),
+            ..Templates::default()
+        };
+        assert!(Templates::default().problems().is_empty());
+        let problems = templates.problems();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with("overdue_body uses unknown placeholder {member}"));
+        assert!(problems[1].starts_with("overdue_body uses unknown placeholder {is}"));
+    }
+}
@@ -0,0 +1,119 @@
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use super::{Message, Notifier, NotifyError};
+
+/// Logs each reminder instead of delivering it, for local runs. A logged
+/// reminder counts as sent and is not repeated.
+#[derive(Debug, Default)]
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn channel(&self) -> &'static str {
+        "log"
+    }
+
+    /// Only says which loan was reminded of what: addresses and message text
+    /// stay out of the logs.
+    async fn send(&self, message: &Message) -> Result<(), NotifyError> {
+        info!(
+            ledger_id = message.ledger_id,
+            member_id = message.member_id,
+            kind = message.kind.as_str(),
+            "reminder"
+        );
+        Ok(())
+    }
+}
+
+/// Appends each reminder to a file as one JSON object per line, for tests
+/// and for handing to another mail system.
+#[derive(Debug)]
+pub struct FileNotifier {
+    path: PathBuf,
+}
+
+impl FileNotifier {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl Notifier for FileNotifier {
+    fn channel(&self) -> &'static str {
+        "file"
+    }
+
+    async fn send(&self, message: &Message) -> Result<(), NotifyError> {
+        let mut line = serde_json::to_vec(message).expect("message serializes");
+        line.push(b'\n');
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&line).await?;
+        // tokio hands the write to a blocking thread; without this the
+        // file can be closed before the line reaches it.
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ReminderKind;
+    use crate::telemetry::Captured;
+
+    fn message() -> Message {
+        Message {
+            ledger_id: 12,
+            member_id: 3,
+            kind: ReminderKind::DueSoon,
+            to_name: "Ada".into(),
+            to: "ada@example.com".into(),
+            subject: "Due soon".into(),
+            body: "Two lines\nof text".into(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn log_sink_keeps_addresses_and_text_out_of_the_log() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        LogNotifier.send(&message()).await.unwrap();
+
+        let output = captured.text();
+        assert!(output.contains("ledger_id=12 member_id=3 kind=\"due_soon\""), "{output}");
+        assert!(!output.contains("ada@example.com") && !output.contains("Two lines"), "{output}");
+    }
+
+    #[actix_web::test]
+    async fn file_sink_appends_json_lines() {
+        let path = std::env::temp_dir().join(format!("reminders-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let notifier = FileNotifier::new(&path);
+        let message = message();
+
+        notifier.send(&message).await.unwrap();
+        notifier.send(&message).await.unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<serde_json::Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["body"], "Two lines\nof text");
+        assert_eq!(lines[1]["kind"], "due_soon");
+    }
+}
@@ -0,0 +1,197 @@
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+use super::{Message, Notifier, NotifyError};
+
+/// How the connection to the mail server is protected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain text, for a relay on the same host or network.
+    None,
+    /// Upgrade with STARTTLS, usually on port 587.
+    #[default]
+    StartTls,
+    /// TLS from the first byte, usually on port 465.
+    Tls,
+}
+
+impl FromStr for SmtpSecurity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(SmtpSecurity::None),
+            "starttls" => Ok(SmtpSecurity::StartTls),
+            "tls" => Ok(SmtpSecurity::Tls),
+            other => Err(format!("expected \"none\", \"starttls\" or \"tls\", got {:?}", other)),
+        }
+    }
+}
+
+/// Where to submit mail and who it comes from. Credentials are only sent
+/// when both are set.
+#[derive(Debug, Clone)]
+pub struct SmtpSettings {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+}
+
+/// Emails reminders through an SMTP server.
+pub struct SmtpNotifier {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpNotifier {
+    pub fn new(settings: &SmtpSettings) -> Result<Self, NotifyError> {
+        let builder = match settings.security {
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&settings.host),
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&settings.host)?,
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&settings.host)?,
+        }
+        .port(settings.port);
+        let builder = match (&settings.username, &settings.password) {
+            (Some(username), Some(password)) => {
+                builder.credentials(Credentials::new(username.clone(), password.clone()))
+            }
+            _ => builder,
+        };
+        Ok(Self {
+            transport: builder.build(),
+            from: settings.from.parse()?,
+        })
+    }
+}
+
+impl Notifier for SmtpNotifier {
+    fn channel(&self) -> &'static str {
+        "smtp"
+    }
+
+    async fn send(&self, message: &Message) -> Result<(), NotifyError> {
+        let to = Mailbox::new(Some(message.to_name.clone()), message.to.parse()?);
+        let email = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&message.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(message.body.clone())?;
+        self.transport.send(email).await?;
+        Ok(())
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for NotifyError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        NotifyError(e.to_string())
+    }
+}
+
+impl From<lettre::address::AddressError> for NotifyError {
+    fn from(e: lettre::address::AddressError) -> Self {
+        NotifyError(e.to_string())
+    }
+}
+
+impl From<lettre::error::Error> for NotifyError {
+    fn from(e: lettre::error::Error) -> Self {
+        NotifyError(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::ReminderKind;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Accepts one connection, answers like a mail server and returns
+    /// everything the client said.
+    async fn fake_server(listener: TcpListener) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        let mut transcript = String::new();
+        write.write_all(b"220 fake ESMTP\r\n").await.unwrap();
+        let mut in_data = false;
+        while let Some(line) = lines.next_line().await.unwrap() {
+            transcript.push_str(&line);
+            transcript.push('\n');
+            let reply: &[u8] = if in_data {
+                if line != "." {
+                    continue;
+                }
+                in_data = false;
+                b"250 queued\r\n"
+            } else if line == "DATA" {
+                in_data = true;
+                b"354 go ahead\r\n"
+            } else if line == "QUIT" {
+                write.write_all(b"221 bye\r\n").await.unwrap();
+                break;
+            } else {
+                b"250 ok\r\n"
+            };
+            write.write_all(reply).await.unwrap();
+        }
+        transcript
+    }
+
+    #[actix_web::test]
+    async fn reminders_are_submitted_over_smtp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = actix_web::rt::spawn(fake_server(listener));
+
+        let notifier = SmtpNotifier::new(&SmtpSettings {
+            host: "127.0.0.1".into(),
+            port,
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: "Book Library <library@example.com>".into(),
+        })
+        .unwrap();
+        notifier
+            .send(&Message {
+                ledger_id: 1,
+                member_id: 1,
+                kind: ReminderKind::Overdue,
+                to_name: "Ada Lovelace".into(),
+                to: "ada@example.com".into(),
+                subject: "Overdue".into(),
+                body: "Please bring it back.".into(),
+            })
+            .await
+            .unwrap();
+
+        let transcript = server.await.unwrap();
+        assert!(transcript.contains("MAIL FROM:<library@example.com>"), "{transcript}");
+        assert!(transcript.contains("RCPT TO:<ada@example.com>"), "{transcript}");
+        assert!(transcript.contains("Subject: Overdue"), "{transcript}");
+        assert!(transcript.contains("Please bring it back."), "{transcript}");
+    }
+
+    #[test]
+    fn bad_addresses_are_rejected() {
+        let settings = SmtpSettings {
+            host: "localhost".into(),
+            port: 25,
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: "not an address".into(),
+        };
+        assert!(SmtpNotifier::new(&settings).is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+pub const DEFAULT_LIMIT: i64 = 20;
+pub const MAX_LIMIT: i64 = 100;
+
+pub fn clamp_limit(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+impl SortOrder {
+    pub fn sql(self) -> &'static str {
+        match self {
+            SortOrder::Asc => "ASC",
+            SortOrder::Desc => "DESC",
+        }
+    }
+
+    /// Comparison that selects rows after a keyset position in this order.
+    pub fn after(self) -> &'static str {
+        match self {
+            SortOrder::Asc => ">",
+            SortOrder::Desc => "<",
+        }
+    }
+}
+
+/// Value of the sort column for the last row on a page.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SortKey {
+    Int(i64),
+    Text(String),
+}
+
+/// Opaque keyset position handed to clients as `next_cursor`. It remembers
+/// the sort it was produced under so it cannot be replayed against another.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub sort: String,
+    pub order: SortOrder,
+    pub key: SortKey,
+    pub id: i32,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("cursor serializes"))
+    }
+
+    pub fn decode(token: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(token).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+}
+
+/// One page of a listing. `page` is set for offset paging and omitted when
+/// the client is following cursors.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub limit: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<i64>,
+    pub next_cursor: Option<String>,
+}
+
+/// How one request pages through a listing. Cursors take over from page
+/// numbers once a client starts following them, so `page` is `None` then.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageRequest {
+    pub limit: i64,
+    pub page: Option<i64>,
+    pub offset: i64,
+}
+
+impl PageRequest {
+    pub fn new(limit: Option<i64>, page: Option<i64>, following_cursor: bool) -> Self {
+        let limit = clamp_limit(limit);
+        let page = if following_cursor {
+            None
+        } else {
+            Some(page.unwrap_or(1).max(1))
+        };
+        Self {
+            limit,
+            page,
+            offset: page.map_or(0, |page| (page - 1) * limit),
+        }
+    }
+
+    /// Rows to fetch: one extra tells whether there is a next page.
+    pub fn fetch_limit(&self) -> i64 {
+        self.limit + 1
+    }
+
+    /// The page of `rows` fetched with `fetch_limit`, continuing from a
+    /// cursor made by `cursor` from its last row if more are left.
+    pub fn page<T>(
+        &self,
+        mut rows: Vec<T>,
+        total: i64,
+        cursor: impl FnOnce(&T) -> Option<String>,
+    ) -> Page<T> {
+        let next_cursor = if rows.len() as i64 > self.limit {
+            rows.truncate(self.limit as usize);
+            rows.last().and_then(cursor)
+        } else {
+            None
+        };
+        Page {
+            items: rows,
+            total,
+            limit: self.limit,
+            page: self.page,
+            next_cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = Cursor {
+            sort: "name".into(),
+            order: SortOrder::Desc,
+            key: SortKey::Text("Dune".into()),
+            id: 42,
+        };
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+
+        let cursor = Cursor {
+            sort: "publication_year".into(),
+            order: SortOrder::Asc,
+            key: SortKey::Int(1965),
+            id: 7,
+        };
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+    }
+
+    #[test]
+    fn garbage_cursors_are_rejected() {
+        assert_eq!(Cursor::decode("not a cursor"), None);
+        assert_eq!(Cursor::decode(&URL_SAFE_NO_PAD.encode("{}")), None);
+    }
+
+    #[test]
+    fn limit_is_clamped() {
+        assert_eq!(clamp_limit(None), DEFAULT_LIMIT);
+        assert_eq!(clamp_limit(Some(0)), 1);
+        assert_eq!(clamp_limit(Some(10_000)), MAX_LIMIT);
+    }
+
+    #[test]
+    fn pages_end_where_the_extra_row_is_missing() {
+        let request = PageRequest::new(Some(2), Some(3), false);
+        assert_eq!((request.page, request.offset), (Some(3), 4));
+        let page = request.page(vec![5, 6, 7], 7, |last| Some(last.to_string()));
+        assert_eq!(page.items, [5, 6]);
+        assert_eq!(page.next_cursor.as_deref(), Some("6"));
+
+        let request = PageRequest::new(Some(2), Some(3), true);
+        assert_eq!((request.page, request.offset), (None, 0));
+        let page = request.page(vec![7], 7, |last| Some(last.to_string()));
+        assert_eq!(page.items, [7]);
+        assert_eq!(page.next_cursor, None);
+    }
+}
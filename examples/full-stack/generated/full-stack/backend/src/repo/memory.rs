@@ -0,0 +1,1145 @@
+use chrono::NaiveDateTime;
+use std::cmp::{Ordering, Reverse};
+use std::sync::Mutex;
+
+use super::{erased_email, stock_target, LibraryRepository, NewMember, ReturnOutcome, ERASED_NAME};
+use crate::error::ApiError;
+use crate::fines::FinePolicy;
+use crate::holds::HoldPolicy;
+use crate::loans::{Borrower, LoanPolicy};
+use crate::models::*;
+use crate::notify::NotificationPolicy;
+use crate::pagination::{Cursor, SortKey, SortOrder};
+
+/// Everything the in-memory backend stores, one `Vec` per table.
+#[derive(Debug, Clone, Default)]
+pub struct State {
+    pub members: Vec<Member>,
+    pub librarians: Vec<Librarian>,
+    pub books: Vec<Book>,
+    pub loans: Vec<BorrowLedger>,
+    pub fines: Vec<MemberFine>,
+    pub holds: Vec<Hold>,
+    pub incidents: Vec<LoanIncident>,
+    pub notifications: Vec<LoanNotification>,
+}
+
+/// A backend that keeps the library in process memory, so handler tests run
+/// without a database. It applies the same rules as Postgres;
+/// full-text search is approximated by matching every word of `q` against
+/// the title or author.
+#[derive(Debug, Default)]
+pub struct MemoryRepository {
+    state: Mutex<State>,
+}
+
+/// Next id for a table whose ids are `key` of its rows, like a `SERIAL`.
+fn next_id<T>(rows: &[T], key: impl Fn(&T) -> i32) -> i32 {
+    rows.iter().map(key).max().unwrap_or(0) + 1
+}
+
+fn is_active(hold: &Hold) -> bool {
+    hold.status == "waiting" || hold.status == "ready"
+}
+
+impl State {
+    fn member_mut(&mut self, member_id: i32) -> Result<&mut Member, ApiError> {
+        self.members
+            .iter_mut()
+            .find(|m| m.member_id == member_id)
+            .ok_or(ApiError::NotFound)
+    }
+
+    /// Refuses members who closed their account.
+    /// What the loan policy judges a member on, refusing closed accounts.
+    fn borrower(&self, member_id: i32) -> Result<Borrower, ApiError> {
+        self.ensure_active(member_id)?;
+        let member = self.members.iter().find(|m| m.member_id == member_id);
+        let member = member.ok_or(ApiError::NotFound)?;
+        Ok(Borrower {
+            age: member.age,
+            blocked: member.blocked_at.is_some(),
+            blocked_reason: member.blocked_reason.clone(),
+            open_loans: self
+                .loans
+                .iter()
+                .filter(|l| l.member_id == member_id && l.actual_return.is_none())
+                .map(|l| l.book_id)
+                .collect(),
+        })
+    }
+
+    fn ensure_active(&self, member_id: i32) -> Result<(), ApiError> {
+        match self.members.iter().find(|m| m.member_id == member_id) {
+            None => Err(ApiError::NotFound),
+            Some(m) if m.deactivated_at.is_some() => Err(ApiError::AccountDeactivated),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Deactivates the member unless books are still out, cancelling their
+    /// active holds. Returns when the account was closed.
+    fn close_account(
+        &mut self,
+        member_id: i32,
+        policy: &HoldPolicy,
+        now: NaiveDateTime,
+    ) -> Result<NaiveDateTime, ApiError> {
+        self.member_mut(member_id)?;
+        let open_loans = self
+            .loans
+            .iter()
+            .filter(|l| l.member_id == member_id && l.actual_return.is_none())
+            .count() as i64;
+        if open_loans > 0 {
+            return Err(ApiError::MemberHasLoans { open_loans });
+        }
+
+        let mut reserved = Vec::new();
+        for hold in self
+            .holds
+            .iter_mut()
+            .filter(|h| h.member_id == member_id && is_active(h))
+        {
+            if hold.status == "ready" {
+                reserved.push(hold.book_id);
+            }
+            hold.status = "cancelled".into();
+        }
+        for book_id in reserved {
+            self.release_copy(book_id, policy, now)?;
+        }
+
+        Ok(*self.member_mut(member_id)?.deactivated_at.get_or_insert(now))
+    }
+
+    fn book(&self, book_id: i32) -> Result<&Book, ApiError> {
+        self.books
+            .iter()
+            .find(|b| b.book_id == book_id)
+            .ok_or(ApiError::BookNotFound(book_id))
+    }
+
+    fn book_mut(&mut self, book_id: i32) -> Result<&mut Book, ApiError> {
+        self.books
+            .iter_mut()
+            .find(|b| b.book_id == book_id)
+            .ok_or(ApiError::BookNotFound(book_id))
+    }
+
+    fn balance_cents(&self, member_id: i32) -> i64 {
+        self.fines
+            .iter()
+            .filter(|f| f.member_id == member_id)
+            .map(|f| i64::from(f.amount_cents))
+            .sum()
+    }
+
+    fn charge(
+        &mut self,
+        member_id: i32,
+        ledger_id: Option<i32>,
+        kind: &str,
+        amount_cents: i32,
+        description: String,
+        now: NaiveDateTime,
+    ) {
+        let fine_id = next_id(&self.fines, |f| f.fine_id);
+        self.fines.push(MemberFine {
+            fine_id,
+            member_id,
+            ledger_id,
+            kind: kind.to_string(),
+            amount_cents,
+            description: Some(description),
+            created_at: now,
+        });
+    }
+
+    /// Marks the longest-waiting hold on the book ready for pickup.
+    fn promote_next(
+        &mut self,
+        book_id: i32,
+        policy: &HoldPolicy,
+        now: NaiveDateTime,
+    ) -> Option<i32> {
+        let hold = self
+            .holds
+            .iter_mut()
+            .filter(|h| h.book_id == book_id && h.status == "waiting")
+            .min_by_key(|h| (h.placed_at, h.hold_id))?;
+        hold.status = "ready".into();
+        hold.ready_at = Some(now);
+        hold.expires_at = Some(now + policy.pickup_window);
+        Some(hold.hold_id)
+    }
+
+    /// Gives a returned copy to the next hold, or puts it back on the shelf.
+    fn release_copy(
+        &mut self,
+        book_id: i32,
+        policy: &HoldPolicy,
+        now: NaiveDateTime,
+    ) -> Result<Option<i32>, ApiError> {
+        let reserved = self.promote_next(book_id, policy, now);
+        if reserved.is_none() {
+            self.book_mut(book_id)?.number_of_copies += 1;
+        }
+        Ok(reserved)
+    }
+
+    /// Moves shelf copies to waiting holds until one or the other runs out.
+    fn serve_waiting(
+        &mut self,
+        book_id: i32,
+        policy: &HoldPolicy,
+        now: NaiveDateTime,
+    ) -> Result<i32, ApiError> {
+        let mut served = 0;
+        while self.book(book_id)?.number_of_copies > 0 {
+            if self.promote_next(book_id, policy, now).is_none() {
+                break;
+            }
+            self.book_mut(book_id)?.number_of_copies -= 1;
+            served += 1;
+        }
+        Ok(served)
+    }
+
+    fn matches(book: &Book, query: &ListBooksQuery) -> bool {
+        let contains =
+            |haystack: &str, needle: &str| haystack.to_lowercase().contains(&needle.to_lowercase());
+        if !query.include_archived && book.archived_at.is_some() {
+            return false;
+        }
+        if let Some(q) = &query.q {
+            if !q
+                .split_whitespace()
+                .all(|word| contains(&book.name, word) || contains(&book.author, word))
+            {
+                return false;
+            }
+        }
+        if let Some(author) = query.author.as_deref().filter(|a| !a.trim().is_empty()) {
+            if !contains(&book.author, author) {
+                return false;
+            }
+        }
+        query.publication_year.is_none() || book.publication_year == query.publication_year
+    }
+}
+
+fn compare_keys(a: &SortKey, b: &SortKey) -> Ordering {
+    match (a, b) {
+        (SortKey::Int(a), SortKey::Int(b)) => a.cmp(b),
+        (SortKey::Text(a), SortKey::Text(b)) => a.cmp(b),
+        // A cursor for another sort is rejected before it gets here.
+        (SortKey::Int(_), SortKey::Text(_)) => Ordering::Less,
+        (SortKey::Text(_), SortKey::Int(_)) => Ordering::Greater,
+    }
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `f` on a copy of the state and keeps the copy only if `f`
+    /// succeeds, which gives each operation the all-or-nothing behaviour of
+    /// a database transaction.
+    fn transaction<T>(
+        &self,
+        f: impl FnOnce(&mut State) -> Result<T, ApiError>,
+    ) -> Result<T, ApiError> {
+        let mut state = self.state.lock().expect("memory repository lock poisoned");
+        let mut draft = state.clone();
+        let out = f(&mut draft)?;
+        *state = draft;
+        Ok(out)
+    }
+
+    fn read<T>(&self, f: impl FnOnce(&State) -> T) -> T {
+        f(&self.state.lock().expect("memory repository lock poisoned"))
+    }
+
+    /// Direct access to the stored rows, for tests to arrange situations the
+    /// API cannot reach (such as an overdue loan) and to inspect the result.
+    pub fn with_state<T>(&self, f: impl FnOnce(&mut State) -> T) -> T {
+        f(&mut self.state.lock().expect("memory repository lock poisoned"))
+    }
+}
+
+impl LibraryRepository for MemoryRepository {
+    // ── Accounts ──
+
+    async fn create_member(&self, member: NewMember) -> Result<i32, ApiError> {
+        self.transaction(|s| {
+            if s.members
+                .iter()
+                .any(|m| m.email.eq_ignore_ascii_case(&member.email))
+            {
+                return Err(ApiError::EmailTaken);
+            }
+            let member_id = next_id(&s.members, |m| m.member_id);
+            s.members.push(Member {
+                member_id,
+                name: member.name,
+                address: member.address,
+                age: Some(member.age),
+                email: member.email,
+                password_hash: Some(member.password_hash),
+                deactivated_at: None,
+                erased_at: None,
+                blocked_at: None,
+                blocked_reason: None,
+            });
+            Ok(member_id)
+        })
+    }
+
+    async fn find_member(&self, member_id: i32) -> Result<Option<Member>, ApiError> {
+        Ok(self.read(|s| s.members.iter().find(|m| m.member_id == member_id).cloned()))
+    }
+
+    async fn update_member(
+        &self,
+        member_id: i32,
+        changes: &UpdateMemberRequest,
+    ) -> Result<Member, ApiError> {
+        self.transaction(|s| {
+            if let Some(email) = &changes.email {
+                if s.members
+                    .iter()
+                    .any(|m| m.member_id != member_id && m.email.eq_ignore_ascii_case(email))
+                {
+                    return Err(ApiError::EmailTaken);
+                }
+            }
+            let member = s.member_mut(member_id)?;
+            if member.erased_at.is_some() {
+                return Err(ApiError::NotFound);
+            }
+            if let Some(name) = &changes.name {
+                member.name = name.clone();
+            }
+            if let Some(address) = &changes.address {
+                member.address = address.clone();
+            }
+            if let Some(age) = changes.age {
+                member.age = Some(age);
+            }
+            if let Some(email) = &changes.email {
+                member.email = email.clone();
+            }
+            Ok(member.clone())
+        })
+    }
+
+    async fn deactivate_member(
+        &self,
+        member_id: i32,
+        now: NaiveDateTime,
+        holds: &HoldPolicy,
+    ) -> Result<NaiveDateTime, ApiError> {
+        self.transaction(|s| s.close_account(member_id, holds, now))
+    }
+
+    async fn erase_member(
+        &self,
+        member_id: i32,
+        now: NaiveDateTime,
+        holds: &HoldPolicy,
+    ) -> Result<(), ApiError> {
+        self.transaction(|s| {
+            s.close_account(member_id, holds, now)?;
+            for incident in s.incidents.iter_mut().filter(|i| i.member_id == member_id) {
+                incident.notes = None;
+            }
+
+            let member = s.member_mut(member_id)?;
+            member.name = ERASED_NAME.into();
+            member.address = None;
+            member.age = None;
+            member.email = erased_email(member_id);
+            member.password_hash = None;
+            member.blocked_reason = None;
+            member.erased_at.get_or_insert(now);
+            Ok(())
+        })
+    }
+
+    async fn export_member(&self, member_id: i32) -> Result<MemberExport, ApiError> {
+        self.read(|s| {
+            let member = s
+                .members
+                .iter()
+                .find(|m| m.member_id == member_id)
+                .cloned()
+                .ok_or(ApiError::NotFound)?;
+            let book_name = |book_id: i32| s.book(book_id).ok().map(|b| b.name.clone());
+
+            let mut loans: Vec<BorrowLedger> = s
+                .loans
+                .iter()
+                .filter(|l| l.member_id == member_id)
+                .map(|l| BorrowLedger {
+                    book_name: book_name(l.book_id),
+                    ..l.clone()
+                })
+                .collect();
+            loans.sort_by_key(|l| Reverse((l.borrow_date, l.id)));
+            let mut fines: Vec<MemberFine> = s
+                .fines
+                .iter()
+                .filter(|f| f.member_id == member_id)
+                .cloned()
+                .collect();
+            fines.sort_by_key(|f| Reverse((f.created_at, f.fine_id)));
+            let mut holds: Vec<Hold> = s
+                .holds
+                .iter()
+                .filter(|h| h.member_id == member_id)
+                .map(|h| Hold {
+                    book_name: book_name(h.book_id),
+                    ..h.clone()
+                })
+                .collect();
+            holds.sort_by_key(|h| Reverse((h.placed_at, h.hold_id)));
+            let mut incidents: Vec<LoanIncident> = s
+                .incidents
+                .iter()
+                .filter(|i| i.member_id == member_id)
+                .cloned()
+                .collect();
+            incidents.sort_by_key(|i| Reverse((i.reported_at, i.incident_id)));
+            let mut notifications: Vec<LoanNotification> = s
+                .notifications
+                .iter()
+                .filter(|n| n.member_id == member_id)
+                .cloned()
+                .collect();
+            notifications.sort_by_key(|n| Reverse((n.sent_at, n.notification_id)));
+
+            Ok(MemberExport {
+                member,
+                loans,
+                fines,
+                holds,
+                incidents,
+                notifications,
+            })
+        })
+    }
+
+    async fn set_member_block(
+        &self,
+        member_id: i32,
+        block: Option<(&str, NaiveDateTime)>,
+    ) -> Result<Member, ApiError> {
+        self.transaction(|s| {
+            let member = s.member_mut(member_id)?;
+            if member.erased_at.is_some() {
+                return Err(ApiError::NotFound);
+            }
+            let (reason, blocked_at) = block.unzip();
+            member.blocked_at = blocked_at;
+            member.blocked_reason = reason.map(str::to_owned);
+            Ok(member.clone())
+        })
+    }
+
+    async fn find_librarian(&self, username: &str) -> Result<Option<Librarian>, ApiError> {
+        Ok(self.read(|s| {
+            s.librarians
+                .iter()
+                .find(|l| l.username == username)
+                .cloned()
+        }))
+    }
+
+    async fn ensure_librarian(&self, username: &str, password_hash: &str) -> Result<(), ApiError> {
+        self.transaction(|s| {
+            if !s.librarians.iter().any(|l| l.username == username) {
+                let librarian_id = next_id(&s.librarians, |l| l.librarian_id);
+                s.librarians.push(Librarian {
+                    librarian_id,
+                    username: username.to_string(),
+                    password_hash: password_hash.to_string(),
+                });
+            }
+            Ok(())
+        })
+    }
+
+    // ── Loans ──
+
+    async fn checkout(
+        &self,
+        member_id: i32,
+        book_ids: &[i32],
+        now: NaiveDateTime,
+        fines: &FinePolicy,
+        loans: &LoanPolicy,
+    ) -> Result<NaiveDateTime, ApiError> {
+        let expected_return = now + loans.loan_period;
+        self.transaction(|s| {
+            let borrower = s.borrower(member_id)?;
+            let balance = s.balance_cents(member_id);
+            if fines.blocks_checkout(balance) {
+                return Err(ApiError::FinesBlockCheckout {
+                    balance_cents: balance,
+                    limit_cents: fines.block_threshold_cents,
+                });
+            }
+
+            let requested: Vec<_> = book_ids
+                .iter()
+                .map(|&id| (id, s.books.iter().find(|b| b.book_id == id).and_then(|b| b.min_age)))
+                .collect();
+            loans.check_checkout(&borrower, &requested)?;
+
+            let mut book_ids = book_ids.to_vec();
+            book_ids.sort_unstable();
+            for book_id in book_ids {
+                let hold = s
+                    .holds
+                    .iter_mut()
+                    .find(|h| h.book_id == book_id && h.member_id == member_id && is_active(h));
+                let reserved = match hold {
+                    Some(hold) => {
+                        let was_ready = hold.status == "ready";
+                        hold.status = "fulfilled".into();
+                        was_ready
+                    }
+                    None => false,
+                };
+
+                let book = s.book_mut(book_id)?;
+                if book.archived_at.is_some() {
+                    return Err(ApiError::BookNotFound(book_id));
+                }
+                if !reserved {
+                    if book.number_of_copies == 0 {
+                        return Err(ApiError::NoCopiesAvailable(book_id));
+                    }
+                    book.number_of_copies -= 1;
+                }
+                let max_renewals = book.max_renewals.unwrap_or(loans.max_renewals);
+
+                let id = next_id(&s.loans, |l| l.id);
+                s.loans.push(BorrowLedger {
+                    id,
+                    book_id,
+                    member_id,
+                    borrow_date: now,
+                    expected_return,
+                    actual_return: None,
+                    return_condition: None,
+                    renewal_count: 0,
+                    max_renewals,
+                    book_name: None,
+                });
+            }
+            Ok(expected_return)
+        })
+    }
+
+    async fn open_loans(&self, member_id: i32) -> Result<Vec<BorrowLedger>, ApiError> {
+        Ok(self.read(|s| {
+            let mut loans: Vec<BorrowLedger> = s
+                .loans
+                .iter()
+                .filter(|l| l.member_id == member_id && l.actual_return.is_none())
+                .map(|l| BorrowLedger {
+                    book_name: s.book(l.book_id).ok().map(|b| b.name.clone()),
+                    ..l.clone()
+                })
+                .collect();
+            loans.sort_by_key(|l| Reverse(l.borrow_date));
+            loans
+        }))
+    }
+
+    async fn renew_loan(
+        &self,
+        member_id: i32,
+        ledger_id: i32,
+        now: NaiveDateTime,
+        loans: &LoanPolicy,
+    ) -> Result<BorrowLedger, ApiError> {
+        self.transaction(|s| {
+            let loan = s
+                .loans
+                .iter()
+                .find(|l| {
+                    l.id == ledger_id && l.member_id == member_id && l.actual_return.is_none()
+                })
+                .ok_or(ApiError::LoanNotFound)?;
+
+            if loan.renewal_count >= loan.max_renewals {
+                return Err(ApiError::RenewalLimitReached {
+                    max_renewals: loan.max_renewals,
+                });
+            }
+            if now > loan.expected_return {
+                return Err(ApiError::LoanOverdue);
+            }
+            if s.holds
+                .iter()
+                .any(|h| h.book_id == loan.book_id && h.status == "waiting")
+            {
+                return Err(ApiError::HoldsWaiting);
+            }
+            let book_name = s.book(loan.book_id).ok().map(|b| b.name.clone());
+
+            let loan = s
+                .loans
+                .iter_mut()
+                .find(|l| l.id == ledger_id)
+                .expect("found above");
+            loan.expected_return += loans.loan_period;
+            loan.renewal_count += 1;
+            Ok(BorrowLedger {
+                book_name,
+                ..loan.clone()
+            })
+        })
+    }
+
+    async fn return_book(
+        &self,
+        member_id: i32,
+        request: &ReturnRequest,
+        now: NaiveDateTime,
+        fines: &FinePolicy,
+        holds: &HoldPolicy,
+    ) -> Result<ReturnOutcome, ApiError> {
+        let condition = request.condition;
+        self.transaction(|s| {
+            let loan = s
+                .loans
+                .iter_mut()
+                .filter(|l| {
+                    l.book_id == request.book_id
+                        && l.member_id == member_id
+                        && l.actual_return.is_none()
+                })
+                .min_by_key(|l| (l.borrow_date, l.id))
+                .ok_or(ApiError::LoanNotFound)?;
+            loan.actual_return = Some(now);
+            loan.return_condition = Some(condition.as_str().to_string());
+            let (ledger_id, due) = (loan.id, loan.expected_return);
+
+            let mut reserved_for_hold = None;
+            if condition != ReturnCondition::Lost {
+                reserved_for_hold = s.release_copy(request.book_id, holds, now)?;
+            }
+
+            let mut incident_id = None;
+            if condition != ReturnCondition::Good {
+                let id = next_id(&s.incidents, |i| i.incident_id);
+                s.incidents.push(LoanIncident {
+                    incident_id: id,
+                    ledger_id,
+                    member_id,
+                    book_id: request.book_id,
+                    condition: condition.as_str().to_string(),
+                    notes: request.notes.clone(),
+                    reported_at: now,
+                });
+                incident_id = Some(id);
+            }
+
+            let late_fee_cents = fines.late_fee_cents(due, now);
+            if late_fee_cents > 0 {
+                let description = format!("Returned late; was due {}", due.format("%Y-%m-%d"));
+                s.charge(
+                    member_id,
+                    Some(ledger_id),
+                    "late_return",
+                    late_fee_cents,
+                    description,
+                    now,
+                );
+            }
+
+            Ok(ReturnOutcome {
+                ledger_id,
+                incident_id,
+                late_fee_cents,
+                reserved_for_hold,
+            })
+        })
+    }
+
+    async fn list_loans(
+        &self,
+        member_id: Option<i32>,
+        query: &LoanQuery,
+        now: NaiveDateTime,
+        after: Option<(NaiveDateTime, i32)>,
+        offset: i64,
+        limit: Option<i64>,
+    ) -> Result<(Vec<BorrowLedger>, i64), ApiError> {
+        let (start, end) = query.borrowed_between();
+        let matches = |l: &BorrowLedger| {
+            member_id.is_none_or(|id| l.member_id == id)
+                && query.book_id.is_none_or(|id| l.book_id == id)
+                && start.is_none_or(|start| l.borrow_date >= start)
+                && end.is_none_or(|end| l.borrow_date < end)
+                && query.overdue.is_none_or(|overdue| {
+                    overdue == (l.actual_return.is_none() && l.expected_return < now)
+                })
+                && query.condition.is_none_or(|condition| {
+                    l.return_condition.as_deref() == Some(condition.as_str())
+                })
+        };
+
+        Ok(self.read(|s| {
+            let mut loans: Vec<&BorrowLedger> = s.loans.iter().filter(|l| matches(l)).collect();
+            let total = loans.len() as i64;
+            loans.sort_by_key(|l| Reverse((l.borrow_date, l.id)));
+
+            let page = loans
+                .into_iter()
+                .filter(|l| after.is_none_or(|position| (l.borrow_date, l.id) < position))
+                .skip(offset as usize)
+                .take(limit.map_or(usize::MAX, |limit| limit as usize))
+                .map(|l| BorrowLedger {
+                    book_name: s.book(l.book_id).ok().map(|b| b.name.clone()),
+                    ..l.clone()
+                })
+                .collect();
+            (page, total)
+        }))
+    }
+
+    // ── Fines ──
+
+    async fn fines(&self, member_id: i32) -> Result<Vec<MemberFine>, ApiError> {
+        Ok(self.read(|s| {
+            let mut entries: Vec<MemberFine> = s
+                .fines
+                .iter()
+                .filter(|f| f.member_id == member_id)
+                .cloned()
+                .collect();
+            entries.sort_by_key(|f| Reverse((f.created_at, f.fine_id)));
+            entries
+        }))
+    }
+
+    async fn pay_fine(
+        &self,
+        member_id: i32,
+        amount_cents: i32,
+        now: NaiveDateTime,
+    ) -> Result<i64, ApiError> {
+        self.transaction(|s| {
+            let balance = s.balance_cents(member_id);
+            if i64::from(amount_cents) > balance {
+                return Err(ApiError::PaymentExceedsBalance {
+                    balance_cents: balance,
+                });
+            }
+            s.charge(
+                member_id,
+                None,
+                "payment",
+                -amount_cents,
+                "Payment received".into(),
+                now,
+            );
+            Ok(balance - i64::from(amount_cents))
+        })
+    }
+
+    // ── Holds ──
+
+    async fn place_hold(
+        &self,
+        member_id: i32,
+        book_id: i32,
+        now: NaiveDateTime,
+    ) -> Result<Hold, ApiError> {
+        self.transaction(|s| {
+            s.ensure_active(member_id)?;
+            let book = s.book(book_id)?;
+            if book.archived_at.is_some() {
+                return Err(ApiError::BookNotFound(book_id));
+            }
+            if book.number_of_copies > 0 {
+                return Err(ApiError::CopiesAvailable);
+            }
+            if s.holds
+                .iter()
+                .any(|h| h.book_id == book_id && h.member_id == member_id && is_active(h))
+            {
+                return Err(ApiError::HoldAlreadyActive);
+            }
+
+            let hold = Hold {
+                hold_id: next_id(&s.holds, |h| h.hold_id),
+                book_id,
+                member_id,
+                status: "waiting".into(),
+                placed_at: now,
+                ready_at: None,
+                expires_at: None,
+                book_name: None,
+                queue_position: None,
+            };
+            s.holds.push(hold.clone());
+            Ok(hold)
+        })
+    }
+
+    async fn active_holds(&self, member_id: i32) -> Result<Vec<Hold>, ApiError> {
+        Ok(self.read(|s| {
+            let mut holds: Vec<Hold> = s
+                .holds
+                .iter()
+                .filter(|h| h.member_id == member_id && is_active(h))
+                .map(|h| {
+                    let ahead = s
+                        .holds
+                        .iter()
+                        .filter(|q| {
+                            q.book_id == h.book_id
+                                && q.status == "waiting"
+                                && (q.placed_at, q.hold_id) < (h.placed_at, h.hold_id)
+                        })
+                        .count() as i64;
+                    Hold {
+                        book_name: s.book(h.book_id).ok().map(|b| b.name.clone()),
+                        queue_position: (h.status == "waiting").then_some(ahead + 1),
+                        ..h.clone()
+                    }
+                })
+                .collect();
+            holds.sort_by_key(|h| h.placed_at);
+            holds
+        }))
+    }
+
+    async fn cancel_hold(
+        &self,
+        member_id: i32,
+        hold_id: i32,
+        now: NaiveDateTime,
+        holds: &HoldPolicy,
+    ) -> Result<(), ApiError> {
+        self.transaction(|s| {
+            let hold = s
+                .holds
+                .iter_mut()
+                .find(|h| h.hold_id == hold_id && h.member_id == member_id && is_active(h))
+                .ok_or(ApiError::HoldNotFound)?;
+            let was_ready = hold.status == "ready";
+            hold.status = "cancelled".into();
+            let book_id = hold.book_id;
+
+            if was_ready {
+                s.release_copy(book_id, holds, now)?;
+            }
+            Ok(())
+        })
+    }
+
+    async fn expire_holds(&self, now: NaiveDateTime, holds: &HoldPolicy) -> Result<u64, ApiError> {
+        self.transaction(|s| {
+            let mut expired = Vec::new();
+            for hold in &mut s.holds {
+                if hold.status == "ready" && hold.expires_at.is_some_and(|at| at < now) {
+                    hold.status = "expired".into();
+                    expired.push(hold.book_id);
+                }
+            }
+            for &book_id in &expired {
+                s.release_copy(book_id, holds, now)?;
+            }
+            Ok(expired.len() as u64)
+        })
+    }
+
+    // ── Notifications ──
+
+    async fn due_reminders(
+        &self,
+        now: NaiveDateTime,
+        policy: &NotificationPolicy,
+    ) -> Result<Vec<LoanReminder>, ApiError> {
+        Ok(self.read(|s| {
+            let reminded = |l: &BorrowLedger| {
+                s.notifications.iter().any(|n| {
+                    n.ledger_id == l.id
+                        && n.due_at == l.expected_return
+                        && if l.expected_return < now {
+                            n.kind == "overdue" && n.sent_at > now - policy.overdue_repeat
+                        } else {
+                            n.kind == "due_soon"
+                        }
+                })
+            };
+            let mut reminders: Vec<LoanReminder> = s
+                .loans
+                .iter()
+                .filter(|l| l.actual_return.is_none() && l.expected_return < now + policy.remind_before)
+                .filter(|l| !reminded(l))
+                .filter_map(|l| {
+                    let member = s
+                        .members
+                        .iter()
+                        .find(|m| m.member_id == l.member_id && m.deactivated_at.is_none())?;
+                    Some(LoanReminder {
+                        ledger_id: l.id,
+                        member_id: l.member_id,
+                        member_name: member.name.clone(),
+                        email: member.email.clone(),
+                        book_id: l.book_id,
+                        book_name: s.book(l.book_id).ok()?.name.clone(),
+                        expected_return: l.expected_return,
+                    })
+                })
+                .collect();
+            reminders.sort_by_key(|r| (r.expected_return, r.ledger_id));
+            reminders
+        }))
+    }
+
+    async fn claim_notification(
+        &self,
+        reminder: &LoanReminder,
+        kind: ReminderKind,
+        channel: &str,
+        now: NaiveDateTime,
+        policy: &NotificationPolicy,
+    ) -> Result<Option<i32>, ApiError> {
+        self.transaction(|s| {
+            let still_due = s.loans.iter().any(|l| {
+                l.id == reminder.ledger_id
+                    && l.actual_return.is_none()
+                    && l.expected_return == reminder.expected_return
+            });
+            let claimed = s.notifications.iter().any(|n| {
+                n.ledger_id == reminder.ledger_id
+                    && n.due_at == reminder.expected_return
+                    && n.kind == kind.as_str()
+                    && (kind == ReminderKind::DueSoon || n.sent_at > now - policy.overdue_repeat)
+            });
+            if !still_due || claimed {
+                return Ok(None);
+            }
+            let notification_id = next_id(&s.notifications, |n| n.notification_id);
+            s.notifications.push(LoanNotification {
+                notification_id,
+                ledger_id: reminder.ledger_id,
+                member_id: reminder.member_id,
+                kind: kind.as_str().into(),
+                channel: channel.into(),
+                due_at: reminder.expected_return,
+                sent_at: now,
+            });
+            Ok(Some(notification_id))
+        })
+    }
+
+    async fn release_notification(&self, notification_id: i32) -> Result<(), ApiError> {
+        self.transaction(|s| {
+            s.notifications.retain(|n| n.notification_id != notification_id);
+            Ok(())
+        })
+    }
+
+    // ── Catalogue ──
+
+    async fn list_books(
+        &self,
+        query: &ListBooksQuery,
+        after: Option<&Cursor>,
+        offset: i64,
+        limit: i64,
+    ) -> Result<(Vec<Book>, i64), ApiError> {
+        let (sort, order) = (query.sort, query.order);
+        let ordered = |a: &Book, b: &Book| {
+            let by_key = compare_keys(&sort.key(a), &sort.key(b)).then(a.book_id.cmp(&b.book_id));
+            match order {
+                SortOrder::Asc => by_key,
+                SortOrder::Desc => by_key.reverse(),
+            }
+        };
+
+        Ok(self.read(|s| {
+            let mut books: Vec<&Book> = s
+                .books
+                .iter()
+                .filter(|b| State::matches(b, query))
+                .collect();
+            let total = books.len() as i64;
+            books.sort_by(|a, b| ordered(a, b));
+
+            let page = books
+                .into_iter()
+                .filter(|b| match after {
+                    None => true,
+                    Some(c) => {
+                        let position =
+                            compare_keys(&sort.key(b), &c.key).then(b.book_id.cmp(&c.id));
+                        match order {
+                            SortOrder::Asc => position == Ordering::Greater,
+                            SortOrder::Desc => position == Ordering::Less,
+                        }
+                    }
+                })
+                .skip(offset as usize)
+                .take(limit as usize)
+                .cloned()
+                .collect();
+            (page, total)
+        }))
+    }
+
+    async fn find_book(&self, book_id: i32) -> Result<Option<Book>, ApiError> {
+        Ok(self.read(|s| s.book(book_id).ok().cloned()))
+    }
+
+    async fn update_book(
+        &self,
+        book_id: i32,
+        expected_version: Option<i32>,
+        changes: &UpdateBookRequest,
+        now: NaiveDateTime,
+        holds: &HoldPolicy,
+    ) -> Result<Book, ApiError> {
+        self.transaction(|s| {
+            let book = s.book_mut(book_id)?;
+            if expected_version.is_some_and(|v| v != book.version) {
+                return Err(ApiError::VersionMismatch { book_id: None });
+            }
+
+            book.version += 1;
+            if let Some(name) = &changes.name {
+                book.name = name.clone();
+            }
+            if let Some(author) = &changes.author {
+                book.author = author.clone();
+            }
+            if let Some(copies) = changes.number_of_copies {
+                book.number_of_copies = copies;
+            }
+            if let Some(year) = changes.publication_year {
+                book.publication_year = year;
+            }
+            if let Some(edition) = &changes.edition {
+                book.edition = edition.clone();
+            }
+            if let Some(max_renewals) = changes.max_renewals {
+                book.max_renewals = max_renewals;
+            }
+            if let Some(min_age) = changes.min_age {
+                book.min_age = min_age;
+            }
+
+            if changes.number_of_copies.is_some() {
+                s.serve_waiting(book_id, holds, now)?;
+            }
+            Ok(s.book(book_id)?.clone())
+        })
+    }
+
+    async fn update_stock(
+        &self,
+        updates: &[&StockUpdate],
+        now: NaiveDateTime,
+        holds: &HoldPolicy,
+    ) -> Result<Vec<Book>, ApiError> {
+        self.transaction(|s| {
+            let mut updated = Vec::with_capacity(updates.len());
+            for u in updates {
+                let book = s.book_mut(u.book_id)?;
+                if u.version.is_some_and(|v| v != book.version) {
+                    return Err(ApiError::VersionMismatch {
+                        book_id: Some(u.book_id),
+                    });
+                }
+                let copies = book.number_of_copies;
+                let target = stock_target(u, copies)?;
+                book.number_of_copies = target;
+                book.version += 1;
+
+                if target > copies {
+                    s.serve_waiting(u.book_id, holds, now)?;
+                }
+                updated.push(s.book(u.book_id)?.clone());
+            }
+            Ok(updated)
+        })
+    }
+
+    async fn add_book(&self, book: &AddBookRequest) -> Result<i32, ApiError> {
+        self.transaction(|s| {
+            let book_id = next_id(&s.books, |b| b.book_id);
+            s.books.push(Book {
+                book_id,
+                name: book.name.clone(),
+                author: book.author.clone(),
+                number_of_copies: book.number_of_copies,
+                publication_year: book.publication_year,
+                edition: book.edition.clone(),
+                max_renewals: book.max_renewals,
+                min_age: book.min_age,
+                version: 1,
+                archived_at: None,
+            });
+            Ok(book_id)
+        })
+    }
+
+    async fn archive_book(&self, book_id: i32, now: NaiveDateTime) -> Result<u64, ApiError> {
+        self.transaction(|s| {
+            if s.book(book_id)?.archived_at.is_some() {
+                return Err(ApiError::AlreadyArchived);
+            }
+            let copies_on_loan = s
+                .loans
+                .iter()
+                .filter(|l| l.book_id == book_id && l.actual_return.is_none())
+                .count() as i64;
+            if copies_on_loan > 0 {
+                return Err(ApiError::BookOnLoan { copies_on_loan });
+            }
+
+            let mut cancelled = 0;
+            let mut reserved = 0;
+            for hold in s
+                .holds
+                .iter_mut()
+                .filter(|h| h.book_id == book_id && is_active(h))
+            {
+                if hold.status == "ready" {
+                    reserved += 1;
+                }
+                hold.status = "cancelled".into();
+                cancelled += 1;
+            }
+
+            let book = s.book_mut(book_id)?;
+            book.number_of_copies += reserved;
+            book.archived_at = Some(now);
+            book.version += 1;
+            Ok(cancelled)
+        })
+    }
+
+    async fn restore_book(&self, book_id: i32) -> Result<Book, ApiError> {
+        self.transaction(|s| {
+            let book = s.book_mut(book_id)?;
+            if book.archived_at.is_none() {
+                return Err(ApiError::NotArchived);
+            }
+            book.archived_at = None;
+            book.version += 1;
+            Ok(book.clone())
+        })
+    }
+}
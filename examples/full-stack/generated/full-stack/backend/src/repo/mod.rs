@@ -0,0 +1,294 @@
+//! Storage behind the handlers. Handlers deal with HTTP (extraction, auth,
+//! ETags, response shapes); a `LibraryRepository` owns every read and write
+//! and enforces the rules that must hold atomically with them, such as the
+//! fines block on checkout or handing returned copies to waiting holds.
+
+#[cfg(test)]
+mod memory;
+#[macro_use]
+mod portable;
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[cfg(test)]
+pub use memory::MemoryRepository;
+pub use postgres::PgRepository;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteRepository;
+
+use chrono::NaiveDateTime;
+
+use crate::error::ApiError;
+use crate::fines::FinePolicy;
+use crate::holds::HoldPolicy;
+use crate::loans::LoanPolicy;
+use crate::models::*;
+use crate::notify::NotificationPolicy;
+use crate::pagination::Cursor;
+
+/// A member as registration creates it.
+#[derive(Debug, Clone)]
+pub struct NewMember {
+    pub name: String,
+    pub address: Option<String>,
+    pub age: i32,
+    pub email: String,
+    pub password_hash: String,
+}
+
+/// What closing a loan did besides closing it.
+#[derive(Debug, Clone, Copy)]
+pub struct ReturnOutcome {
+    pub ledger_id: i32,
+    pub incident_id: Option<i32>,
+    pub late_fee_cents: i32,
+    pub reserved_for_hold: Option<i32>,
+}
+
+/// Every operation is atomic: it either applies completely or, on error,
+/// leaves nothing behind. Failures are `ApiError`s so domain rules surface
+/// with the same codes whichever backend is in use.
+pub trait LibraryRepository: Send + Sync + 'static {
+    // ── Accounts ──
+
+    /// Fails with `EmailTaken` if the email is registered, ignoring case.
+    async fn create_member(&self, member: NewMember) -> Result<i32, ApiError>;
+    async fn find_member(&self, member_id: i32) -> Result<Option<Member>, ApiError>;
+    /// Applies the changes unless the member has been erased (`NotFound`).
+    /// Fails with `EmailTaken` if another member has the new email.
+    async fn update_member(
+        &self,
+        member_id: i32,
+        changes: &UpdateMemberRequest,
+    ) -> Result<Member, ApiError>;
+    /// Closes the account, refusing with `MemberHasLoans` while books are
+    /// out. Active holds are cancelled and reserved copies passed on.
+    /// Returns when the account was closed, which for an account that
+    /// already was is the original time.
+    async fn deactivate_member(
+        &self,
+        member_id: i32,
+        now: NaiveDateTime,
+        holds: &HoldPolicy,
+    ) -> Result<NaiveDateTime, ApiError>;
+    /// Deactivates the account as above, then overwrites the member's name,
+    /// address, age, email, password, block reason and incident notes.
+    /// Loans, fines and holds keep their rows, so statistics over them are
+    /// unchanged.
+    async fn erase_member(
+        &self,
+        member_id: i32,
+        now: NaiveDateTime,
+        holds: &HoldPolicy,
+    ) -> Result<(), ApiError>;
+    /// The member with every loan, fine, hold, incident and reminder, read
+    /// in one transaction.
+    async fn export_member(&self, member_id: i32) -> Result<MemberExport, ApiError>;
+    /// Suspends borrowing with `Some((reason, now))` or lifts the block with
+    /// `None`; returns the member as changed. Erased members are `NotFound`.
+    async fn set_member_block(
+        &self,
+        member_id: i32,
+        block: Option<(&str, NaiveDateTime)>,
+    ) -> Result<Member, ApiError>;
+    async fn find_librarian(&self, username: &str) -> Result<Option<Librarian>, ApiError>;
+    /// Creates the account unless the username already exists.
+    async fn ensure_librarian(&self, username: &str, password_hash: &str) -> Result<(), ApiError>;
+
+    // ── Loans ──
+
+    /// Lends one copy of each book, refusing the whole request if the account
+    /// is closed, fines are over the limit, `LoanPolicy::check_checkout`
+    /// objects or any title is missing, archived or out of stock. The policy
+    /// is checked before any copy is taken, in the same transaction, so
+    /// concurrent checkouts cannot overrun the limits together. A
+    /// member's own hold on a title is fulfilled, using its reserved copy
+    /// when it was ready. Returns the due date.
+    async fn checkout(
+        &self,
+        member_id: i32,
+        book_ids: &[i32],
+        now: NaiveDateTime,
+        fines: &FinePolicy,
+        loans: &LoanPolicy,
+    ) -> Result<NaiveDateTime, ApiError>;
+    /// Open loans with `book_name`, newest first.
+    async fn open_loans(&self, member_id: i32) -> Result<Vec<BorrowLedger>, ApiError>;
+    /// Extends the loan by one loan period; returns it as renewed.
+    async fn renew_loan(
+        &self,
+        member_id: i32,
+        ledger_id: i32,
+        now: NaiveDateTime,
+        loans: &LoanPolicy,
+    ) -> Result<BorrowLedger, ApiError>;
+    /// Closes the member's oldest open loan of the book, records an incident
+    /// for anything but a good return and charges any late fee.
+    async fn return_book(
+        &self,
+        member_id: i32,
+        request: &ReturnRequest,
+        now: NaiveDateTime,
+        fines: &FinePolicy,
+        holds: &HoldPolicy,
+    ) -> Result<ReturnOutcome, ApiError>;
+    /// Open and returned loans matching the filters, with `book_name`, of
+    /// one member or of everyone. Newest first, starting after the
+    /// `(borrow_date, id)` position `after` or skipping `offset` rows, at
+    /// most `limit` of them (all when `None`); plus how many match in total.
+    /// `now` decides which loans are overdue.
+    async fn list_loans(
+        &self,
+        member_id: Option<i32>,
+        query: &LoanQuery,
+        now: NaiveDateTime,
+        after: Option<(NaiveDateTime, i32)>,
+        offset: i64,
+        limit: Option<i64>,
+    ) -> Result<(Vec<BorrowLedger>, i64), ApiError>;
+
+    // ── Fines ──
+
+    /// Charges and payments, newest first.
+    async fn fines(&self, member_id: i32) -> Result<Vec<MemberFine>, ApiError>;
+    /// Records a payment; returns the balance left.
+    async fn pay_fine(
+        &self,
+        member_id: i32,
+        amount_cents: i32,
+        now: NaiveDateTime,
+    ) -> Result<i64, ApiError>;
+
+    // ── Holds ──
+
+    /// Refused, like checkout, for a closed account.
+    async fn place_hold(
+        &self,
+        member_id: i32,
+        book_id: i32,
+        now: NaiveDateTime,
+    ) -> Result<Hold, ApiError>;
+    /// Waiting and ready holds with `book_name` and, while waiting, the
+    /// position in the queue.
+    async fn active_holds(&self, member_id: i32) -> Result<Vec<Hold>, ApiError>;
+    async fn cancel_hold(
+        &self,
+        member_id: i32,
+        hold_id: i32,
+        now: NaiveDateTime,
+        holds: &HoldPolicy,
+    ) -> Result<(), ApiError>;
+    /// Expires ready holds past their pickup window and passes each copy on.
+    /// Returns how many expired.
+    async fn expire_holds(&self, now: NaiveDateTime, holds: &HoldPolicy) -> Result<u64, ApiError>;
+
+    // ── Notifications ──
+
+    /// Open loans of active members due before `now` plus the policy's
+    /// `remind_before` whose member has not been reminded of the current due
+    /// date: once before it, then every `overdue_repeat` while the loan is
+    /// overdue. Soonest due first.
+    async fn due_reminders(
+        &self,
+        now: NaiveDateTime,
+        policy: &NotificationPolicy,
+    ) -> Result<Vec<LoanReminder>, ApiError>;
+    /// Records the reminder as sent `kind` through `channel` before it goes
+    /// out, provided the loan still has that due date and nobody else has
+    /// claimed the reminder since `due_reminders` listed it. Returns the new
+    /// `notification_id`, or `None` when there is nothing left to send.
+    async fn claim_notification(
+        &self,
+        reminder: &LoanReminder,
+        kind: ReminderKind,
+        channel: &str,
+        now: NaiveDateTime,
+        policy: &NotificationPolicy,
+    ) -> Result<Option<i32>, ApiError>;
+    /// Gives up a claim whose reminder could not be delivered, so the next
+    /// sweep tries again.
+    async fn release_notification(&self, notification_id: i32) -> Result<(), ApiError>;
+
+    // ── Catalogue ──
+
+    /// Books matching the filters in `query`'s sort order, starting after
+    /// `after` or skipping `offset` rows, at most `limit` of them; plus how
+    /// many match in total.
+    async fn list_books(
+        &self,
+        query: &ListBooksQuery,
+        after: Option<&Cursor>,
+        offset: i64,
+        limit: i64,
+    ) -> Result<(Vec<Book>, i64), ApiError>;
+    async fn find_book(&self, book_id: i32) -> Result<Option<Book>, ApiError>;
+    /// Applies the changes if the book is still at `expected_version`
+    /// (`None` skips the check). Added copies go to waiting holds first.
+    async fn update_book(
+        &self,
+        book_id: i32,
+        expected_version: Option<i32>,
+        changes: &UpdateBookRequest,
+        now: NaiveDateTime,
+        holds: &HoldPolicy,
+    ) -> Result<Book, ApiError>;
+    /// Applies every stock change or none. `updates` are sorted by book and
+    /// each sets exactly one of `number_of_copies` and `adjust_by`.
+    async fn update_stock(
+        &self,
+        updates: &[&StockUpdate],
+        now: NaiveDateTime,
+        holds: &HoldPolicy,
+    ) -> Result<Vec<Book>, ApiError>;
+    async fn add_book(&self, book: &AddBookRequest) -> Result<i32, ApiError>;
+    /// Archives a book with no copies on loan and cancels its holds, putting
+    /// copies reserved for ready holds back on the shelf; returns how many
+    /// holds were cancelled.
+    async fn archive_book(&self, book_id: i32, now: NaiveDateTime) -> Result<u64, ApiError>;
+    async fn restore_book(&self, book_id: i32) -> Result<Book, ApiError>;
+
+    /// True once the backend has been shut down, so background jobs can
+    /// stop instead of failing on every tick.
+    fn is_closed(&self) -> bool {
+        false
+    }
+}
+
+/// What erasure leaves in place of a member's name.
+const ERASED_NAME: &str = "Erased member";
+
+/// What erasure leaves in place of a member's email: still unique, and under
+/// the reserved `.invalid` domain so nothing can ever be sent to it.
+fn erased_email(member_id: i32) -> String {
+    format!("erased-{}@erased.invalid", member_id)
+}
+
+/// The stock level a bulk update asks for, or `NegativeStock` /
+/// `StockAboveLimit` if it would leave the range `AddBookRequest` allows.
+fn stock_target(update: &StockUpdate, copies: i32) -> Result<i32, ApiError> {
+    let target = match (update.number_of_copies, update.adjust_by) {
+        (Some(n), None) => n,
+        (None, Some(delta)) => copies.saturating_add(delta),
+        _ => {
+            return Err(ApiError::BadRequest(format!(
+                "update for book {} needs exactly one of number_of_copies or adjust_by",
+                update.book_id
+            )))
+        }
+    };
+    if target < 0 {
+        return Err(ApiError::NegativeStock {
+            book_id: update.book_id,
+            number_of_copies: copies,
+        });
+    }
+    if target > MAX_COPIES {
+        return Err(ApiError::StockAboveLimit {
+            book_id: update.book_id,
+            number_of_copies: copies,
+            max_copies: MAX_COPIES,
+        });
+    }
+    Ok(target)
+}
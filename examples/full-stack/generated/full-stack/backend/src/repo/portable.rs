@@ -0,0 +1,1436 @@
+//! The repository written once for Postgres and SQLite. Statements are
+//! Postgres SQL that SQLite also accepts; the row locks in them are what
+//! keeps concurrent requests on Postgres from racing, and SQLite, whose pool
+//! has one connection, runs them without.
+
+use chrono::NaiveDateTime;
+
+/// Columns of `Book`, for the statements assembled at runtime.
+pub(super) const BOOK_COLUMNS: &str =
+    "book_id, name, author, number_of_copies, publication_year, edition, max_renewals, min_age, version, archived_at";
+
+/// What a checkout judges a member on.
+#[derive(sqlx::FromRow)]
+pub(super) struct MemberStanding {
+    pub age: Option<i32>,
+    pub deactivated_at: Option<NaiveDateTime>,
+    pub blocked_at: Option<NaiveDateTime>,
+    pub blocked_reason: Option<String>,
+}
+
+/// An active hold, read before it is closed.
+#[derive(sqlx::FromRow)]
+pub(super) struct HoldState {
+    pub hold_id: i32,
+    pub book_id: i32,
+    pub status: String,
+}
+
+#[derive(sqlx::FromRow)]
+pub(super) struct ClosedLoan {
+    pub id: i32,
+    pub expected_return: NaiveDateTime,
+}
+
+#[derive(sqlx::FromRow)]
+pub(super) struct StockLevel {
+    pub number_of_copies: i32,
+    pub version: i32,
+}
+
+/// A `LIKE ... ESCAPE '\'` pattern matching `text` anywhere, with `%` and `_`
+/// in it taken literally.
+pub(super) fn contains_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len() + 2);
+    pattern.push('%');
+    for c in text.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// The shared helpers and the `LibraryRepository` impl for `$repo`, a struct
+/// with a `pool` of `$db`. The expanding module supplies `sql!`,
+/// `sql_scalar!` and `sql_as!` to prepare a statement, `push_search` for the
+/// `q` filter and `begin_snapshot` for the export.
+macro_rules! portable_repository {
+    ($repo:ident, $db:ty, $conn:ty) => {
+        /// Outstanding amount a member owes: charges minus payments.
+        #[tracing::instrument(skip(conn))]
+        async fn balance_cents(conn: &mut $conn, member_id: i32) -> Result<i64, sqlx::Error> {
+            sql_scalar!(
+                i64,
+                r#"SELECT CAST(COALESCE(SUM(amount_cents), 0) AS BIGINT) AS "balance!" FROM member_fines WHERE member_id = $1"#,
+                member_id
+            )
+            .fetch_one(conn)
+            .await
+        }
+
+        /// Marks the longest-waiting hold on the book ready for pickup. Returns
+        /// the hold, or `None` when nobody is waiting.
+        async fn promote_next(
+            conn: &mut $conn,
+            book_id: i32,
+            policy: &HoldPolicy,
+            now: NaiveDateTime,
+        ) -> Result<Option<i32>, sqlx::Error> {
+            sql_scalar!(
+                i32,
+                r#"UPDATE holds SET status = 'ready', ready_at = $2, expires_at = $3
+                   WHERE hold_id = (
+                       SELECT hold_id FROM holds
+                       WHERE book_id = $1 AND status = 'waiting'
+                       ORDER BY placed_at, hold_id
+                       LIMIT 1
+                       FOR UPDATE
+                   )
+                   RETURNING hold_id"#,
+                book_id,
+                now,
+                now + policy.pickup_window
+            )
+            .fetch_optional(&mut *conn)
+            .await
+        }
+
+        /// Hands a copy that just came back to the longest-waiting hold on the
+        /// book, or puts it back on the shelf when nobody is waiting. Returns
+        /// the hold the copy was reserved for, if any.
+        #[tracing::instrument(skip(conn, policy, now))]
+        async fn release_copy(
+            conn: &mut $conn,
+            book_id: i32,
+            policy: &HoldPolicy,
+            now: NaiveDateTime,
+        ) -> Result<Option<i32>, sqlx::Error> {
+            let reserved = promote_next(conn, book_id, policy, now).await?;
+
+            if reserved.is_none() {
+                sql!(
+                    "UPDATE books SET number_of_copies = number_of_copies + 1 WHERE book_id = $1",
+                    book_id
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+            Ok(reserved)
+        }
+
+        /// Moves shelf copies to waiting holds, oldest first, until one or the
+        /// other runs out. Used when a librarian adds stock to a title people
+        /// are queued for. Returns how many copies were reserved.
+        #[tracing::instrument(skip(conn, policy, now))]
+        async fn serve_waiting(
+            conn: &mut $conn,
+            book_id: i32,
+            policy: &HoldPolicy,
+            now: NaiveDateTime,
+        ) -> Result<i32, sqlx::Error> {
+            let mut served = 0;
+            loop {
+                let taken = sql!(
+                    r#"UPDATE books SET number_of_copies = number_of_copies - 1
+                       WHERE book_id = $1 AND number_of_copies > 0
+                         AND EXISTS (SELECT 1 FROM holds WHERE book_id = $1 AND status = 'waiting')"#,
+                    book_id
+                )
+                .execute(&mut *conn)
+                .await?;
+                if taken.rows_affected() == 0 {
+                    return Ok(served);
+                }
+                promote_next(conn, book_id, policy, now).await?;
+                served += 1;
+            }
+        }
+
+        /// Refuses members who closed their account. The share lock keeps the
+        /// account from being closed until the caller's transaction ends.
+        async fn ensure_active(conn: &mut $conn, member_id: i32) -> Result<(), ApiError> {
+            let deactivated_at = sql_scalar!(
+                Option<NaiveDateTime>,
+                "SELECT deactivated_at FROM members WHERE member_id = $1 FOR SHARE",
+                member_id
+            )
+            .fetch_optional(conn)
+            .instrument(db_span("check_member"))
+            .await?
+            .ok_or(ApiError::NotFound)?;
+            match deactivated_at {
+                Some(_) => Err(ApiError::AccountDeactivated),
+                None => Ok(()),
+            }
+        }
+
+        /// Locks the member for a checkout and reads what the loan policy
+        /// judges them on. The row lock queues concurrent checkouts by the same
+        /// member, so each one counts the loans the other took.
+        async fn lock_borrower(conn: &mut $conn, member_id: i32) -> Result<Borrower, ApiError> {
+            let member = sql_as!(
+                MemberStanding,
+                "SELECT age, deactivated_at, blocked_at, blocked_reason FROM members WHERE member_id = $1 FOR UPDATE",
+                member_id
+            )
+            .fetch_optional(&mut *conn)
+            .instrument(db_span("lock_member"))
+            .await?
+            .ok_or(ApiError::NotFound)?;
+            if member.deactivated_at.is_some() {
+                return Err(ApiError::AccountDeactivated);
+            }
+
+            let open_loans = sql_scalar!(
+                i32,
+                "SELECT book_id FROM book_borrow_ledger WHERE member_id = $1 AND actual_return IS NULL",
+                member_id
+            )
+            .fetch_all(&mut *conn)
+            .instrument(db_span("select_open_loans"))
+            .await?;
+            Ok(Borrower {
+                age: member.age,
+                blocked: member.blocked_at.is_some(),
+                blocked_reason: member.blocked_reason,
+                open_loans,
+            })
+        }
+
+        /// Deactivates the member unless books are still out, cancelling their
+        /// active holds and passing reserved copies on. Returns when the account
+        /// was closed.
+        async fn close_account(
+            conn: &mut $conn,
+            member_id: i32,
+            policy: &HoldPolicy,
+            now: NaiveDateTime,
+        ) -> Result<NaiveDateTime, ApiError> {
+            sql_scalar!(i32, "SELECT member_id FROM members WHERE member_id = $1 FOR UPDATE", member_id)
+                .fetch_optional(&mut *conn)
+                .instrument(db_span("lock_member"))
+                .await?
+                .ok_or(ApiError::NotFound)?;
+
+            let open_loans = sql_scalar!(
+                i64,
+                r#"SELECT COUNT(*) AS "count!" FROM book_borrow_ledger WHERE member_id = $1 AND actual_return IS NULL"#,
+                member_id
+            )
+            .fetch_one(&mut *conn)
+            .instrument(db_span("count_open_loans"))
+            .await?;
+            if open_loans > 0 {
+                return Err(ApiError::MemberHasLoans { open_loans });
+            }
+
+            // Locked first, so none turns ready between this read and the update.
+            let active = sql_as!(
+                HoldState,
+                "SELECT hold_id, book_id, status FROM holds WHERE member_id = $1 AND status IN ('waiting', 'ready') FOR UPDATE",
+                member_id
+            )
+            .fetch_all(&mut *conn)
+            .instrument(db_span("lock_member_holds"))
+            .await?;
+            sql!(
+                "UPDATE holds SET status = 'cancelled' WHERE member_id = $1 AND status IN ('waiting', 'ready')",
+                member_id
+            )
+            .execute(&mut *conn)
+            .instrument(db_span("cancel_member_holds"))
+            .await?;
+            for hold in active.iter().filter(|h| h.status == "ready") {
+                release_copy(conn, hold.book_id, policy, now).await?;
+            }
+
+            let closed_at = sql_scalar!(
+                NaiveDateTime,
+                r#"UPDATE members SET deactivated_at = COALESCE(deactivated_at, $2) WHERE member_id = $1
+                   RETURNING deactivated_at AS "deactivated_at!""#,
+                member_id,
+                now
+            )
+            .fetch_one(&mut *conn)
+            .instrument(db_span("deactivate_member"))
+            .await?;
+            Ok(closed_at)
+        }
+
+        async fn book_exists(conn: &mut $conn, book_id: i32) -> Result<bool, sqlx::Error> {
+            sql_scalar!(
+                bool,
+                r#"SELECT EXISTS (SELECT 1 FROM books WHERE book_id = $1) AS "exists!""#,
+                book_id
+            )
+            .fetch_one(conn)
+            .instrument(db_span("book_exists"))
+            .await
+        }
+
+        fn push_book_filters(qb: &mut QueryBuilder<'_, $db>, query: &ListBooksQuery) {
+            if !query.include_archived {
+                qb.push(" AND archived_at IS NULL");
+            }
+            if let Some(q) = query.q.as_deref().filter(|q| !q.trim().is_empty()) {
+                push_search(qb, q);
+            }
+            if let Some(author) = query.author.as_deref().filter(|a| !a.trim().is_empty()) {
+                qb.push(" AND LOWER(author) LIKE ")
+                    .push_bind(contains_pattern(&author.to_lowercase()))
+                    .push(" ESCAPE '\\'");
+            }
+            if let Some(year) = query.publication_year {
+                qb.push(" AND publication_year = ").push_bind(year);
+            }
+        }
+
+        fn push_loan_filters(
+            qb: &mut QueryBuilder<'_, $db>,
+            member_id: Option<i32>,
+            query: &LoanQuery,
+            now: NaiveDateTime,
+        ) {
+            if let Some(member_id) = member_id {
+                qb.push(" AND l.member_id = ").push_bind(member_id);
+            }
+            if let Some(book_id) = query.book_id {
+                qb.push(" AND l.book_id = ").push_bind(book_id);
+            }
+            let (start, end) = query.borrowed_between();
+            if let Some(start) = start {
+                qb.push(" AND l.borrow_date >= ").push_bind(start);
+            }
+            if let Some(end) = end {
+                qb.push(" AND l.borrow_date < ").push_bind(end);
+            }
+            if let Some(overdue) = query.overdue {
+                qb.push(if overdue { " AND " } else { " AND NOT " })
+                    .push("(l.actual_return IS NULL AND l.expected_return < ")
+                    .push_bind(now)
+                    .push(")");
+            }
+            if let Some(condition) = query.condition {
+                qb.push(" AND l.return_condition = ").push_bind(condition.as_str());
+            }
+        }
+
+        impl LibraryRepository for $repo {
+            // ── Accounts ──
+
+            async fn create_member(&self, member: NewMember) -> Result<i32, ApiError> {
+                sql_scalar!(
+                    i32,
+                    "INSERT INTO members (name, address, age, email, password_hash) VALUES ($1, $2, $3, $4, $5) RETURNING member_id",
+                    member.name,
+                    member.address,
+                    member.age,
+                    member.email,
+                    member.password_hash
+                )
+                .fetch_one(&self.pool)
+                .instrument(db_span("insert_member"))
+                .await
+                .map_err(|e| match ApiError::from(e) {
+                    ApiError::Duplicate => ApiError::EmailTaken,
+                    other => other,
+                })
+            }
+
+            async fn find_member(&self, member_id: i32) -> Result<Option<Member>, ApiError> {
+                let member = sql_as!(
+                    Member,
+                    r#"SELECT member_id, name, address, age, email, password_hash, deactivated_at, erased_at,
+                              blocked_at, blocked_reason
+                       FROM members WHERE member_id = $1"#,
+                    member_id
+                )
+                .fetch_optional(&self.pool)
+                .instrument(db_span("select_member"))
+                .await?;
+                Ok(member)
+            }
+
+            async fn update_member(
+                &self,
+                member_id: i32,
+                changes: &UpdateMemberRequest,
+            ) -> Result<Member, ApiError> {
+                let member = sql_as!(
+                    Member,
+                    r#"UPDATE members SET
+                           name = COALESCE($2, name),
+                           address = CASE WHEN $3 THEN $4 ELSE address END,
+                           age = COALESCE($5, age),
+                           email = COALESCE($6, email)
+                       WHERE member_id = $1 AND erased_at IS NULL
+                       RETURNING member_id, name, address, age, email, password_hash, deactivated_at, erased_at,
+                                 blocked_at, blocked_reason"#,
+                    member_id,
+                    changes.name,
+                    changes.address.is_some(),
+                    changes.address.clone().flatten(),
+                    changes.age,
+                    changes.email
+                )
+                .fetch_optional(&self.pool)
+                .instrument(db_span("update_member"))
+                .await
+                .map_err(|e| match ApiError::from(e) {
+                    ApiError::Duplicate => ApiError::EmailTaken,
+                    other => other,
+                })?;
+                member.ok_or(ApiError::NotFound)
+            }
+
+            async fn deactivate_member(
+                &self,
+                member_id: i32,
+                now: NaiveDateTime,
+                holds: &HoldPolicy,
+            ) -> Result<NaiveDateTime, ApiError> {
+                let mut tx = self.pool.begin().instrument(db_span("begin")).await?;
+                let closed_at = close_account(&mut tx, member_id, holds, now).await?;
+                tx.commit().instrument(db_span("commit")).await?;
+                Ok(closed_at)
+            }
+
+            async fn erase_member(
+                &self,
+                member_id: i32,
+                now: NaiveDateTime,
+                holds: &HoldPolicy,
+            ) -> Result<(), ApiError> {
+                let mut tx = self.pool.begin().instrument(db_span("begin")).await?;
+                close_account(&mut tx, member_id, holds, now).await?;
+
+                sql!(
+                    r#"UPDATE members SET name = $2, address = NULL, age = NULL, email = $3,
+                           password_hash = NULL, blocked_reason = NULL, erased_at = COALESCE(erased_at, $4)
+                       WHERE member_id = $1"#,
+                    member_id,
+                    ERASED_NAME,
+                    erased_email(member_id),
+                    now
+                )
+                .execute(&mut *tx)
+                .instrument(db_span("erase_member"))
+                .await?;
+                sql!("UPDATE loan_incidents SET notes = NULL WHERE member_id = $1", member_id)
+                    .execute(&mut *tx)
+                    .instrument(db_span("erase_incident_notes"))
+                    .await?;
+
+                tx.commit().instrument(db_span("commit")).await?;
+                Ok(())
+            }
+
+            async fn export_member(&self, member_id: i32) -> Result<MemberExport, ApiError> {
+                // One snapshot, so the parts agree with each other.
+                let mut tx = self.pool.begin().instrument(db_span("begin")).await?;
+                begin_snapshot(&mut tx).await?;
+
+                let member = sql_as!(
+                    Member,
+                    r#"SELECT member_id, name, address, age, email, password_hash, deactivated_at, erased_at,
+                              blocked_at, blocked_reason
+                       FROM members WHERE member_id = $1"#,
+                    member_id
+                )
+                .fetch_optional(&mut *tx)
+                .instrument(db_span("select_member"))
+                .await?
+                .ok_or(ApiError::NotFound)?;
+                let loans = sql_as!(
+                    BorrowLedger,
+                    r#"SELECT l.id, l.book_id, l.member_id, l.borrow_date, l.expected_return,
+                              l.actual_return, l.return_condition, l.renewal_count, l.max_renewals,
+                              b.name AS "book_name?"
+                       FROM book_borrow_ledger l
+                       JOIN books b ON b.book_id = l.book_id
+                       WHERE l.member_id = $1
+                       ORDER BY l.borrow_date DESC, l.id DESC"#,
+                    member_id
+                )
+                .fetch_all(&mut *tx)
+                .instrument(db_span("select_loans"))
+                .await?;
+                let fines = sql_as!(
+                    MemberFine,
+                    r#"SELECT fine_id, member_id, ledger_id, kind, amount_cents, description, created_at
+                       FROM member_fines
+                       WHERE member_id = $1
+                       ORDER BY created_at DESC, fine_id DESC"#,
+                    member_id
+                )
+                .fetch_all(&mut *tx)
+                .instrument(db_span("select_fines"))
+                .await?;
+                let holds = sql_as!(
+                    Hold,
+                    r#"SELECT h.hold_id, h.book_id, h.member_id, h.status, h.placed_at, h.ready_at, h.expires_at,
+                              b.name AS "book_name?", CAST(NULL AS BIGINT) AS "queue_position?"
+                       FROM holds h
+                       JOIN books b ON b.book_id = h.book_id
+                       WHERE h.member_id = $1
+                       ORDER BY h.placed_at DESC, h.hold_id DESC"#,
+                    member_id
+                )
+                .fetch_all(&mut *tx)
+                .instrument(db_span("select_holds"))
+                .await?;
+                let incidents = sql_as!(
+                    LoanIncident,
+                    r#"SELECT incident_id, ledger_id, member_id, book_id, condition, notes, reported_at
+                       FROM loan_incidents
+                       WHERE member_id = $1
+                       ORDER BY reported_at DESC, incident_id DESC"#,
+                    member_id
+                )
+                .fetch_all(&mut *tx)
+                .instrument(db_span("select_incidents"))
+                .await?;
+                let notifications = sql_as!(
+                    LoanNotification,
+                    r#"SELECT notification_id, ledger_id, member_id, kind, channel, due_at, sent_at
+                       FROM loan_notifications
+                       WHERE member_id = $1
+                       ORDER BY sent_at DESC, notification_id DESC"#,
+                    member_id
+                )
+                .fetch_all(&mut *tx)
+                .instrument(db_span("select_notifications"))
+                .await?;
+                tx.commit().instrument(db_span("commit")).await?;
+
+                Ok(MemberExport {
+                    member,
+                    loans,
+                    fines,
+                    holds,
+                    incidents,
+                    notifications,
+                })
+            }
+
+            async fn set_member_block(
+                &self,
+                member_id: i32,
+                block: Option<(&str, NaiveDateTime)>,
+            ) -> Result<Member, ApiError> {
+                let (reason, blocked_at) = block.unzip();
+                let member = sql_as!(
+                    Member,
+                    r#"UPDATE members SET blocked_at = $2, blocked_reason = $3
+                       WHERE member_id = $1 AND erased_at IS NULL
+                       RETURNING member_id, name, address, age, email, password_hash, deactivated_at, erased_at,
+                                 blocked_at, blocked_reason"#,
+                    member_id,
+                    blocked_at,
+                    reason
+                )
+                .fetch_optional(&self.pool)
+                .instrument(db_span("block_member"))
+                .await?;
+                member.ok_or(ApiError::NotFound)
+            }
+
+            async fn find_librarian(&self, username: &str) -> Result<Option<Librarian>, ApiError> {
+                let librarian = sql_as!(
+                    Librarian,
+                    "SELECT librarian_id, username, password_hash FROM librarians WHERE username = $1",
+                    username
+                )
+                .fetch_optional(&self.pool)
+                .instrument(db_span("select_librarian"))
+                .await?;
+                Ok(librarian)
+            }
+
+            async fn ensure_librarian(&self, username: &str, password_hash: &str) -> Result<(), ApiError> {
+                crate::db::ensure_librarian(&self.pool, username, password_hash).await?;
+                Ok(())
+            }
+
+            // ── Loans ──
+
+            async fn checkout(
+                &self,
+                member_id: i32,
+                book_ids: &[i32],
+                now: NaiveDateTime,
+                fines: &FinePolicy,
+                loans: &LoanPolicy,
+            ) -> Result<NaiveDateTime, ApiError> {
+                let expected_return = now + loans.loan_period;
+
+                let mut tx = self.pool.begin().instrument(db_span("begin")).await?;
+
+                let borrower = lock_borrower(&mut tx, member_id).await?;
+                let balance = balance_cents(&mut tx, member_id).await?;
+                if fines.blocks_checkout(balance) {
+                    return Err(ApiError::FinesBlockCheckout {
+                        balance_cents: balance,
+                        limit_cents: fines.block_threshold_cents,
+                    });
+                }
+
+                let mut requested = Vec::with_capacity(book_ids.len());
+                for &book_id in book_ids {
+                    let min_age = sql_scalar!(
+                        Option<i32>,
+                        "SELECT min_age FROM books WHERE book_id = $1",
+                        book_id
+                    )
+                    .fetch_optional(&mut *tx)
+                    .instrument(db_span("select_min_age"))
+                    .await?
+                    .flatten();
+                    requested.push((book_id, min_age));
+                }
+                loans.check_checkout(&borrower, &requested)?;
+
+                // Lock rows in a fixed order so two multi-book checkouts cannot deadlock.
+                let mut book_ids = book_ids.to_vec();
+                book_ids.sort_unstable();
+
+                for book_id in book_ids {
+                    // One span per title, so every statement below carries its book_id.
+                    async {
+                        // Checking out closes any hold this member has on the book. A hold
+                        // that was `ready` already has a copy set aside for them.
+                        let held = sql_as!(
+                            HoldState,
+                            r#"SELECT hold_id, book_id, status FROM holds
+                               WHERE book_id = $1 AND member_id = $2 AND status IN ('waiting', 'ready')
+                               FOR UPDATE"#,
+                            book_id,
+                            member_id
+                        )
+                        .fetch_optional(&mut *tx)
+                        .instrument(db_span("lock_hold"))
+                        .await?;
+                        if let Some(hold) = &held {
+                            sql!("UPDATE holds SET status = 'fulfilled' WHERE hold_id = $1", hold.hold_id)
+                                .execute(&mut *tx)
+                                .instrument(db_span("fulfil_hold"))
+                                .await?;
+                        }
+                        let reserved = held.is_some_and(|h| h.status == "ready");
+
+                        if !reserved {
+                            // Decrement only while a copy is left; the row lock taken here makes
+                            // concurrent checkouts of the same book queue up behind each other.
+                            let decremented = sql_scalar!(
+                                i32,
+                                "UPDATE books SET number_of_copies = number_of_copies - 1 WHERE book_id = $1 AND number_of_copies > 0 AND archived_at IS NULL RETURNING book_id",
+                                book_id
+                            )
+                            .fetch_optional(&mut *tx)
+                            .instrument(db_span("take_copy"))
+                            .await?;
+
+                            if decremented.is_none() {
+                                let exists = sql_scalar!(
+                                    bool,
+                                    r#"SELECT EXISTS (SELECT 1 FROM books WHERE book_id = $1 AND archived_at IS NULL) AS "exists!""#,
+                                    book_id
+                                )
+                                .fetch_one(&mut *tx)
+                                .instrument(db_span("book_exists"))
+                                .await?;
+                                return Err(if exists {
+                                    ApiError::NoCopiesAvailable(book_id)
+                                } else {
+                                    ApiError::BookNotFound(book_id)
+                                });
+                            }
+                        }
+
+                        sql!(
+                            r#"INSERT INTO book_borrow_ledger (book_id, member_id, borrow_date, expected_return, max_renewals)
+                               SELECT $1, $2, $3, $4, COALESCE(max_renewals, $5) FROM books WHERE book_id = $1"#,
+                            book_id,
+                            member_id,
+                            now,
+                            expected_return,
+                            loans.max_renewals
+                        )
+                        .execute(&mut *tx)
+                        .instrument(db_span("insert_loan"))
+                        .await?;
+                        Ok::<_, ApiError>(())
+                    }
+                    .instrument(tracing::info_span!("checkout_book", book_id))
+                    .await?;
+                }
+
+                // Any error above drops `tx`, which rolls the whole checkout back.
+                tx.commit().instrument(db_span("commit")).await?;
+                Ok(expected_return)
+            }
+
+            async fn open_loans(&self, member_id: i32) -> Result<Vec<BorrowLedger>, ApiError> {
+                let loans = sql_as!(
+                    BorrowLedger,
+                    r#"SELECT l.id, l.book_id, l.member_id, l.borrow_date, l.expected_return,
+                              l.actual_return, l.return_condition, l.renewal_count, l.max_renewals,
+                              b.name AS "book_name?"
+                       FROM book_borrow_ledger l
+                       JOIN books b ON b.book_id = l.book_id
+                       WHERE l.member_id = $1 AND l.actual_return IS NULL
+                       ORDER BY l.borrow_date DESC"#,
+                    member_id
+                )
+                .fetch_all(&self.pool)
+                .instrument(db_span("select_open_loans"))
+                .await?;
+                Ok(loans)
+            }
+
+            async fn renew_loan(
+                &self,
+                member_id: i32,
+                ledger_id: i32,
+                now: NaiveDateTime,
+                loans: &LoanPolicy,
+            ) -> Result<BorrowLedger, ApiError> {
+                let mut tx = self.pool.begin().instrument(db_span("begin")).await?;
+
+                let mut loan = sql_as!(
+                    BorrowLedger,
+                    r#"SELECT l.id, l.book_id, l.member_id, l.borrow_date, l.expected_return,
+                              l.actual_return, l.return_condition, l.renewal_count, l.max_renewals,
+                              b.name AS "book_name?"
+                       FROM book_borrow_ledger l
+                       JOIN books b ON b.book_id = l.book_id
+                       WHERE l.id = $1 AND l.member_id = $2 AND l.actual_return IS NULL
+                       FOR UPDATE OF l"#,
+                    ledger_id,
+                    member_id
+                )
+                .fetch_optional(&mut *tx)
+                .instrument(db_span("lock_loan"))
+                .await?
+                .ok_or(ApiError::LoanNotFound)?;
+
+                if loan.renewal_count >= loan.max_renewals {
+                    return Err(ApiError::RenewalLimitReached {
+                        max_renewals: loan.max_renewals,
+                    });
+                }
+                // Renewing an overdue loan would quietly forgive its late fee.
+                if now > loan.expected_return {
+                    return Err(ApiError::LoanOverdue);
+                }
+
+                let waiting = sql_scalar!(
+                    bool,
+                    r#"SELECT EXISTS (SELECT 1 FROM holds WHERE book_id = $1 AND status = 'waiting') AS "waiting!""#,
+                    loan.book_id
+                )
+                .fetch_one(&mut *tx)
+                .instrument(db_span("holds_waiting"))
+                .await?;
+                if waiting {
+                    return Err(ApiError::HoldsWaiting);
+                }
+
+                loan.expected_return += loans.loan_period;
+                loan.renewal_count += 1;
+                sql!(
+                    "UPDATE book_borrow_ledger SET expected_return = $1, renewal_count = $2 WHERE id = $3",
+                    loan.expected_return,
+                    loan.renewal_count,
+                    ledger_id
+                )
+                .execute(&mut *tx)
+                .instrument(db_span("extend_loan"))
+                .await?;
+
+                tx.commit().instrument(db_span("commit")).await?;
+                Ok(loan)
+            }
+
+            async fn return_book(
+                &self,
+                member_id: i32,
+                request: &ReturnRequest,
+                now: NaiveDateTime,
+                fines: &FinePolicy,
+                holds: &HoldPolicy,
+            ) -> Result<ReturnOutcome, ApiError> {
+                let condition = request.condition;
+
+                let mut tx = self.pool.begin().instrument(db_span("begin")).await?;
+
+                // Close the oldest open loan of this book; a member may hold several copies.
+                let closed = sql_as!(
+                    ClosedLoan,
+                    r#"UPDATE book_borrow_ledger SET actual_return = $1, return_condition = $2
+                       WHERE id = (
+                           SELECT id FROM book_borrow_ledger
+                           WHERE book_id = $3 AND member_id = $4 AND actual_return IS NULL
+                           ORDER BY borrow_date, id
+                           LIMIT 1
+                           FOR UPDATE
+                       )
+                       RETURNING id, expected_return"#,
+                    now,
+                    condition.as_str(),
+                    request.book_id,
+                    member_id
+                )
+                .fetch_optional(&mut *tx)
+                .instrument(db_span("close_loan"))
+                .await?
+                .ok_or(ApiError::LoanNotFound)?;
+                let (ledger_id, due) = (closed.id, closed.expected_return);
+
+                // A lost copy never comes back; any other goes to the next hold or the shelf.
+                let mut reserved_for_hold = None;
+                if condition != ReturnCondition::Lost {
+                    reserved_for_hold = release_copy(&mut tx, request.book_id, holds, now).await?;
+                }
+
+                let mut incident_id = None;
+                if condition != ReturnCondition::Good {
+                    let id = sql_scalar!(
+                        i32,
+                        "INSERT INTO loan_incidents (ledger_id, member_id, book_id, condition, notes) VALUES ($1, $2, $3, $4, $5) RETURNING incident_id",
+                        ledger_id,
+                        member_id,
+                        request.book_id,
+                        condition.as_str(),
+                        request.notes
+                    )
+                    .fetch_one(&mut *tx)
+                    .instrument(db_span("insert_incident"))
+                    .await?;
+                    incident_id = Some(id);
+                }
+
+                let late_fee_cents = fines.late_fee_cents(due, now);
+                if late_fee_cents > 0 {
+                    sql!(
+                        "INSERT INTO member_fines (member_id, ledger_id, kind, amount_cents, description, created_at) VALUES ($1, $2, 'late_return', $3, $4, $5)",
+                        member_id,
+                        ledger_id,
+                        late_fee_cents,
+                        format!("Returned late; was due {}", due.format("%Y-%m-%d")),
+                        now
+                    )
+                    .execute(&mut *tx)
+                    .instrument(db_span("insert_late_fee"))
+                    .await?;
+                }
+
+                tx.commit().instrument(db_span("commit")).await?;
+                Ok(ReturnOutcome {
+                    ledger_id,
+                    incident_id,
+                    late_fee_cents,
+                    reserved_for_hold,
+                })
+            }
+
+            async fn list_loans(
+                &self,
+                member_id: Option<i32>,
+                query: &LoanQuery,
+                now: NaiveDateTime,
+                after: Option<(NaiveDateTime, i32)>,
+                offset: i64,
+                limit: Option<i64>,
+            ) -> Result<(Vec<BorrowLedger>, i64), ApiError> {
+                let mut count = QueryBuilder::new("SELECT COUNT(*) FROM book_borrow_ledger l WHERE TRUE");
+                push_loan_filters(&mut count, member_id, query, now);
+                let total = count
+                    .build_query_scalar::<i64>()
+                    .fetch_one(&self.pool)
+                    .instrument(db_span("count_loans"))
+                    .await?;
+
+                let mut select = QueryBuilder::new(
+                    "SELECT l.id, l.book_id, l.member_id, l.borrow_date, l.expected_return, \
+                            l.actual_return, l.return_condition, l.renewal_count, l.max_renewals, \
+                            b.name AS book_name \
+                     FROM book_borrow_ledger l JOIN books b ON b.book_id = l.book_id WHERE TRUE",
+                );
+                push_loan_filters(&mut select, member_id, query, now);
+                if let Some((borrow_date, id)) = after {
+                    select
+                        .push(" AND (l.borrow_date, l.id) < (")
+                        .push_bind(borrow_date)
+                        .push(", ")
+                        .push_bind(id)
+                        .push(")");
+                }
+                // SQLite only accepts OFFSET after a LIMIT, so "all" is the largest one.
+                select
+                    .push(" ORDER BY l.borrow_date DESC, l.id DESC LIMIT ")
+                    .push_bind(limit.unwrap_or(i64::MAX));
+                if offset > 0 {
+                    select.push(" OFFSET ").push_bind(offset);
+                }
+
+                let loans = select
+                    .build_query_as::<BorrowLedger>()
+                    .fetch_all(&self.pool)
+                    .instrument(db_span("select_loans"))
+                    .await?;
+                Ok((loans, total))
+            }
+
+            // ── Fines ──
+
+            async fn fines(&self, member_id: i32) -> Result<Vec<MemberFine>, ApiError> {
+                let entries = sql_as!(
+                    MemberFine,
+                    r#"SELECT fine_id, member_id, ledger_id, kind, amount_cents, description, created_at
+                       FROM member_fines
+                       WHERE member_id = $1
+                       ORDER BY created_at DESC, fine_id DESC"#,
+                    member_id
+                )
+                .fetch_all(&self.pool)
+                .instrument(db_span("select_fines"))
+                .await?;
+                Ok(entries)
+            }
+
+            async fn pay_fine(
+                &self,
+                member_id: i32,
+                amount_cents: i32,
+                now: NaiveDateTime,
+            ) -> Result<i64, ApiError> {
+                let mut tx = self.pool.begin().instrument(db_span("begin")).await?;
+
+                // Serialise payments per member so two at once cannot both pass the balance check.
+                sql_scalar!(i32, "SELECT member_id FROM members WHERE member_id = $1 FOR UPDATE", member_id)
+                    .fetch_optional(&mut *tx)
+                    .instrument(db_span("lock_member"))
+                    .await?;
+
+                let balance = balance_cents(&mut tx, member_id).await?;
+                if i64::from(amount_cents) > balance {
+                    return Err(ApiError::PaymentExceedsBalance {
+                        balance_cents: balance,
+                    });
+                }
+
+                sql!(
+                    "INSERT INTO member_fines (member_id, kind, amount_cents, description, created_at) VALUES ($1, 'payment', $2, 'Payment received', $3)",
+                    member_id,
+                    -amount_cents,
+                    now
+                )
+                .execute(&mut *tx)
+                .instrument(db_span("insert_payment"))
+                .await?;
+
+                tx.commit().instrument(db_span("commit")).await?;
+                Ok(balance - i64::from(amount_cents))
+            }
+
+            // ── Holds ──
+
+            async fn place_hold(
+                &self,
+                member_id: i32,
+                book_id: i32,
+                now: NaiveDateTime,
+            ) -> Result<Hold, ApiError> {
+                let mut tx = self.pool.begin().instrument(db_span("begin")).await?;
+                ensure_active(&mut tx, member_id).await?;
+
+                let copies = sql_scalar!(
+                    i32,
+                    "SELECT number_of_copies FROM books WHERE book_id = $1 AND archived_at IS NULL",
+                    book_id
+                )
+                .fetch_optional(&mut *tx)
+                .instrument(db_span("select_copies"))
+                .await?
+                .ok_or(ApiError::BookNotFound(book_id))?;
+                if copies > 0 {
+                    return Err(ApiError::CopiesAvailable);
+                }
+
+                let hold = sql_as!(
+                    Hold,
+                    r#"INSERT INTO holds (book_id, member_id, placed_at) VALUES ($1, $2, $3)
+                       RETURNING hold_id, book_id, member_id, status, placed_at, ready_at, expires_at,
+                                 CAST(NULL AS TEXT) AS "book_name?", CAST(NULL AS BIGINT) AS "queue_position?""#,
+                    book_id,
+                    member_id,
+                    now
+                )
+                .fetch_one(&mut *tx)
+                .instrument(db_span("insert_hold"))
+                .await
+                .map_err(|e| match ApiError::from(e) {
+                    ApiError::Duplicate => ApiError::HoldAlreadyActive,
+                    other => other,
+                })?;
+
+                tx.commit().instrument(db_span("commit")).await?;
+                Ok(hold)
+            }
+
+            async fn active_holds(&self, member_id: i32) -> Result<Vec<Hold>, ApiError> {
+                let holds = sql_as!(
+                    Hold,
+                    r#"SELECT h.hold_id, h.book_id, h.member_id, h.status, h.placed_at, h.ready_at, h.expires_at,
+                              b.name AS "book_name?",
+                              CASE WHEN h.status = 'waiting' THEN (
+                                  SELECT COUNT(*) + 1 FROM holds q
+                                  WHERE q.book_id = h.book_id AND q.status = 'waiting'
+                                    AND (q.placed_at, q.hold_id) < (h.placed_at, h.hold_id)
+                              ) END AS "queue_position?"
+                       FROM holds h
+                       JOIN books b ON b.book_id = h.book_id
+                       WHERE h.member_id = $1 AND h.status IN ('waiting', 'ready')
+                       ORDER BY h.placed_at"#,
+                    member_id
+                )
+                .fetch_all(&self.pool)
+                .instrument(db_span("select_holds"))
+                .await?;
+                Ok(holds)
+            }
+
+            async fn cancel_hold(
+                &self,
+                member_id: i32,
+                hold_id: i32,
+                now: NaiveDateTime,
+                holds: &HoldPolicy,
+            ) -> Result<(), ApiError> {
+                let mut tx = self.pool.begin().instrument(db_span("begin")).await?;
+
+                let hold = sql_as!(
+                    HoldState,
+                    r#"SELECT hold_id, book_id, status FROM holds
+                       WHERE hold_id = $1 AND member_id = $2 AND status IN ('waiting', 'ready')
+                       FOR UPDATE"#,
+                    hold_id,
+                    member_id
+                )
+                .fetch_optional(&mut *tx)
+                .instrument(db_span("lock_hold"))
+                .await?
+                .ok_or(ApiError::HoldNotFound)?;
+                sql!("UPDATE holds SET status = 'cancelled' WHERE hold_id = $1", hold_id)
+                    .execute(&mut *tx)
+                    .instrument(db_span("cancel_hold"))
+                    .await?;
+
+                // Giving up a reserved copy passes it to whoever is next.
+                if hold.status == "ready" {
+                    release_copy(&mut tx, hold.book_id, holds, now).await?;
+                }
+
+                tx.commit().instrument(db_span("commit")).await?;
+                Ok(())
+            }
+
+            #[tracing::instrument(skip_all)]
+            async fn expire_holds(&self, now: NaiveDateTime, holds: &HoldPolicy) -> Result<u64, ApiError> {
+                let mut tx = self.pool.begin().await?;
+
+                let expired = sql_scalar!(
+                    i32,
+                    r#"UPDATE holds SET status = 'expired'
+                       WHERE status = 'ready' AND expires_at < $1
+                       RETURNING book_id"#,
+                    now
+                )
+                .fetch_all(&mut *tx)
+                .await?;
+
+                for &book_id in &expired {
+                    release_copy(&mut tx, book_id, holds, now).await?;
+                }
+
+                tx.commit().await?;
+                Ok(expired.len() as u64)
+            }
+
+            // ── Notifications ──
+
+            async fn due_reminders(
+                &self,
+                now: NaiveDateTime,
+                policy: &NotificationPolicy,
+            ) -> Result<Vec<LoanReminder>, ApiError> {
+                let reminders = sql_as!(
+                    LoanReminder,
+                    r#"SELECT l.id AS ledger_id, l.member_id, m.name AS "member_name!", m.email AS "email!",
+                              l.book_id, b.name AS "book_name!", l.expected_return
+                       FROM book_borrow_ledger l
+                       JOIN members m ON m.member_id = l.member_id
+                       JOIN books b ON b.book_id = l.book_id
+                       WHERE l.actual_return IS NULL
+                         AND m.deactivated_at IS NULL
+                         AND l.expected_return < $2
+                         AND NOT EXISTS (
+                             SELECT 1 FROM loan_notifications n
+                             WHERE n.ledger_id = l.id
+                               AND n.due_at = l.expected_return
+                               AND CASE WHEN l.expected_return < $1
+                                        THEN n.kind = 'overdue' AND n.sent_at > $3
+                                        ELSE n.kind = 'due_soon' END)
+                       ORDER BY l.expected_return, l.id"#,
+                    now,
+                    now + policy.remind_before,
+                    now - policy.overdue_repeat
+                )
+                .fetch_all(&self.pool)
+                .instrument(db_span("select_due_reminders"))
+                .await?;
+                Ok(reminders)
+            }
+
+            async fn claim_notification(
+                &self,
+                reminder: &LoanReminder,
+                kind: ReminderKind,
+                channel: &str,
+                now: NaiveDateTime,
+                policy: &NotificationPolicy,
+            ) -> Result<Option<i32>, ApiError> {
+                // Re-checks what `due_reminders` saw. Two sweeps that both pass the
+                // check compute the same ordinal, and the unique key lets one win.
+                let claimed = sql_scalar!(
+                    i32,
+                    r#"INSERT INTO loan_notifications (ledger_id, member_id, kind, channel, due_at, sent_at, ordinal)
+                       SELECT l.id, l.member_id, $2, $3, l.expected_return, $4,
+                              CAST((SELECT COUNT(*) FROM loan_notifications n
+                                    WHERE n.ledger_id = l.id AND n.kind = $2 AND n.due_at = l.expected_return) AS INTEGER)
+                       FROM book_borrow_ledger l
+                       WHERE l.id = $1
+                         AND l.actual_return IS NULL
+                         AND l.expected_return = $5
+                         AND NOT EXISTS (
+                             SELECT 1 FROM loan_notifications n
+                             WHERE n.ledger_id = l.id
+                               AND n.due_at = l.expected_return
+                               AND n.kind = $2
+                               AND (n.kind = 'due_soon' OR n.sent_at > $6))
+                       ON CONFLICT (ledger_id, kind, due_at, ordinal) DO NOTHING
+                       RETURNING notification_id"#,
+                    reminder.ledger_id,
+                    kind.as_str(),
+                    channel,
+                    now,
+                    reminder.expected_return,
+                    now - policy.overdue_repeat
+                )
+                .fetch_optional(&self.pool)
+                .instrument(db_span("claim_notification"))
+                .await?;
+                Ok(claimed)
+            }
+
+            async fn release_notification(&self, notification_id: i32) -> Result<(), ApiError> {
+                sql!("DELETE FROM loan_notifications WHERE notification_id = $1", notification_id)
+                    .execute(&self.pool)
+                    .instrument(db_span("release_notification"))
+                    .await?;
+                Ok(())
+            }
+
+            // ── Catalogue ──
+
+            async fn list_books(
+                &self,
+                query: &ListBooksQuery,
+                after: Option<&Cursor>,
+                offset: i64,
+                limit: i64,
+            ) -> Result<(Vec<Book>, i64), ApiError> {
+                let (sort, order) = (query.sort, query.order);
+
+                let mut count = QueryBuilder::new("SELECT COUNT(*) FROM books WHERE TRUE");
+                push_book_filters(&mut count, query);
+                let total = count
+                    .build_query_scalar::<i64>()
+                    .fetch_one(&self.pool)
+                    .instrument(db_span("count_books"))
+                    .await?;
+
+                let mut select =
+                    QueryBuilder::new(format!("SELECT {} FROM books WHERE TRUE", BOOK_COLUMNS));
+                push_book_filters(&mut select, query);
+                if let Some(c) = after {
+                    select.push(format!(
+                        " AND ({}, book_id) {} (",
+                        sort.expr(),
+                        order.after()
+                    ));
+                    match &c.key {
+                        SortKey::Int(v) => select.push_bind(*v),
+                        SortKey::Text(v) => select.push_bind(v.clone()),
+                    };
+                    select.push(", ").push_bind(c.id).push(")");
+                }
+                select.push(format!(
+                    " ORDER BY {expr} {dir}, book_id {dir}",
+                    expr = sort.expr(),
+                    dir = order.sql()
+                ));
+                select.push(" LIMIT ").push_bind(limit);
+                if offset > 0 {
+                    select.push(" OFFSET ").push_bind(offset);
+                }
+
+                let books = select
+                    .build_query_as::<Book>()
+                    .fetch_all(&self.pool)
+                    .instrument(db_span("select_books"))
+                    .await?;
+                Ok((books, total))
+            }
+
+            async fn find_book(&self, book_id: i32) -> Result<Option<Book>, ApiError> {
+                let book = sql_as!(
+                    Book,
+                    r#"SELECT book_id, name, author, number_of_copies, publication_year, edition, max_renewals,
+                              min_age, version, archived_at
+                       FROM books WHERE book_id = $1"#,
+                    book_id
+                )
+                .fetch_optional(&self.pool)
+                .instrument(db_span("select_book"))
+                .await?;
+                Ok(book)
+            }
+
+            async fn update_book(
+                &self,
+                book_id: i32,
+                expected_version: Option<i32>,
+                changes: &UpdateBookRequest,
+                now: NaiveDateTime,
+                holds: &HoldPolicy,
+            ) -> Result<Book, ApiError> {
+                let mut tx = self.pool.begin().instrument(db_span("begin")).await?;
+
+                let mut update = QueryBuilder::<$db>::new("UPDATE books SET version = version + 1");
+                if let Some(name) = &changes.name {
+                    update.push(", name = ").push_bind(name.clone());
+                }
+                if let Some(author) = &changes.author {
+                    update.push(", author = ").push_bind(author.clone());
+                }
+                if let Some(copies) = changes.number_of_copies {
+                    update.push(", number_of_copies = ").push_bind(copies);
+                }
+                if let Some(year) = changes.publication_year {
+                    update.push(", publication_year = ").push_bind(year);
+                }
+                if let Some(edition) = &changes.edition {
+                    update.push(", edition = ").push_bind(edition.clone());
+                }
+                if let Some(max_renewals) = changes.max_renewals {
+                    update.push(", max_renewals = ").push_bind(max_renewals);
+                }
+                if let Some(min_age) = changes.min_age {
+                    update.push(", min_age = ").push_bind(min_age);
+                }
+                update.push(" WHERE book_id = ").push_bind(book_id);
+                if let Some(version) = expected_version {
+                    update.push(" AND version = ").push_bind(version);
+                }
+                update.push(format!(" RETURNING {}", BOOK_COLUMNS));
+
+                let Some(mut book) = update
+                    .build_query_as::<Book>()
+                    .fetch_optional(&mut *tx)
+                    .instrument(db_span("update_book"))
+                    .await?
+                else {
+                    return Err(if book_exists(&mut tx, book_id).await? {
+                        ApiError::VersionMismatch { book_id: None }
+                    } else {
+                        ApiError::BookNotFound(book_id)
+                    });
+                };
+
+                // New stock goes to members already queued for the title first.
+                if changes.number_of_copies.is_some() {
+                    book.number_of_copies -= serve_waiting(&mut tx, book_id, holds, now).await?;
+                }
+
+                tx.commit().instrument(db_span("commit")).await?;
+                Ok(book)
+            }
+
+            async fn update_stock(
+                &self,
+                updates: &[&StockUpdate],
+                now: NaiveDateTime,
+                holds: &HoldPolicy,
+            ) -> Result<Vec<Book>, ApiError> {
+                let mut tx = self.pool.begin().instrument(db_span("begin")).await?;
+
+                let mut updated = Vec::with_capacity(updates.len());
+                for u in updates {
+                    let current = sql_as!(
+                        StockLevel,
+                        "SELECT number_of_copies, version FROM books WHERE book_id = $1 FOR UPDATE",
+                        u.book_id
+                    )
+                    .fetch_optional(&mut *tx)
+                    .instrument(db_span("lock_book"))
+                    .await?
+                    .ok_or(ApiError::BookNotFound(u.book_id))?;
+                    let copies = current.number_of_copies;
+                    if u.version.is_some_and(|v| v != current.version) {
+                        return Err(ApiError::VersionMismatch {
+                            book_id: Some(u.book_id),
+                        });
+                    }
+
+                    let target = stock_target(u, copies)?;
+                    let mut book = sql_as!(
+                        Book,
+                        r#"UPDATE books SET number_of_copies = $2, version = version + 1 WHERE book_id = $1
+                           RETURNING book_id, name, author, number_of_copies, publication_year, edition,
+                                     max_renewals, min_age, version, archived_at"#,
+                        u.book_id,
+                        target
+                    )
+                    .fetch_one(&mut *tx)
+                    .instrument(db_span("update_stock"))
+                    .await?;
+
+                    if target > copies {
+                        book.number_of_copies -= serve_waiting(&mut tx, u.book_id, holds, now).await?;
+                    }
+                    updated.push(book);
+                }
+
+                tx.commit().instrument(db_span("commit")).await?;
+                Ok(updated)
+            }
+
+            async fn add_book(&self, book: &AddBookRequest) -> Result<i32, ApiError> {
+                let book_id = sql_scalar!(
+                    i32,
+                    "INSERT INTO books (name, author, number_of_copies, publication_year, edition, max_renewals, min_age) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING book_id",
+                    book.name,
+                    book.author,
+                    book.number_of_copies,
+                    book.publication_year,
+                    book.edition,
+                    book.max_renewals,
+                    book.min_age
+                )
+                .fetch_one(&self.pool)
+                .instrument(db_span("insert_book"))
+                .await?;
+                Ok(book_id)
+            }
+
+            async fn archive_book(&self, book_id: i32, now: NaiveDateTime) -> Result<u64, ApiError> {
+                let mut tx = self.pool.begin().instrument(db_span("begin")).await?;
+
+                // Locking the book row stops a checkout from slipping in between the
+                // loan count and the archive.
+                let archived_at = sql_scalar!(
+                    Option<NaiveDateTime>,
+                    "SELECT archived_at FROM books WHERE book_id = $1 FOR UPDATE",
+                    book_id
+                )
+                .fetch_optional(&mut *tx)
+                .instrument(db_span("lock_book"))
+                .await?
+                .ok_or(ApiError::BookNotFound(book_id))?;
+                if archived_at.is_some() {
+                    return Err(ApiError::AlreadyArchived);
+                }
+
+                let copies_on_loan = sql_scalar!(
+                    i64,
+                    r#"SELECT COUNT(*) AS "count!" FROM book_borrow_ledger WHERE book_id = $1 AND actual_return IS NULL"#,
+                    book_id
+                )
+                .fetch_one(&mut *tx)
+                .instrument(db_span("count_open_loans"))
+                .await?;
+                if copies_on_loan > 0 {
+                    return Err(ApiError::BookOnLoan { copies_on_loan });
+                }
+
+                // Nobody can pick up a book that is leaving the catalogue.
+                let active = sql_as!(
+                    HoldState,
+                    "SELECT hold_id, book_id, status FROM holds WHERE book_id = $1 AND status IN ('waiting', 'ready') FOR UPDATE",
+                    book_id
+                )
+                .fetch_all(&mut *tx)
+                .instrument(db_span("lock_holds"))
+                .await?;
+                sql!(
+                    "UPDATE holds SET status = 'cancelled' WHERE book_id = $1 AND status IN ('waiting', 'ready')",
+                    book_id
+                )
+                .execute(&mut *tx)
+                .instrument(db_span("cancel_holds"))
+                .await?;
+                // Copies set aside for ready holds go back on the shelf.
+                let reserved = active.iter().filter(|h| h.status == "ready").count() as i32;
+
+                sql!(
+                    r#"UPDATE books SET number_of_copies = number_of_copies + $2, archived_at = $3,
+                           version = version + 1
+                       WHERE book_id = $1"#,
+                    book_id,
+                    reserved,
+                    now
+                )
+                .execute(&mut *tx)
+                .instrument(db_span("archive_book"))
+                .await?;
+
+                tx.commit().instrument(db_span("commit")).await?;
+                Ok(active.len() as u64)
+            }
+
+            async fn restore_book(&self, book_id: i32) -> Result<Book, ApiError> {
+                let restored = sql_as!(
+                    Book,
+                    r#"UPDATE books SET archived_at = NULL, version = version + 1
+                       WHERE book_id = $1 AND archived_at IS NOT NULL
+                       RETURNING book_id, name, author, number_of_copies, publication_year, edition,
+                                 max_renewals, min_age, version, archived_at"#,
+                    book_id
+                )
+                .fetch_optional(&self.pool)
+                .instrument(db_span("restore_book"))
+                .await?;
+
+                match restored {
+                    Some(book) => Ok(book),
+                    None => Err(
+                        if book_exists(&mut *self.pool.acquire().await?, book_id).await? {
+                            ApiError::NotArchived
+                        } else {
+                            ApiError::BookNotFound(book_id)
+                        },
+                    ),
+                }
+            }
+
+            fn is_closed(&self) -> bool {
+                self.pool.is_closed()
+            }
+        }
+    };
+}
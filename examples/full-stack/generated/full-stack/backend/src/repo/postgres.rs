@@ -0,0 +1,65 @@
+use chrono::NaiveDateTime;
+use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder};
+use tracing::Instrument;
+
+use super::portable::{contains_pattern, ClosedLoan, HoldState, MemberStanding, StockLevel, BOOK_COLUMNS};
+use super::{erased_email, stock_target, LibraryRepository, NewMember, ReturnOutcome, ERASED_NAME};
+use crate::error::ApiError;
+use crate::fines::FinePolicy;
+use crate::holds::HoldPolicy;
+use crate::loans::{Borrower, LoanPolicy};
+use crate::models::*;
+use crate::notify::NotificationPolicy;
+use crate::pagination::{Cursor, SortKey};
+use crate::telemetry::db_span;
+
+/// The production backend. Multi-statement operations run in one
+/// transaction and lock the rows they check, so concurrent requests on the
+/// same book or member queue up instead of racing.
+#[derive(Clone)]
+pub struct PgRepository {
+    pool: PgPool,
+}
+
+impl PgRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+// Every fixed statement is a `query!` macro, checked at compile time against
+// the migrated schema (or the `.sqlx/` metadata when offline).
+macro_rules! sql {
+    ($sql:literal $(, $arg:expr)* $(,)?) => {
+        sqlx::query!($sql $(, $arg)*)
+    };
+}
+
+macro_rules! sql_scalar {
+    ($ty:ty, $sql:literal $(, $arg:expr)* $(,)?) => {
+        sqlx::query_scalar!($sql $(, $arg)*)
+    };
+}
+
+macro_rules! sql_as {
+    ($out:path, $sql:literal $(, $arg:expr)* $(,)?) => {
+        sqlx::query_as!($out, $sql $(, $arg)*)
+    };
+}
+
+/// Full-text search over the generated `search_vector` column.
+fn push_search(qb: &mut QueryBuilder<'_, Postgres>, q: &str) {
+    qb.push(" AND search_vector @@ websearch_to_tsquery('english', ")
+        .push_bind(q.to_string())
+        .push(")");
+}
+
+async fn begin_snapshot(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    sqlx::query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ READ ONLY")
+        .execute(conn)
+        .instrument(db_span("set_snapshot"))
+        .await?;
+    Ok(())
+}
+
+portable_repository!(PgRepository, Postgres, PgConnection);
@@ -0,0 +1,298 @@
+use chrono::NaiveDateTime;
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection, SqlitePool};
+use tracing::Instrument;
+
+use super::portable::{contains_pattern, ClosedLoan, HoldState, MemberStanding, StockLevel, BOOK_COLUMNS};
+use super::{erased_email, stock_target, LibraryRepository, NewMember, ReturnOutcome, ERASED_NAME};
+use crate::error::ApiError;
+use crate::fines::FinePolicy;
+use crate::holds::HoldPolicy;
+use crate::loans::{Borrower, LoanPolicy};
+use crate::models::*;
+use crate::notify::NotificationPolicy;
+use crate::pagination::{Cursor, SortKey};
+use crate::telemetry::db_span;
+
+/// The embedded backend, for running the library as a single binary. The
+/// pool from `db::connect_sqlite` has one connection, so each transaction
+/// runs alone and reads what it checks without the row locks the Postgres
+/// backend takes.
+#[derive(Clone)]
+pub struct SqliteRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+// The shared statements run as runtime queries, through `sqlite_sql`.
+macro_rules! sql {
+    ($sql:literal $(, $arg:expr)* $(,)?) => {
+        sqlx::query(&sqlite_sql($sql))$(.bind(&$arg))*
+    };
+}
+
+macro_rules! sql_scalar {
+    ($ty:ty, $sql:literal $(, $arg:expr)* $(,)?) => {
+        sqlx::query_scalar::<_, $ty>(&sqlite_sql($sql))$(.bind(&$arg))*
+    };
+}
+
+macro_rules! sql_as {
+    ($out:path, $sql:literal $(, $arg:expr)* $(,)?) => {
+        sqlx::query_as::<_, $out>(&sqlite_sql($sql))$(.bind(&$arg))*
+    };
+}
+
+/// A shared statement as SQLite takes it: without row locks, which SQLite
+/// has no syntax for, and without the `!` / `?` nullability markers the
+/// Postgres macros read from column aliases.
+fn sqlite_sql(sql: &str) -> String {
+    let mut sql = sql.replace("!\"", "\"").replace("?\"", "\"");
+    for lock in [" FOR UPDATE OF l", " FOR UPDATE", " FOR SHARE"] {
+        sql = sql.replace(lock, "");
+    }
+    sql
+}
+
+/// Without full-text search every word must appear in the name or the
+/// author; there is no stemming, so "hobbits" does not find "hobbit".
+fn push_search(qb: &mut QueryBuilder<'_, Sqlite>, q: &str) {
+    for word in q.split_whitespace() {
+        let pattern = contains_pattern(&word.to_lowercase());
+        qb.push(" AND (LOWER(name) LIKE ")
+            .push_bind(pattern.clone())
+            .push(" ESCAPE '\\' OR LOWER(author) LIKE ")
+            .push_bind(pattern)
+            .push(" ESCAPE '\\')");
+    }
+}
+
+// The pool's one connection already gives the transaction a single snapshot.
+async fn begin_snapshot(_: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    Ok(())
+}
+
+portable_repository!(SqliteRepository, Sqlite, SqliteConnection);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, Utc};
+    use serde_json::json;
+
+    async fn repo() -> SqliteRepository {
+        let pool = crate::db::connect_sqlite("sqlite::memory:").await.unwrap();
+        crate::db::migrate(&pool).await.unwrap();
+        SqliteRepository::new(pool)
+    }
+
+    async fn member(repo: &SqliteRepository, email: &str) -> i32 {
+        repo.create_member(NewMember {
+            name: "Test Member".into(),
+            address: None,
+            age: 30,
+            email: email.into(),
+            password_hash: "not used".into(),
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn book(repo: &SqliteRepository, name: &str, author: &str, copies: i32) -> i32 {
+        repo.add_book(&AddBookRequest {
+            name: name.into(),
+            author: author.into(),
+            number_of_copies: copies,
+            publication_year: None,
+            edition: None,
+            max_renewals: None,
+            min_age: None,
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn copies(repo: &SqliteRepository, book_id: i32) -> i32 {
+        repo.find_book(book_id).await.unwrap().unwrap().number_of_copies
+    }
+
+    fn returned(book_id: i32, condition: ReturnCondition) -> ReturnRequest {
+        ReturnRequest {
+            book_id,
+            condition,
+            notes: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn emails_are_unique_ignoring_case() {
+        let repo = repo().await;
+        member(&repo, "ada@example.com").await;
+        let again = repo
+            .create_member(NewMember {
+                name: "Ada Again".into(),
+                address: None,
+                age: 36,
+                email: "Ada@Example.com".into(),
+                password_hash: "not used".into(),
+            })
+            .await;
+        assert!(matches!(again, Err(ApiError::EmailTaken)), "{:?}", again);
+    }
+
+    #[actix_web::test]
+    async fn failed_checkout_rolls_back_and_late_returns_are_charged() {
+        let repo = repo().await;
+        let (fines, holds) = (FinePolicy::default(), HoldPolicy::default());
+        // A second copy of `single` is allowed, so the checkout below fails on stock.
+        let loans = LoanPolicy {
+            max_copies_per_title: 2,
+            ..LoanPolicy::default()
+        };
+        let member_id = member(&repo, "reader@example.com").await;
+        let plenty = book(&repo, "Plenty", "Tester", 3).await;
+        let single = book(&repo, "Single", "Tester", 1).await;
+        let now = Utc::now().naive_utc();
+
+        repo.checkout(member_id, &[single], now, &fines, &loans).await.unwrap();
+        let err = repo.checkout(member_id, &[plenty, single], now, &fines, &loans).await.unwrap_err();
+        assert!(matches!(err, ApiError::NoCopiesAvailable(id) if id == single), "{:?}", err);
+        assert_eq!(copies(&repo, plenty).await, 3, "the first title was put back");
+        assert_eq!(repo.open_loans(member_id).await.unwrap().len(), 1);
+
+        // Fifty days late is 1250 cents at the default rate.
+        let late = now + loans.loan_period + Duration::days(50);
+        let outcome = repo
+            .return_book(member_id, &returned(single, ReturnCondition::Damaged), late, &fines, &holds)
+            .await
+            .unwrap();
+        assert_eq!(outcome.late_fee_cents, 1250);
+        assert!(outcome.incident_id.is_some());
+        assert_eq!(copies(&repo, single).await, 1);
+
+        let err = repo.checkout(member_id, &[plenty], late, &fines, &loans).await.unwrap_err();
+        assert!(matches!(err, ApiError::FinesBlockCheckout { balance_cents: 1250, .. }), "{:?}", err);
+        assert_eq!(repo.pay_fine(member_id, 250, late).await.unwrap(), 1000);
+        let entries = repo.fines(member_id).await.unwrap();
+        assert_eq!(entries[0].kind, "payment");
+        assert_eq!(entries[1].kind, "late_return");
+        repo.checkout(member_id, &[plenty], late, &fines, &loans).await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn checkout_rules_see_blocks_ages_and_open_loans() {
+        let repo = repo().await;
+        let (fines, loans) = (FinePolicy::default(), LoanPolicy::default());
+        let member_id = member(&repo, "reader@example.com").await;
+        let plenty = book(&repo, "Plenty", "Tester", 3).await;
+        let adult = book(&repo, "Adult", "Tester", 1).await;
+        sqlx::query("UPDATE books SET min_age = 99 WHERE book_id = $1")
+            .bind(adult)
+            .execute(&repo.pool)
+            .await
+            .unwrap();
+        let now = Utc::now().naive_utc();
+
+        repo.set_member_block(member_id, Some(("unpaid damage", now))).await.unwrap();
+        let err = repo.checkout(member_id, &[plenty], now, &fines, &loans).await.unwrap_err();
+        assert!(
+            matches!(&err, ApiError::MemberBlocked { reason } if reason.as_deref() == Some("unpaid damage")),
+            "{:?}",
+            err
+        );
+        let member = repo.set_member_block(member_id, None).await.unwrap();
+        assert!(member.blocked_at.is_none() && member.blocked_reason.is_none());
+
+        let err = repo.checkout(member_id, &[plenty, adult], now, &fines, &loans).await.unwrap_err();
+        assert!(matches!(err, ApiError::AgeRestricted { book_id, min_age: 99 } if book_id == adult), "{:?}", err);
+        assert_eq!(copies(&repo, plenty).await, 3);
+
+        repo.checkout(member_id, &[plenty], now, &fines, &loans).await.unwrap();
+        let err = repo.checkout(member_id, &[plenty], now, &fines, &loans).await.unwrap_err();
+        assert!(matches!(err, ApiError::TitleLimitReached { max_copies_per_title: 1, .. }), "{:?}", err);
+    }
+
+    #[actix_web::test]
+    async fn returned_copies_go_to_holds_in_order() {
+        let repo = repo().await;
+        let (fines, loans, holds) = (FinePolicy::default(), LoanPolicy::default(), HoldPolicy::default());
+        let borrower = member(&repo, "borrower@example.com").await;
+        let first = member(&repo, "first@example.com").await;
+        let second = member(&repo, "second@example.com").await;
+        let book_id = book(&repo, "Popular", "Tester", 1).await;
+        let now = Utc::now().naive_utc();
+
+        repo.checkout(borrower, &[book_id], now, &fines, &loans).await.unwrap();
+        let first_hold = repo.place_hold(first, book_id, now).await.unwrap();
+        let second_hold = repo.place_hold(second, book_id, now + Duration::seconds(1)).await.unwrap();
+        assert!(matches!(
+            repo.place_hold(first, book_id, now).await,
+            Err(ApiError::HoldAlreadyActive)
+        ));
+        assert_eq!(repo.active_holds(second).await.unwrap()[0].queue_position, Some(2));
+
+        let outcome = repo
+            .return_book(borrower, &returned(book_id, ReturnCondition::Good), now, &fines, &holds)
+            .await
+            .unwrap();
+        assert_eq!(outcome.reserved_for_hold, Some(first_hold.hold_id));
+        assert_eq!(copies(&repo, book_id).await, 0, "the copy is set aside, not shelved");
+
+        let after_pickup_window = now + holds.pickup_window + Duration::minutes(1);
+        assert_eq!(repo.expire_holds(after_pickup_window, &holds).await.unwrap(), 1);
+        let waiting = repo.active_holds(second).await.unwrap();
+        assert_eq!((waiting[0].hold_id, waiting[0].status.as_str()), (second_hold.hold_id, "ready"));
+
+        repo.checkout(second, &[book_id], after_pickup_window, &fines, &loans).await.unwrap();
+        assert!(repo.active_holds(second).await.unwrap().is_empty());
+        assert_eq!(copies(&repo, book_id).await, 0);
+    }
+
+    #[actix_web::test]
+    async fn search_matches_every_word_in_name_or_author() {
+        let repo = repo().await;
+        book(&repo, "The Hobbit", "J. R. R. Tolkien", 1).await;
+        book(&repo, "The Silmarillion", "J. R. R. Tolkien", 1).await;
+        book(&repo, "The Hobbit Companion", "David Day", 1).await;
+        let list = |query: serde_json::Value| {
+            let query: ListBooksQuery = serde_json::from_value(query).unwrap();
+            let repo = &repo;
+            async move {
+                let (books, total) = repo.list_books(&query, None, 0, 10).await.unwrap();
+                assert_eq!(books.len() as i64, total);
+                books.into_iter().map(|b| b.name).collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(list(json!({"q": "hobbit TOLKIEN"})).await, ["The Hobbit"]);
+        assert_eq!(list(json!({"q": "hobbit", "sort": "name"})).await, ["The Hobbit", "The Hobbit Companion"]);
+        assert_eq!(list(json!({"author": "tolk", "sort": "name", "order": "desc"})).await, [
+            "The Silmarillion",
+            "The Hobbit"
+        ]);
+    }
+
+    #[actix_web::test]
+    async fn like_wildcards_in_filters_match_literally() {
+        let repo = repo().await;
+        book(&repo, "Underscored", "Ann_Smith", 1).await;
+        book(&repo, "Plain", "Annesmith", 1).await;
+        book(&repo, "Percent", "100% Press", 1).await;
+        let list = |query: serde_json::Value| {
+            let query: ListBooksQuery = serde_json::from_value(query).unwrap();
+            let repo = &repo;
+            async move {
+                let (books, _) = repo.list_books(&query, None, 0, 10).await.unwrap();
+                books.into_iter().map(|b| b.name).collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(list(json!({"author": "ann_s"})).await, ["Underscored"]);
+        assert_eq!(list(json!({"q": "ann_s"})).await, ["Underscored"]);
+        assert_eq!(list(json!({"author": "%"})).await, ["Percent"]);
+    }
+}
@@ -0,0 +1,1308 @@
+use actix_web::middleware::from_fn;
+use actix_web::web;
+
+use crate::repo::LibraryRepository;
+use crate::{auth, handlers};
+
+/// Declares the API once and generates both `configure`, which registers the
+/// routes for a storage backend, and (under test) `ROUTES`, the
+/// `(method, path)` list the OpenAPI test checks against the document.
+macro_rules! api_routes {
+    (
+        public { $($method:ident $path:literal => $handler:ident;)* }
+        books { $($bmethod:ident $bpath:literal => $bhandler:ident;)* }
+    ) => {
+        pub fn configure<R: LibraryRepository>(cfg: &mut web::ServiceConfig) {
+            cfg$(.route($path, web::$method().to(handlers::$handler::<R>)))*
+                .service(
+                    web::scope("/api/books")
+                        .wrap(from_fn(auth::require_librarian_for_writes))
+                        $(.route($bpath, web::$bmethod().to(handlers::$bhandler::<R>)))*,
+                );
+        }
+
+        #[cfg(test)]
+        pub const ROUTES: &[(&str, &str)] = &[
+            $((stringify!($method), $path),)*
+            $((stringify!($bmethod), concat!("/api/books", $bpath)),)*
+        ];
+    };
+}
+
+api_routes! {
+    public {
+        // Members
+        post "/api/register" => register;
+        post "/api/login" => login;
+        get "/api/members/{member_id}" => get_member;
+        patch "/api/members/{member_id}" => update_member;
+        delete "/api/members/{member_id}" => erase_member;
+        post "/api/members/{member_id}/deactivate" => deactivate_member;
+        get "/api/members/{member_id}/export" => export_member;
+        post "/api/members/{member_id}/checkout" => checkout;
+        get "/api/members/{member_id}/borrowed" => borrowed_books;
+        get "/api/members/{member_id}/history" => loan_history;
+        post "/api/members/{member_id}/return" => return_book;
+        post "/api/members/{member_id}/loans/{ledger_id}/renew" => renew_loan;
+        get "/api/members/{member_id}/fines" => member_fines;
+        post "/api/members/{member_id}/fines/payments" => pay_fine;
+        post "/api/members/{member_id}/holds" => place_hold;
+        get "/api/members/{member_id}/holds" => list_holds;
+        delete "/api/members/{member_id}/holds/{hold_id}" => cancel_hold;
+        // Librarians
+        post "/api/librarian/login" => librarian_login;
+        get "/api/loans" => list_loans;
+        put "/api/librarian/members/{member_id}/block" => block_member;
+        delete "/api/librarian/members/{member_id}/block" => unblock_member;
+    }
+    // Reads are open; writes need a librarian token.
+    books {
+        get "" => list_books;
+        post "" => add_book;
+        patch "" => bulk_update_stock;
+        get "/{book_id}" => get_book;
+        patch "/{book_id}" => update_book;
+        delete "/{book_id}" => remove_book;
+        post "/{book_id}/restore" => restore_book;
+    }
+}
+
+/// The whole API wired to the in-memory repository, the way `main` wires it
+/// to Postgres. Each test arranges and checks stored rows through
+/// `MemoryRepository::with_state`, so none of them needs a database.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{self, AuthKeys, Role};
+    use crate::fines::FinePolicy;
+    use crate::holds::HoldPolicy;
+    use crate::loans::LoanPolicy;
+    use crate::metrics::Metrics;
+    use crate::models::AddBookRequest;
+    use crate::repo::{MemoryRepository, NewMember};
+    use actix_web::http::{header, Method, StatusCode};
+    use actix_web::{test, App};
+    use chrono::{Duration, Utc};
+    use serde_json::{json, Value};
+    use std::collections::BTreeSet;
+
+    /// A header the request carries to identify itself.
+    type Credentials = (header::HeaderName, String);
+
+    macro_rules! app {
+        ($repo:expr, $keys:expr) => {
+            app!($repo, $keys, MemoryRepository)
+        };
+        ($repo:expr, $keys:expr, $backend:ty) => {
+            test::init_service(
+                App::new()
+                    .app_data($repo.clone())
+                    .app_data($keys.clone())
+                    .app_data(web::Data::new(FinePolicy::default()))
+                    .app_data(web::Data::new(HoldPolicy::default()))
+                    .app_data(web::Data::new(LoanPolicy::default()))
+                    .app_data(web::Data::new(Metrics::new()))
+                    .configure(configure::<$backend>),
+            )
+            .await
+        };
+    }
+
+    fn setup() -> (web::Data<MemoryRepository>, web::Data<AuthKeys>) {
+        (
+            web::Data::new(MemoryRepository::new()),
+            web::Data::new(AuthKeys::from_secret(b"test-secret")),
+        )
+    }
+
+    async fn member(repo: &web::Data<impl LibraryRepository>, email: &str) -> i32 {
+        repo.create_member(NewMember {
+            name: "Test Member".into(),
+            address: None,
+            age: 30,
+            email: email.into(),
+            password_hash: "not used".into(),
+        })
+        .await
+        .unwrap()
+    }
+
+    async fn book(repo: &web::Data<impl LibraryRepository>, name: &str, copies: i32) -> i32 {
+        repo.add_book(&AddBookRequest {
+            name: name.into(),
+            author: "Tester".into(),
+            number_of_copies: copies,
+            publication_year: None,
+            edition: None,
+            max_renewals: None,
+            min_age: None,
+        })
+        .await
+        .unwrap()
+    }
+
+    fn copies(repo: &MemoryRepository, book_id: i32) -> i32 {
+        repo.with_state(|s| {
+            s.books
+                .iter()
+                .find(|b| b.book_id == book_id)
+                .unwrap()
+                .number_of_copies
+        })
+    }
+
+    fn hold_status(repo: &MemoryRepository, hold_id: i64) -> String {
+        repo.with_state(|s| {
+            s.holds
+                .iter()
+                .find(|h| i64::from(h.hold_id) == hold_id)
+                .unwrap()
+                .status
+                .clone()
+        })
+    }
+
+    fn auth(keys: &AuthKeys, id: i32, role: Role) -> Credentials {
+        (
+            header::AUTHORIZATION,
+            format!("Bearer {}", keys.issue(id, role).unwrap()),
+        )
+    }
+
+    fn post(uri: &str, auth: &Credentials, body: Value) -> test::TestRequest {
+        test::TestRequest::post()
+            .uri(uri)
+            .insert_header(auth.clone())
+            .set_json(body)
+    }
+
+    fn get(uri: &str, auth: &Credentials) -> test::TestRequest {
+        test::TestRequest::get()
+            .uri(uri)
+            .insert_header(auth.clone())
+    }
+
+    #[actix_web::test]
+    async fn every_route_is_served_by_the_memory_repository() {
+        let (repo, keys) = setup();
+        exercise_every_route(repo, keys).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[actix_web::test]
+    async fn every_route_is_served_by_the_sqlite_repository() {
+        let pool = crate::db::connect_sqlite("sqlite::memory:").await.unwrap();
+        crate::db::migrate(&pool).await.unwrap();
+        let repo = web::Data::new(crate::repo::SqliteRepository::new(pool));
+        let keys = web::Data::new(AuthKeys::from_secret(b"test-secret"));
+        exercise_every_route(repo, keys).await;
+    }
+
+    /// One request per route, in an order where each finds what it needs.
+    async fn exercise_every_route<R: LibraryRepository>(repo: web::Data<R>, keys: web::Data<AuthKeys>) {
+        let hash = auth::hash_password("librarian-password").unwrap();
+        repo.ensure_librarian("admin", &hash).await.unwrap();
+        let shelved = book(&repo, "On the Shelf", 2).await;
+        let lent_out = book(&repo, "Lent Out", 0).await;
+        let app = app!(repo, keys, R);
+
+        let member = auth(&keys, 1, Role::Member);
+        let closing = auth(&keys, 2, Role::Member);
+        let librarian = auth(&keys, 1, Role::Librarian);
+        let none = (header::ACCEPT, "*/*".to_string());
+        // (method, route, uri, credentials, body, expected status)
+        let cases: Vec<(&str, &str, String, &Credentials, Value, u16)> = vec![
+            (
+                "post",
+                "/api/register",
+                "/api/register".into(),
+                &none,
+                json!({
+                    "first_name": "Ada", "last_name": "Lovelace", "age": 36,
+                    "email": "ada@example.com", "password": "analytical"
+                }),
+                201,
+            ),
+            (
+                "post",
+                "/api/login",
+                "/api/login".into(),
+                &none,
+                json!({"member_id": 1, "password": "analytical"}),
+                200,
+            ),
+            (
+                "get",
+                "/api/members/{member_id}",
+                "/api/members/1".into(),
+                &member,
+                Value::Null,
+                200,
+            ),
+            (
+                "patch",
+                "/api/members/{member_id}",
+                "/api/members/1".into(),
+                &member,
+                json!({"address": "12 St James's Square"}),
+                200,
+            ),
+            (
+                "post",
+                "/api/librarian/login",
+                "/api/librarian/login".into(),
+                &none,
+                json!({"username": "admin", "password": "librarian-password"}),
+                200,
+            ),
+            (
+                "get",
+                "/api/books",
+                "/api/books?q=shelf".into(),
+                &none,
+                Value::Null,
+                200,
+            ),
+            (
+                "post",
+                "/api/books",
+                "/api/books".into(),
+                &librarian,
+                json!({"name": "New Arrival", "author": "Tester", "number_of_copies": 1}),
+                201,
+            ),
+            (
+                "get",
+                "/api/books/{book_id}",
+                "/api/books/3".into(),
+                &none,
+                Value::Null,
+                200,
+            ),
+            (
+                "patch",
+                "/api/books/{book_id}",
+                "/api/books/3".into(),
+                &librarian,
+                json!({"edition": "2nd"}),
+                200,
+            ),
+            (
+                "patch",
+                "/api/books",
+                "/api/books".into(),
+                &librarian,
+                json!({"updates": [{"book_id": 3, "adjust_by": 1}]}),
+                200,
+            ),
+            (
+                "post",
+                "/api/members/{member_id}/checkout",
+                "/api/members/1/checkout".into(),
+                &member,
+                json!({"book_ids": [shelved]}),
+                200,
+            ),
+            (
+                "get",
+                "/api/members/{member_id}/borrowed",
+                "/api/members/1/borrowed".into(),
+                &member,
+                Value::Null,
+                200,
+            ),
+            (
+                "post",
+                "/api/members/{member_id}/loans/{ledger_id}/renew",
+                "/api/members/1/loans/1/renew".into(),
+                &member,
+                Value::Null,
+                200,
+            ),
+            (
+                "post",
+                "/api/members/{member_id}/return",
+                "/api/members/1/return".into(),
+                &member,
+                json!({"book_id": shelved}),
+                200,
+            ),
+            (
+                "get",
+                "/api/members/{member_id}/history",
+                "/api/members/1/history?condition=good".into(),
+                &member,
+                Value::Null,
+                200,
+            ),
+            (
+                "get",
+                "/api/loans",
+                "/api/loans?member_id=1&format=csv".into(),
+                &librarian,
+                Value::Null,
+                200,
+            ),
+            (
+                "put",
+                "/api/librarian/members/{member_id}/block",
+                "/api/librarian/members/1/block".into(),
+                &librarian,
+                json!({"reason": "testing"}),
+                200,
+            ),
+            (
+                "delete",
+                "/api/librarian/members/{member_id}/block",
+                "/api/librarian/members/1/block".into(),
+                &librarian,
+                Value::Null,
+                200,
+            ),
+            (
+                "get",
+                "/api/members/{member_id}/fines",
+                "/api/members/1/fines".into(),
+                &member,
+                Value::Null,
+                200,
+            ),
+            (
+                "post",
+                "/api/members/{member_id}/fines/payments",
+                "/api/members/1/fines/payments".into(),
+                &member,
+                json!({"amount_cents": 100}),
+                409,
+            ),
+            (
+                "post",
+                "/api/members/{member_id}/holds",
+                "/api/members/1/holds".into(),
+                &member,
+                json!({"book_id": lent_out}),
+                201,
+            ),
+            (
+                "get",
+                "/api/members/{member_id}/holds",
+                "/api/members/1/holds".into(),
+                &member,
+                Value::Null,
+                200,
+            ),
+            (
+                "delete",
+                "/api/members/{member_id}/holds/{hold_id}",
+                "/api/members/1/holds/1".into(),
+                &member,
+                Value::Null,
+                200,
+            ),
+            (
+                "get",
+                "/api/members/{member_id}/export",
+                "/api/members/1/export".into(),
+                &member,
+                Value::Null,
+                200,
+            ),
+            (
+                "delete",
+                "/api/books/{book_id}",
+                "/api/books/3".into(),
+                &librarian,
+                Value::Null,
+                200,
+            ),
+            (
+                "post",
+                "/api/books/{book_id}/restore",
+                "/api/books/3/restore".into(),
+                &librarian,
+                Value::Null,
+                200,
+            ),
+            // A second account to close, since a closed account's token no
+            // longer reaches the member routes and the first is erased last.
+            (
+                "post",
+                "/api/register",
+                "/api/register".into(),
+                &none,
+                json!({
+                    "first_name": "Charles", "last_name": "Babbage", "age": 79,
+                    "email": "charles@example.com", "password": "difference"
+                }),
+                201,
+            ),
+            (
+                "post",
+                "/api/members/{member_id}/deactivate",
+                "/api/members/2/deactivate".into(),
+                &closing,
+                Value::Null,
+                200,
+            ),
+            (
+                "delete",
+                "/api/members/{member_id}",
+                "/api/members/1".into(),
+                &member,
+                Value::Null,
+                200,
+            ),
+        ];
+
+        for (method, _, uri, credentials, body, expected) in &cases {
+            let mut req = test::TestRequest::default()
+                .method(Method::from_bytes(method.to_uppercase().as_bytes()).unwrap())
+                .uri(uri)
+                .insert_header((*credentials).clone())
+                // Only PATCH /api/books/{book_id} reads it.
+                .insert_header((header::IF_MATCH, "*"));
+            if !body.is_null() {
+                req = req.set_json(body);
+            }
+            let res = test::call_service(&app, req.to_request()).await;
+            let status = res.status().as_u16();
+            let body = test::read_body(res).await;
+            assert_eq!(
+                status,
+                *expected,
+                "{method} {uri}: {}",
+                String::from_utf8_lossy(&body)
+            );
+        }
+
+        let covered: BTreeSet<(&str, &str)> = cases
+            .iter()
+            .map(|(method, route, ..)| (*method, *route))
+            .collect();
+        let routed: BTreeSet<(&str, &str)> = ROUTES.iter().copied().collect();
+        assert_eq!(covered, routed, "add a case for every route");
+    }
+
+    #[actix_web::test]
+    async fn loans_renewals_and_fines_are_recorded() {
+        let (repo, keys) = setup();
+        let member_id = member(&repo, "reader@example.com").await;
+        let book_id = book(&repo, "Single Copy", 1).await;
+        let app = app!(repo, keys);
+        let me = auth(&keys, member_id, Role::Member);
+        let uri = |rest: &str| format!("/api/members/{}/{}", member_id, rest);
+
+        let res = test::call_service(
+            &app,
+            post(&uri("checkout"), &me, json!({"book_ids": [book_id]})).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(copies(&repo, book_id), 0);
+
+        // The second copy does not exist, so nothing is lent.
+        let res = test::call_service(
+            &app,
+            post(&uri("checkout"), &me, json!({"book_ids": [book_id]})).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(repo.with_state(|s| s.loans.len()), 1);
+
+        let loans: Value =
+            test::call_and_read_body_json(&app, get(&uri("borrowed"), &me).to_request()).await;
+        assert_eq!(loans[0]["book_name"], "Single Copy");
+        assert_eq!(loans[0]["renewals_left"], 2);
+        let ledger_id = loans[0]["id"].as_i64().unwrap();
+
+        let renew = || {
+            post(
+                &uri(&format!("loans/{}/renew", ledger_id)),
+                &me,
+                Value::Null,
+            )
+            .to_request()
+        };
+        let res: Value = test::call_and_read_body_json(&app, renew()).await;
+        assert_eq!(res["renewal_count"], 1);
+        assert_eq!(res["renewals_left"], 1);
+
+        // Fifty days late is 1250 cents, over the default 1000 limit.
+        repo.with_state(|s| {
+            s.loans[0].expected_return = Utc::now().naive_utc() - Duration::days(50)
+        });
+        let res = test::call_service(&app, renew()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "loan_overdue");
+
+        let res: Value = test::call_and_read_body_json(
+            &app,
+            post(
+                &uri("return"),
+                &me,
+                json!({"book_id": book_id, "condition": "damaged"}),
+            )
+            .to_request(),
+        )
+        .await;
+        assert_eq!(res["late_fee_cents"], 1250);
+        assert!(res["incident_id"].is_i64());
+        assert_eq!(copies(&repo, book_id), 1);
+        repo.with_state(|s| {
+            assert!(s.loans[0].actual_return.is_some());
+            assert_eq!(s.loans[0].return_condition.as_deref(), Some("damaged"));
+        });
+
+        let res = test::call_service(
+            &app,
+            post(&uri("checkout"), &me, json!({"book_ids": [book_id]})).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res: Value = test::call_and_read_body_json(
+            &app,
+            post(&uri("fines/payments"), &me, json!({"amount_cents": 250})).to_request(),
+        )
+        .await;
+        assert_eq!(res["balance_cents"], 1000);
+        let fines: Value =
+            test::call_and_read_body_json(&app, get(&uri("fines"), &me).to_request()).await;
+        assert_eq!(fines["entries"][0]["kind"], "payment");
+        assert_eq!(fines["entries"][1]["kind"], "late_return");
+
+        let res = test::call_service(
+            &app,
+            post(&uri("checkout"), &me, json!({"book_ids": [book_id]})).to_request(),
+        )
+        .await;
+        assert_eq!(
+            res.status(),
+            StatusCode::OK,
+            "a balance at the limit does not block"
+        );
+    }
+
+    #[actix_web::test]
+    async fn closed_accounts_keep_their_history_but_cannot_borrow() {
+        let (repo, keys) = setup();
+        member(&repo, "taken@example.com").await;
+        let book_id = book(&repo, "Borrowed", 1).await;
+        let held_id = book(&repo, "Awaited", 0).await;
+        let app = app!(repo, keys);
+        let req = test::TestRequest::post()
+            .uri("/api/register")
+            .set_json(json!({
+                "first_name": "Grace", "last_name": "Hopper", "age": 40,
+                "email": "grace@example.com", "address": "Arlington", "password": "compilers"
+            }))
+            .to_request();
+        let res: Value = test::call_and_read_body_json(&app, req).await;
+        let member_id = res["member_id"].as_i64().unwrap() as i32;
+        let me = auth(&keys, member_id, Role::Member);
+        let uri = |rest: &str| format!("/api/members/{}{}", member_id, rest);
+        let patch = |body: Value| {
+            test::TestRequest::patch()
+                .uri(&uri(""))
+                .insert_header(me.clone())
+                .set_json(body)
+                .to_request()
+        };
+        let login = || {
+            test::TestRequest::post()
+                .uri("/api/login")
+                .set_json(json!({"member_id": member_id, "password": "compilers"}))
+                .to_request()
+        };
+
+        let res = test::call_service(&app, patch(json!({"email": "TAKEN@example.com"}))).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let profile: Value =
+            test::call_and_read_body_json(&app, patch(json!({"address": null, "age": 41}))).await;
+        assert_eq!(profile["address"], Value::Null);
+        assert_eq!(profile["age"], 41);
+        assert_eq!(profile["email"], "grace@example.com");
+        assert!(profile.get("password_hash").is_none());
+
+        let req = post(&uri("/checkout"), &me, json!({"book_ids": [book_id]})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let res = test::call_service(&app, post(&uri("/deactivate"), &me, Value::Null).to_request()).await;
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "member_has_loans");
+        assert_eq!(body["open_loans"], 1);
+
+        let req = post(
+            &uri("/return"),
+            &me,
+            json!({"book_id": book_id, "condition": "damaged", "notes": "water damage at home"}),
+        )
+        .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = post(&uri("/holds"), &me, json!({"book_id": held_id})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+
+        let export: Value = test::call_and_read_body_json(&app, get(&uri("/export"), &me).to_request()).await;
+        assert_eq!(export["member"]["name"], "Grace Hopper");
+        assert_eq!(export["loans"][0]["book_name"], "Borrowed");
+        assert_eq!(export["holds"][0]["status"], "waiting");
+        assert_eq!(export["incidents"][0]["notes"], "water damage at home");
+
+        let res = test::call_service(&app, post(&uri("/deactivate"), &me, Value::Null).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(hold_status(&repo, 1), "cancelled");
+        let res = test::call_service(&app, login()).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // `me` was issued before the account closed and stops working with it.
+        let profile = || test::TestRequest::default().uri(&uri("")).insert_header(me.clone());
+        let refused = [
+            post(&uri("/checkout"), &me, json!({"book_ids": [book_id]})),
+            post(&uri("/holds"), &me, json!({"book_id": held_id})),
+            get(&uri("/export"), &me),
+            profile(),
+            profile().method(Method::PATCH).set_json(json!({"age": 42})),
+            profile().method(Method::DELETE),
+        ];
+        for req in refused {
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body["code"], "account_deactivated");
+        }
+        repo.with_state(|s| {
+            let closed = s.members.iter().find(|m| m.member_id == member_id).unwrap();
+            assert_eq!(closed.age, Some(41));
+            assert_eq!(closed.name, "Grace Hopper");
+            assert_eq!(s.holds.len(), 1);
+        });
+    }
+
+    #[actix_web::test]
+    async fn erasure_removes_personal_data_but_keeps_loans() {
+        let (repo, keys) = setup();
+        let book_id = book(&repo, "Borrowed", 1).await;
+        let app = app!(repo, keys);
+        let req = test::TestRequest::post()
+            .uri("/api/register")
+            .set_json(json!({
+                "first_name": "Grace", "last_name": "Hopper", "age": 40,
+                "email": "grace@example.com", "address": "Arlington", "password": "compilers"
+            }))
+            .to_request();
+        let res: Value = test::call_and_read_body_json(&app, req).await;
+        let member_id = res["member_id"].as_i64().unwrap() as i32;
+        let me = auth(&keys, member_id, Role::Member);
+        let uri = |rest: &str| format!("/api/members/{}{}", member_id, rest);
+
+        let req = post(&uri("/checkout"), &me, json!({"book_ids": [book_id]})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = post(
+            &uri("/return"),
+            &me,
+            json!({"book_id": book_id, "condition": "damaged", "notes": "water damage at home"}),
+        )
+        .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::delete()
+            .uri(&uri(""))
+            .insert_header(me.clone())
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let res = test::call_service(&app, get(&uri(""), &me).to_request()).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let login = test::TestRequest::post()
+            .uri("/api/login")
+            .set_json(json!({"member_id": member_id, "password": "compilers"}))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, login).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        repo.with_state(|s| {
+            let erased = s.members.iter().find(|m| m.member_id == member_id).unwrap();
+            assert_eq!(erased.name, "Erased member");
+            assert_eq!(erased.address, None);
+            assert_ne!(erased.email, "grace@example.com");
+            assert!(erased.password_hash.is_none());
+            assert_eq!(s.incidents[0].notes, None);
+            assert_eq!(s.loans.len(), 1, "loan history is kept");
+        });
+    }
+
+    #[actix_web::test]
+    async fn returned_copies_go_to_holds_in_order() {
+        let (repo, keys) = setup();
+        let borrower = member(&repo, "borrower@example.com").await;
+        let first = member(&repo, "first@example.com").await;
+        let second = member(&repo, "second@example.com").await;
+        let book_id = book(&repo, "Popular", 1).await;
+        let app = app!(repo, keys);
+        let as_member = |id: i32| auth(&keys, id, Role::Member);
+        let checkout = |id: i32| {
+            post(
+                &format!("/api/members/{}/checkout", id),
+                &as_member(id),
+                json!({"book_ids": [book_id]}),
+            )
+            .to_request()
+        };
+
+        assert_eq!(
+            test::call_service(&app, checkout(borrower)).await.status(),
+            StatusCode::OK
+        );
+
+        let mut hold_ids = Vec::new();
+        for id in [first, second] {
+            let req = post(
+                &format!("/api/members/{}/holds", id),
+                &as_member(id),
+                json!({"book_id": book_id}),
+            )
+            .to_request();
+            let hold: Value = test::call_and_read_body_json(&app, req).await;
+            hold_ids.push(hold["hold_id"].as_i64().unwrap());
+        }
+        let req = post(
+            &format!("/api/members/{}/holds", first),
+            &as_member(first),
+            json!({"book_id": book_id}),
+        )
+        .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::CONFLICT
+        );
+
+        let holds: Value = test::call_and_read_body_json(
+            &app,
+            get(
+                &format!("/api/members/{}/holds", second),
+                &as_member(second),
+            )
+            .to_request(),
+        )
+        .await;
+        assert_eq!(holds[0]["queue_position"], 2);
+        assert_eq!(holds[0]["book_name"], "Popular");
+
+        let req = post(
+            &format!("/api/members/{}/return", borrower),
+            &as_member(borrower),
+            json!({"book_id": book_id}),
+        )
+        .to_request();
+        let res: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res["reserved_for_hold"], hold_ids[0]);
+        assert_eq!(hold_status(&repo, hold_ids[0]), "ready");
+        assert_eq!(
+            copies(&repo, book_id),
+            0,
+            "the copy is set aside, not shelved"
+        );
+        assert_eq!(
+            test::call_service(&app, checkout(second)).await.status(),
+            StatusCode::CONFLICT
+        );
+
+        repo.with_state(|s| {
+            s.holds[0].expires_at = Some(Utc::now().naive_utc() - Duration::minutes(1))
+        });
+        let expired = repo
+            .expire_holds(Utc::now().naive_utc(), &HoldPolicy::default())
+            .await
+            .unwrap();
+        assert_eq!(expired, 1);
+        assert_eq!(hold_status(&repo, hold_ids[0]), "expired");
+        assert_eq!(hold_status(&repo, hold_ids[1]), "ready");
+
+        assert_eq!(
+            test::call_service(&app, checkout(second)).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(hold_status(&repo, hold_ids[1]), "fulfilled");
+        assert_eq!(copies(&repo, book_id), 0);
+    }
+
+    #[actix_web::test]
+    async fn book_edits_check_versions_and_archiving_keeps_history() {
+        let (repo, keys) = setup();
+        let member_id = member(&repo, "patron@example.com").await;
+        let book_id = book(&repo, "Teh Hobbit", 1).await;
+        let queued_id = book(&repo, "Queued", 0).await;
+        let app = app!(repo, keys);
+        let librarian = auth(&keys, 1, Role::Librarian);
+        let me = auth(&keys, member_id, Role::Member);
+        let uri = format!("/api/books/{}", book_id);
+        let patch = |etag: &str, body: Value| {
+            test::TestRequest::patch()
+                .uri(&uri)
+                .insert_header(librarian.clone())
+                .insert_header((header::IF_MATCH, etag.to_string()))
+                .set_json(body)
+                .to_request()
+        };
+        let bulk = |body: Value| {
+            test::TestRequest::patch()
+                .uri("/api/books")
+                .insert_header(librarian.clone())
+                .set_json(body)
+                .to_request()
+        };
+
+        let req = post(
+            &format!("/api/members/{}/holds", member_id),
+            &me,
+            json!({"book_id": queued_id}),
+        )
+        .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::CREATED
+        );
+
+        let res = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(res.headers().get(header::ETAG).unwrap(), "\"1\"");
+        let res = test::call_service(&app, patch("\"1\"", json!({"name": "The Hobbit"}))).await;
+        assert_eq!(res.headers().get(header::ETAG).unwrap(), "\"2\"");
+        let res = test::call_service(&app, patch("\"1\"", json!({"author": "Tolkien"}))).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+
+        // Members may read the catalogue but not change it.
+        let req = test::TestRequest::patch()
+            .uri(&uri)
+            .insert_header(me.clone())
+            .set_json(json!({}))
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::FORBIDDEN
+        );
+
+        let res = test::call_service(
+            &app,
+            bulk(json!({"updates": [{"book_id": book_id, "adjust_by": 1}, {"book_id": queued_id, "adjust_by": -1}]})),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(copies(&repo, book_id), 1, "nothing applied");
+
+        let res = test::call_service(
+            &app,
+            bulk(json!({"updates": [
+                {"book_id": book_id, "adjust_by": 1, "number_of_copies": 3},
+                {"book_id": queued_id, "number_of_copies": 10_001},
+                {"book_id": book_id, "adjust_by": 1}
+            ]})),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = test::read_body_json(res).await;
+        let fields: Vec<&str> = body["errors"].as_array().unwrap().iter().map(|e| e["field"].as_str().unwrap()).collect();
+        assert_eq!(fields, ["updates", "updates[0]", "updates[1].number_of_copies"]);
+        let res = test::call_service(&app, bulk(json!({"updates": []}))).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let res = test::call_service(&app, bulk(json!({"updates": [{"book_id": book_id, "adjust_by": 10_000}]}))).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "stock_above_limit");
+        assert_eq!(copies(&repo, book_id), 1, "nothing applied");
+
+        let res = test::call_service(
+            &app,
+            bulk(json!({"updates": [{"book_id": queued_id, "number_of_copies": 2}]})),
+        )
+        .await;
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(
+            body["updated"][0]["number_of_copies"], 1,
+            "one new copy is reserved for the hold"
+        );
+        assert_eq!(hold_status(&repo, 1), "ready");
+
+        let req = post(
+            &format!("/api/members/{}/checkout", member_id),
+            &me,
+            json!({"book_ids": [book_id]}),
+        )
+        .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let remove = || {
+            test::TestRequest::delete()
+                .uri(&uri)
+                .insert_header(librarian.clone())
+                .to_request()
+        };
+        let res = test::call_service(&app, remove()).await;
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(body["code"], "book_on_loan");
+
+        let req = post(
+            &format!("/api/members/{}/return", member_id),
+            &me,
+            json!({"book_id": book_id}),
+        )
+        .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        assert_eq!(
+            test::call_service(&app, remove()).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            test::call_service(&app, remove()).await.status(),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            repo.with_state(|s| s.loans.len()),
+            1,
+            "loan history survives archiving"
+        );
+
+        let listed = |query: &str| {
+            test::TestRequest::get()
+                .uri(&format!("/api/books?q=hobbit{}", query))
+                .to_request()
+        };
+        let page: Value = test::call_and_read_body_json(&app, listed("")).await;
+        assert_eq!(page["total"], 0);
+        let page: Value =
+            test::call_and_read_body_json(&app, listed("&include_archived=true")).await;
+        assert_eq!(page["total"], 1);
+
+        let restore = || {
+            test::TestRequest::post()
+                .uri(&format!("{}/restore", uri))
+                .insert_header(librarian.clone())
+                .to_request()
+        };
+        assert_eq!(
+            test::call_service(&app, restore()).await.status(),
+            StatusCode::OK
+        );
+        assert_eq!(
+            test::call_service(&app, restore()).await.status(),
+            StatusCode::CONFLICT
+        );
+    }
+
+    #[actix_web::test]
+    async fn archiving_returns_copies_reserved_for_ready_holds_on_memory() {
+        let (repo, keys) = setup();
+        archive_returns_reserved_copies(repo, keys).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[actix_web::test]
+    async fn archiving_returns_copies_reserved_for_ready_holds_on_sqlite() {
+        let pool = crate::db::connect_sqlite("sqlite::memory:").await.unwrap();
+        crate::db::migrate(&pool).await.unwrap();
+        let repo = web::Data::new(crate::repo::SqliteRepository::new(pool));
+        let keys = web::Data::new(AuthKeys::from_secret(b"test-secret"));
+        archive_returns_reserved_copies(repo, keys).await;
+    }
+
+    /// Place hold, copy comes back for it, archive, restore: the copy the
+    /// ready hold was keeping must be on the shelf again.
+    async fn archive_returns_reserved_copies<R: LibraryRepository>(repo: web::Data<R>, keys: web::Data<AuthKeys>) {
+        let borrower = member(&repo, "borrower@example.com").await;
+        let waiting = member(&repo, "waiting@example.com").await;
+        let book_id = book(&repo, "Reserved", 1).await;
+        let app = app!(repo, keys, R);
+        let librarian = auth(&keys, 1, Role::Librarian);
+        let as_member = |id: i32| auth(&keys, id, Role::Member);
+        let uri = format!("/api/books/{}", book_id);
+
+        let req = post(
+            &format!("/api/members/{}/checkout", borrower),
+            &as_member(borrower),
+            json!({"book_ids": [book_id]}),
+        )
+        .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = post(
+            &format!("/api/members/{}/holds", waiting),
+            &as_member(waiting),
+            json!({"book_id": book_id}),
+        )
+        .to_request();
+        let hold: Value = test::call_and_read_body_json(&app, req).await;
+
+        let req = post(
+            &format!("/api/members/{}/return", borrower),
+            &as_member(borrower),
+            json!({"book_id": book_id}),
+        )
+        .to_request();
+        let res: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(res["reserved_for_hold"], hold["hold_id"]);
+        let detail: Value =
+            test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(detail["number_of_copies"], 0, "the copy is set aside for the hold");
+
+        let req = test::TestRequest::delete()
+            .uri(&uri)
+            .insert_header(librarian.clone())
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let holds: Value = test::call_and_read_body_json(
+            &app,
+            get(&format!("/api/members/{}/holds", waiting), &as_member(waiting)).to_request(),
+        )
+        .await;
+        assert_eq!(holds, json!([]), "the ready hold is cancelled");
+
+        let req = test::TestRequest::post()
+            .uri(&format!("{}/restore", uri))
+            .insert_header(librarian.clone())
+            .to_request();
+        let restored: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(restored["number_of_copies"], 1, "the reserved copy is back on the shelf");
+    }
+
+    #[actix_web::test]
+    async fn book_listing_follows_cursors_across_pages() {
+        let (repo, keys) = setup();
+        for (name, year) in [
+            ("Quixote A", 1990),
+            ("Quixote B", 1985),
+            ("Quixote C", 2001),
+            ("Quixote D", 1985),
+            ("Other", 1970),
+        ] {
+            let book_id = book(&repo, name, 1).await;
+            repo.with_state(|s| s.books[book_id as usize - 1].publication_year = Some(year));
+        }
+        let app = app!(repo, keys);
+
+        let mut years = Vec::new();
+        let mut uri = "/api/books?q=quixote&sort=publication_year&order=desc&limit=2".to_string();
+        loop {
+            let page: Value = test::call_and_read_body_json(
+                &app,
+                test::TestRequest::get().uri(&uri).to_request(),
+            )
+            .await;
+            assert_eq!(page["total"], 4);
+            years.extend(
+                page["items"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|b| b["publication_year"].as_i64().unwrap()),
+            );
+            match page["next_cursor"].as_str() {
+                Some(cursor) => {
+                    uri = format!(
+                        "/api/books?q=quixote&sort=publication_year&order=desc&limit=2&cursor={}",
+                        cursor
+                    )
+                }
+                None => break,
+            }
+        }
+        assert_eq!(years, vec![2001, 1990, 1985, 1985]);
+
+        let req = test::TestRequest::get()
+            .uri("/api/books?publication_year=1985&page=2&limit=1")
+            .to_request();
+        let page: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(page["items"][0]["name"], "Quixote D");
+        assert_eq!(page["page"], 2);
+    }
+
+    #[actix_web::test]
+    async fn returned_loans_stay_visible_in_history_and_the_ledger() {
+        let (repo, keys) = setup();
+        let reader = member(&repo, "reader@example.com").await;
+        let other = member(&repo, "other@example.com").await;
+        let first = book(&repo, "First", 1).await;
+        let second = book(&repo, "Second", 1).await;
+        let third = book(&repo, "Third", 1).await;
+        let app = app!(repo, keys);
+        let me = auth(&keys, reader, Role::Member);
+        let them = auth(&keys, other, Role::Member);
+        let librarian = auth(&keys, 1, Role::Librarian);
+        let history = |query: &str| format!("/api/members/{}/history?{}", reader, query);
+
+        let req = post(
+            &format!("/api/members/{}/checkout", reader),
+            &me,
+            json!({"book_ids": [first, second]}),
+        )
+        .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = post(
+            &format!("/api/members/{}/return", reader),
+            &me,
+            json!({"book_id": first, "condition": "damaged"}),
+        )
+        .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = post(
+            &format!("/api/members/{}/checkout", other),
+            &them,
+            json!({"book_ids": [third]}),
+        )
+        .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let ten_days_ago = Utc::now().naive_utc() - Duration::days(10);
+        repo.with_state(|s| {
+            s.loans[1].expected_return = ten_days_ago;
+            s.loans[2].borrow_date = ten_days_ago;
+        });
+
+        let page: Value = test::call_and_read_body_json(&app, get(&history(""), &me).to_request()).await;
+        assert_eq!(page["total"], 2, "returned loans are listed too");
+        let page: Value =
+            test::call_and_read_body_json(&app, get(&history("condition=damaged"), &me).to_request()).await;
+        assert_eq!(page["items"][0]["book_name"], "First");
+        assert_eq!(page["total"], 1);
+        let page: Value =
+            test::call_and_read_body_json(&app, get(&history("overdue=true"), &me).to_request()).await;
+        assert_eq!(page["items"][0]["book_name"], "Second");
+        assert_eq!(page["total"], 1);
+
+        // Both loans share a borrow time; the cursor still steps past each once.
+        let mut ids = Vec::new();
+        let mut uri = history("limit=1");
+        loop {
+            let page: Value = test::call_and_read_body_json(&app, get(&uri, &me).to_request()).await;
+            ids.extend(page["items"].as_array().unwrap().iter().map(|l| l["id"].as_i64().unwrap()));
+            match page["next_cursor"].as_str() {
+                Some(cursor) => uri = history(&format!("limit=1&cursor={}", cursor)),
+                None => break,
+            }
+        }
+        assert_eq!(ids, vec![2, 1]);
+
+        let res = test::call_service(&app, get("/api/loans", &me).to_request()).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let page: Value = test::call_and_read_body_json(&app, get("/api/loans", &librarian).to_request()).await;
+        assert_eq!(page["total"], 3);
+        let page: Value = test::call_and_read_body_json(
+            &app,
+            get(&format!("/api/loans?member_id={}", other), &librarian).to_request(),
+        )
+        .await;
+        assert_eq!(page["items"][0]["book_name"], "Third");
+        assert_eq!(page["total"], 1);
+        let week_ago = (Utc::now() - Duration::days(7)).date_naive();
+        let page: Value = test::call_and_read_body_json(
+            &app,
+            get(&format!("/api/loans?from={}", week_ago), &librarian).to_request(),
+        )
+        .await;
+        assert_eq!(page["total"], 2);
+        let page: Value = test::call_and_read_body_json(
+            &app,
+            get(&format!("/api/loans?to={}", week_ago), &librarian).to_request(),
+        )
+        .await;
+        assert_eq!(page["total"], 1);
+        let req = get(&format!("/api/loans?from={}&to=2000-01-01", week_ago), &librarian).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+
+        let res = test::call_service(&app, get("/api/loans?format=csv&overdue=false", &librarian).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::CONTENT_TYPE).unwrap(), "text/csv; charset=utf-8");
+        let body = test::read_body(res).await;
+        let lines: Vec<&str> = std::str::from_utf8(&body).unwrap().lines().collect();
+        assert_eq!(
+            lines[0],
+            "id,book_id,member_id,borrow_date,expected_return,actual_return,return_condition,renewal_count,max_renewals,book_name"
+        );
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains(",damaged,") && lines[2].starts_with("3,"), "{lines:?}");
+    }
+
+    #[actix_web::test]
+    async fn return_notes_are_validated_before_anything_is_stored() {
+        let (repo, keys) = setup();
+        let reader = member(&repo, "reader@example.com").await;
+        let book_id = book(&repo, "Noted", 1).await;
+        let app = app!(repo, keys);
+        let me = auth(&keys, reader, Role::Member);
+        let uri = format!("/api/members/{}/return", reader);
+
+        for (notes, code) in [("  ".to_string(), "blank"), ("x".repeat(1001), "too_long")] {
+            let req = post(&uri, &me, json!({"book_id": book_id, "condition": "damaged", "notes": notes}));
+            let res = test::call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            let body: Value = test::read_body_json(res).await;
+            assert_eq!(body["errors"][0]["field"], "notes");
+            assert_eq!(body["errors"][0]["code"], code);
+        }
+        assert!(repo.with_state(|s| s.incidents.is_empty()));
+    }
+
+    #[actix_web::test]
+    async fn checkout_policies_refuse_with_distinct_codes() {
+        let (repo, keys) = setup();
+        let reader = member(&repo, "reader@example.com").await;
+        let plenty = book(&repo, "Plenty", 3).await;
+        let restricted = book(&repo, "Restricted", 1).await;
+        let mut shelf = Vec::new();
+        for n in 0..LoanPolicy::default().max_open_loans {
+            shelf.push(book(&repo, &format!("Shelf {}", n), 1).await);
+        }
+        repo.with_state(|s| s.books.iter_mut().find(|b| b.book_id == restricted).unwrap().min_age = Some(40));
+        let app = app!(repo, keys);
+        let me = auth(&keys, reader, Role::Member);
+        let librarian = auth(&keys, 1, Role::Librarian);
+        let checkout = format!("/api/members/{}/checkout", reader);
+        let block = format!("/api/librarian/members/{}/block", reader);
+
+        let refuse = |body: Value| {
+            let req = post(&checkout, &me, body).to_request();
+            let app = &app;
+            async move {
+                let res = test::call_service(app, req).await;
+                let status = res.status();
+                let body: Value = test::read_body_json(res).await;
+                (status, body)
+            }
+        };
+
+        let req = test::TestRequest::put()
+            .uri(&block)
+            .insert_header(me.clone())
+            .set_json(json!({"reason": "self-service"}))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+        let req = test::TestRequest::put()
+            .uri(&block)
+            .insert_header(librarian.clone())
+            .set_json(json!({"reason": "three books lost"}))
+            .to_request();
+        let blocked: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(blocked["blocked_reason"], "three books lost");
+        let (status, body) = refuse(json!({"book_ids": [plenty]})).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["code"], "member_blocked");
+        assert_eq!(body["reason"], "three books lost");
+        let req = test::TestRequest::delete().uri(&block).insert_header(librarian.clone()).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let (status, body) = refuse(json!({"book_ids": [plenty, restricted]})).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["code"], "age_restricted");
+        assert_eq!((body["book_id"].as_i64(), body["min_age"].as_i64()), (Some(restricted.into()), Some(40)));
+        assert_eq!(copies(&repo, plenty), 3, "nothing is taken when a rule refuses");
+
+        let req = post(&checkout, &me, json!({"book_ids": [plenty]})).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let (status, body) = refuse(json!({"book_ids": [plenty]})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "title_limit_reached");
+        assert_eq!(body["max_copies_per_title"], 1);
+
+        let (status, body) = refuse(json!({"book_ids": shelf})).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "loan_limit_reached");
+        assert_eq!((body["open_loans"].as_i64(), body["max_open_loans"].as_i64()), (Some(1), Some(10)));
+        assert!(shelf.iter().all(|&id| copies(&repo, id) == 1));
+    }
+}
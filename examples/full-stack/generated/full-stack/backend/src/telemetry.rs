@@ -0,0 +1,179 @@
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::middleware::Next;
+use std::time::Instant;
+use tracing::{info_span, Instrument, Span};
+use tracing_subscriber::EnvFilter;
+
+use crate::config::{LogFormat, LoggingConfig};
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client-supplied request ID that is passed through; anything
+/// longer (or not printable ASCII) is replaced with a fresh one.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Installs the global subscriber. `log` records from actix and sqlx are
+/// forwarded into it, so they carry the same span fields as our own events.
+pub fn init(logging: &LoggingConfig) {
+    let builder = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(&logging.level));
+    match logging.format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .init(),
+    }
+}
+
+fn request_id(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(&REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic()))
+        .map(String::from)
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string())
+}
+
+/// Middleware opening the `request` span every other span and event nests
+/// under, and logging one line per request when it completes. The ID is
+/// taken from `X-Request-Id` when the caller sent one and echoed back.
+pub async fn trace_request(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let id = request_id(&req);
+    let span = info_span!(
+        "request",
+        request_id = %id,
+        method = %req.method(),
+        route = req.match_pattern().as_deref().unwrap_or("unmatched"),
+    );
+    let started = Instant::now();
+
+    let res = next.call(req).instrument(span.clone()).await;
+
+    let _entered = span.enter();
+    let latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+    match res {
+        Ok(mut res) => {
+            tracing::info!(status = res.status().as_u16(), latency_ms, "request completed");
+            if let Ok(value) = HeaderValue::from_str(&id) {
+                res.headers_mut().insert(REQUEST_ID, value);
+            }
+            Ok(res)
+        }
+        Err(e) => {
+            let status = e.as_response_error().status_code().as_u16();
+            tracing::info!(status, latency_ms, "request failed");
+            Err(e)
+        }
+    }
+}
+
+/// Span for one database statement, so sqlx's own query log lines (enable
+/// with `sqlx::query=debug`) say which statement of which request they were.
+pub fn db_span(query: &'static str) -> Span {
+    info_span!("db", query)
+}
+
+/// Collects what a test subscriber writes, for asserting on log output.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Captured {
+    pub fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl std::io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, web, App, HttpResponse};
+
+    #[tracing::instrument(skip_all, fields(member_id = *path))]
+    async fn handler(path: web::Path<i32>) -> HttpResponse {
+        async { tracing::info!("inside") }.instrument(db_span("select_member")).await;
+        HttpResponse::Ok().finish()
+    }
+
+    macro_rules! app {
+        () => {
+            test::init_service(
+                App::new()
+                    .wrap(from_fn(trace_request))
+                    .route("/members/{member_id}", web::get().to(handler)),
+            )
+            .await
+        };
+    }
+
+    #[actix_web::test]
+    async fn request_ids_are_echoed_or_generated() {
+        let app = app!();
+
+        let req = test::TestRequest::get().uri("/members/1").insert_header(("X-Request-Id", "abc-123")).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.headers().get(&REQUEST_ID).unwrap(), "abc-123");
+
+        for sent in [None, Some("has space"), Some("")] {
+            let mut req = test::TestRequest::get().uri("/members/1");
+            if let Some(sent) = sent {
+                req = req.insert_header(("X-Request-Id", sent));
+            }
+            let res = test::call_service(&app, req.to_request()).await;
+            let id = res.headers().get(&REQUEST_ID).unwrap().to_str().unwrap();
+            assert!(uuid::Uuid::parse_str(id).is_ok(), "{sent:?} got {id}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn events_carry_request_and_handler_fields() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .flatten_event(true)
+            .with_span_list(true)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let app = app!();
+        let req = test::TestRequest::get().uri("/members/7").insert_header(("X-Request-Id", "trace-me")).to_request();
+        test::call_service(&app, req).await;
+
+        let output = captured.text();
+        let lines: Vec<serde_json::Value> = output.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+
+        let inside = lines.iter().find(|l| l["message"] == "inside").expect(&output);
+        let spans = &inside["spans"];
+        assert_eq!(spans[0]["request_id"], "trace-me");
+        assert_eq!(spans[0]["route"], "/members/{member_id}");
+        assert_eq!(spans[1]["member_id"], 7);
+        assert_eq!(spans[2]["query"], "select_member");
+
+        let done = lines.iter().find(|l| l["message"] == "request completed").expect(&output);
+        assert_eq!(done["status"], 200);
+        assert_eq!(done["spans"][0]["request_id"], "trace-me");
+    }
+}
@@ -0,0 +1,249 @@
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::future::Future;
+use std::hash::Hash;
+use std::ops::Deref;
+use std::pin::Pin;
+use utoipa::ToSchema;
+
+use crate::error::ApiError;
+
+/// One rule a request field broke. `code` is stable; `message` is for people.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// A request body that can check its own fields before a handler sees it.
+pub trait Validate {
+    fn validate(&self) -> Result<(), Vec<FieldError>>;
+}
+
+/// Collects every broken rule instead of stopping at the first, so clients
+/// can highlight all bad fields at once.
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn fail(&mut self, field: &str, code: &'static str, message: String) -> &mut Self {
+        self.errors.push(FieldError {
+            field: field.to_string(),
+            code,
+            message,
+        });
+        self
+    }
+
+    pub fn not_blank(&mut self, field: &str, value: &str) -> &mut Self {
+        if value.trim().is_empty() {
+            return self.fail(field, "blank", "must not be blank".into());
+        }
+        self
+    }
+
+    pub fn min_chars(&mut self, field: &str, value: &str, min: usize) -> &mut Self {
+        if value.chars().count() < min {
+            return self.fail(field, "too_short", format!("must be at least {} characters", min));
+        }
+        self
+    }
+
+    /// Skipped for `None`, so optional fields are only checked when present.
+    pub fn max_chars<'a>(&mut self, field: &str, value: impl Into<Option<&'a str>>, max: usize) -> &mut Self {
+        match value.into() {
+            Some(v) if v.chars().count() > max => {
+                self.fail(field, "too_long", format!("must be at most {} characters", max))
+            }
+            _ => self,
+        }
+    }
+
+    /// Skipped for `None`, so optional fields are only checked when present.
+    pub fn range<T>(&mut self, field: &str, value: impl Into<Option<T>>, min: T, max: T) -> &mut Self
+    where
+        T: PartialOrd + Display,
+    {
+        match value.into() {
+            Some(v) if v < min || v > max => {
+                self.fail(field, "out_of_range", format!("must be between {} and {}", min, max))
+            }
+            _ => self,
+        }
+    }
+
+    /// A deliberately loose shape check; the real test is whether mail arrives.
+    pub fn email(&mut self, field: &str, value: &str) -> &mut Self {
+        let valid = value.len() <= 254
+            && !value.chars().any(char::is_whitespace)
+            && match value.split_once('@') {
+                Some((local, domain)) => {
+                    !local.is_empty()
+                        && !domain.contains('@')
+                        && domain.contains('.')
+                        && !domain.starts_with('.')
+                        && !domain.ends_with('.')
+                }
+                None => false,
+            };
+        if !valid {
+            return self.fail(field, "invalid_email", "must be a valid email address".into());
+        }
+        self
+    }
+
+    /// For mutually exclusive fields: exactly one of `options` must be set.
+    pub fn exactly_one(&mut self, field: &str, options: &[(&str, bool)]) -> &mut Self {
+        if options.iter().filter(|(_, present)| *present).count() != 1 {
+            let names: Vec<&str> = options.iter().map(|(name, _)| *name).collect();
+            return self.fail(field, "exactly_one", format!("must set exactly one of {}", names.join(", ")));
+        }
+        self
+    }
+
+    pub fn not_empty<T>(&mut self, field: &str, values: &[T]) -> &mut Self {
+        if values.is_empty() {
+            return self.fail(field, "empty", "must contain at least one item".into());
+        }
+        self
+    }
+
+    pub fn unique<T: Eq + Hash>(&mut self, field: &str, values: &[T]) -> &mut Self {
+        let mut seen = HashSet::new();
+        if !values.iter().all(|v| seen.insert(v)) {
+            return self.fail(field, "duplicate", "must not contain duplicates".into());
+        }
+        self
+    }
+
+    /// Applies `range` to every element, reporting failures as `field[i]`.
+    pub fn each_in_range<T>(&mut self, field: &str, values: &[T], min: T, max: T) -> &mut Self
+    where
+        T: PartialOrd + Display + Copy,
+    {
+        for (i, v) in values.iter().enumerate() {
+            self.range(&format!("{}[{}]", field, i), *v, min, max);
+        }
+        self
+    }
+
+    pub fn finish(&mut self) -> Result<(), Vec<FieldError>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+}
+
+/// `web::Json<T>` that also runs `T::validate`, answering 422 with every
+/// field error when it fails.
+#[derive(Debug)]
+pub struct ValidJson<T>(pub T);
+
+impl<T> Deref for ValidJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for ValidJson<T>
+where
+    T: DeserializeOwned + Validate + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+        Box::pin(async move {
+            let body = json.await?.into_inner();
+            body.validate().map_err(ApiError::Validation)?;
+            Ok(ValidJson(body))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(result: Result<(), Vec<FieldError>>) -> Vec<(String, &'static str)> {
+        result
+            .err()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|e| (e.field, e.code))
+            .collect()
+    }
+
+    #[test]
+    fn collects_every_failure() {
+        let result = Validator::new()
+            .not_blank("name", "  ")
+            .range("age", -1, 0, 150)
+            .email("email", "not-an-email")
+            .finish();
+        assert_eq!(
+            codes(result),
+            vec![
+                ("name".into(), "blank"),
+                ("age".into(), "out_of_range"),
+                ("email".into(), "invalid_email"),
+            ]
+        );
+    }
+
+    #[test]
+    fn optional_fields_are_skipped_when_absent() {
+        let result = Validator::new()
+            .range("age", None::<i32>, 0, 150)
+            .max_chars("edition", None, 10)
+            .finish();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn emails_need_a_local_part_and_a_dotted_domain() {
+        for ok in ["a@b.co", "first.last+tag@example.org"] {
+            assert!(Validator::new().email("email", ok).finish().is_ok(), "{ok}");
+        }
+        for bad in ["", "@example.com", "a@b", "a@.com", "a@b.", "a b@c.com", "a@b@c.com"] {
+            assert!(Validator::new().email("email", bad).finish().is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn lists_must_be_non_empty_unique_and_in_range() {
+        assert_eq!(codes(Validator::new().not_empty::<i32>("ids", &[]).finish()), vec![("ids".into(), "empty")]);
+        assert_eq!(codes(Validator::new().unique("ids", &[1, 2, 1]).finish()), vec![("ids".into(), "duplicate")]);
+        assert_eq!(
+            codes(Validator::new().each_in_range("ids", &[3, 0], 1, i32::MAX).finish()),
+            vec![("ids[1]".into(), "out_of_range")]
+        );
+    }
+
+    #[test]
+    fn exclusive_fields_need_exactly_one_value() {
+        assert!(Validator::new().exactly_one("u", &[("a", true), ("b", false)]).finish().is_ok());
+        for both_or_neither in [true, false] {
+            let result = Validator::new()
+                .exactly_one("u", &[("a", both_or_neither), ("b", both_or_neither)])
+                .finish();
+            assert_eq!(codes(result), vec![("u".into(), "exactly_one")]);
+        }
+    }
+}
@@ -6,6 +6,7 @@ import CartPage from './pages/CartPage';
 import BorrowedPage from './pages/BorrowedPage';
 import AdminPage from './pages/AdminPage';
 import Navbar from './components/Navbar';
+import { setToken } from './api';
 
 export default function App() {
     const [user, setUser] = useState(null);         // { member_id, name }
@@ -25,6 +26,7 @@ export default function App() {
     const clearCart = () => setCart([]);
 
     const logout = () => {
+        setToken(null);
         setUser(null);
         setIsAdmin(false);
         setCart([]);
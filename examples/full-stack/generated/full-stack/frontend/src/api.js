@@ -1,28 +1,69 @@
 const API = '';
 
-async function request(method, path, body) {
+let token = null;
+
+export function setToken(t) {
+    token = t;
+}
+
+async function request(method, path, body, headers = {}) {
     const opts = {
         method,
-        headers: { 'Content-Type': 'application/json' },
+        headers: { 'Content-Type': 'application/json', ...headers },
     };
+    if (token) opts.headers.Authorization = `Bearer ${token}`;
     if (body) opts.body = JSON.stringify(body);
 
     const res = await fetch(`${API}${path}`, opts);
     const data = await res.json();
-    if (!res.ok) throw new Error(data.error || 'Request failed');
+    if (!res.ok) {
+        // Errors are RFC 7807 problem documents; `code` is stable, `detail` is for people.
+        // Validation failures (422) list every bad field in `errors`.
+        const fields = (data.errors || []).map((e) => `${e.field} ${e.message}`);
+        const err = new Error(fields.length ? fields.join('; ') : data.detail || 'Request failed');
+        err.code = data.code;
+        err.errors = data.errors;
+        err.status = res.status;
+        throw err;
+    }
     return data;
 }
 
 export const api = {
     register: (data) => request('POST', '/api/register', data),
     login: (data) => request('POST', '/api/login', data),
-    listBooks: () => request('GET', '/api/books'),
+    librarianLogin: (data) => request('POST', '/api/librarian/login', data),
+    // Returns { items, total, limit, page, next_cursor }.
+    listBooks: (params = {}) => {
+        const qs = new URLSearchParams(
+            Object.entries(params).filter(([, v]) => v !== undefined && v !== ''),
+        ).toString();
+        return request('GET', `/api/books${qs ? `?${qs}` : ''}`);
+    },
+    getBook: (id) => request('GET', `/api/books/${id}`),
     addBook: (data) => request('POST', '/api/books', data),
+    // `version` comes from the book as last loaded; a stale one gets 412.
+    updateBook: (id, data, version) =>
+        request('PATCH', `/api/books/${id}`, data, { 'If-Match': `"${version}"` }),
+    // updates: [{ book_id, number_of_copies | adjust_by, version? }]
+    bulkUpdateStock: (updates) => request('PATCH', '/api/books', { updates }),
+    // Archives the book; refused with 409 while copies are on loan.
     removeBook: (id) => request('DELETE', `/api/books/${id}`),
+    restoreBook: (id) => request('POST', `/api/books/${id}/restore`),
     checkout: (memberId, bookIds) =>
         request('POST', `/api/members/${memberId}/checkout`, { book_ids: bookIds }),
     borrowedBooks: (memberId) =>
         request('GET', `/api/members/${memberId}/borrowed`),
-    returnBook: (memberId, bookId) =>
-        request('POST', `/api/members/${memberId}/return`, { book_id: bookId }),
+    returnBook: (memberId, bookId, condition = 'good') =>
+        request('POST', `/api/members/${memberId}/return`, { book_id: bookId, condition }),
+    renewLoan: (memberId, ledgerId) =>
+        request('POST', `/api/members/${memberId}/loans/${ledgerId}/renew`),
+    holds: (memberId) => request('GET', `/api/members/${memberId}/holds`),
+    placeHold: (memberId, bookId) =>
+        request('POST', `/api/members/${memberId}/holds`, { book_id: bookId }),
+    cancelHold: (memberId, holdId) =>
+        request('DELETE', `/api/members/${memberId}/holds/${holdId}`),
+    fines: (memberId) => request('GET', `/api/members/${memberId}/fines`),
+    payFine: (memberId, amountCents) =>
+        request('POST', `/api/members/${memberId}/fines/payments`, { amount_cents: amountCents }),
 };
@@ -3,6 +3,7 @@ import { api } from '../api';
 
 export default function AdminPage() {
     const [books, setBooks] = useState([]);
+    const [total, setTotal] = useState(0);
     const [msg, setMsg] = useState('');
     const [error, setError] = useState('');
 
@@ -14,7 +15,12 @@ export default function AdminPage() {
     const [edition, setEdition] = useState('');
 
     const load = () => {
-        api.listBooks().then(setBooks).catch(console.error);
+        api.listBooks({ limit: 100, include_archived: true })
+            .then((page) => {
+                setBooks(page.items);
+                setTotal(page.total);
+            })
+            .catch(console.error);
     };
 
     useEffect(load, []);
@@ -39,10 +45,32 @@ export default function AdminPage() {
         }
     };
 
+    const handleCopies = async (book, delta) => {
+        setError('');
+        try {
+            await api.updateBook(book.book_id, { number_of_copies: book.number_of_copies + delta }, book.version);
+            load();
+        } catch (err) {
+            setError(err.message);
+            load();
+        }
+    };
+
+    const handleRestore = async (bookId) => {
+        try {
+            await api.restoreBook(bookId);
+            setMsg('Book restored.');
+            load();
+            setTimeout(() => setMsg(''), 3000);
+        } catch (err) {
+            setError(err.message);
+        }
+    };
+
     const handleRemove = async (bookId) => {
         try {
             await api.removeBook(bookId);
-            setMsg('Book removed.');
+            setMsg('Book archived.');
             load();
             setTimeout(() => setMsg(''), 3000);
         } catch (err) {
@@ -54,7 +82,7 @@ export default function AdminPage() {
         <>
             <div className="page-header">
                 <h1>Library Admin</h1>
-                <p>Add or remove books from the inventory</p>
+                <p>Add, restock, archive or restore books in the inventory</p>
             </div>
 
             {error && <div className="alert alert-error">{error}</div>}
@@ -94,7 +122,7 @@ export default function AdminPage() {
 
             {/* ── Inventory ─────────────────────────────────────────── */}
             <div className="glass" style={{ padding: 20 }}>
-                <h2 style={{ fontSize: '1.1rem', marginBottom: 16 }}>Inventory ({books.length})</h2>
+                <h2 style={{ fontSize: '1.1rem', marginBottom: 16 }}>Inventory ({total})</h2>
                 {books.length === 0 ? (
                     <p style={{ color: 'var(--text-secondary)' }}>No books yet.</p>
                 ) : (
@@ -110,15 +138,25 @@ export default function AdminPage() {
                         </thead>
                         <tbody>
                             {books.map((b) => (
-                                <tr key={b.book_id}>
+                                <tr key={b.book_id} style={b.archived_at ? { opacity: 0.5 } : undefined}>
                                     <td style={{ color: 'var(--text-muted)' }}>{b.book_id}</td>
                                     <td>{b.name}</td>
                                     <td style={{ color: 'var(--text-secondary)' }}>{b.author}</td>
-                                    <td>{b.number_of_copies}</td>
                                     <td>
-                                        <button className="btn btn-danger btn-sm" onClick={() => handleRemove(b.book_id)}>
-                                            Remove
-                                        </button>
+                                        <button className="btn btn-sm" disabled={b.number_of_copies === 0} onClick={() => handleCopies(b, -1)}>−</button>
+                                        <span style={{ margin: '0 8px' }}>{b.number_of_copies}</span>
+                                        <button className="btn btn-sm" onClick={() => handleCopies(b, 1)}>+</button>
+                                    </td>
+                                    <td>
+                                        {b.archived_at ? (
+                                            <button className="btn btn-sm" onClick={() => handleRestore(b.book_id)}>
+                                                Restore
+                                            </button>
+                                        ) : (
+                                            <button className="btn btn-danger btn-sm" onClick={() => handleRemove(b.book_id)}>
+                                                Archive
+                                            </button>
+                                        )}
                                     </td>
                                 </tr>
                             ))}
@@ -1,5 +1,5 @@
 import React, { useState } from 'react';
-import { api } from '../api';
+import { api, setToken } from '../api';
 
 export default function AuthPage({ onLogin, onAdminLogin }) {
     const [tab, setTab] = useState('login');
@@ -7,8 +7,8 @@ export default function AuthPage({ onLogin, onAdminLogin }) {
     const [success, setSuccess] = useState('');
 
     // Login state
-    const [loginName, setLoginName] = useState('');
     const [loginId, setLoginId] = useState('');
+    const [loginPass, setLoginPass] = useState('');
 
     // Register state
     const [firstName, setFirstName] = useState('');
@@ -16,15 +16,18 @@ export default function AuthPage({ onLogin, onAdminLogin }) {
     const [age, setAge] = useState('');
     const [email, setEmail] = useState('');
     const [address, setAddress] = useState('');
+    const [password, setPassword] = useState('');
 
     // Admin state
+    const [adminUser, setAdminUser] = useState('admin');
     const [adminPass, setAdminPass] = useState('');
 
     const handleLogin = async (e) => {
         e.preventDefault();
         setError('');
         try {
-            const data = await api.login({ username: loginName, member_id: Number(loginId) });
+            const data = await api.login({ member_id: Number(loginId), password: loginPass });
+            setToken(data.token);
             onLogin({ member_id: data.member_id, name: data.name });
         } catch (err) {
             setError(err.message);
@@ -42,6 +45,7 @@ export default function AuthPage({ onLogin, onAdminLogin }) {
                 age: Number(age),
                 email,
                 address,
+                password,
             });
             setSuccess(`Welcome! Your Member ID is ${data.member_id}. Switch to Login to enter.`);
         } catch (err) {
@@ -49,12 +53,15 @@ export default function AuthPage({ onLogin, onAdminLogin }) {
         }
     };
 
-    const handleAdminLogin = (e) => {
+    const handleAdminLogin = async (e) => {
         e.preventDefault();
-        if (adminPass === 'password') {
+        setError('');
+        try {
+            const data = await api.librarianLogin({ username: adminUser, password: adminPass });
+            setToken(data.token);
             onAdminLogin();
-        } else {
-            setError('Invalid admin password');
+        } catch (err) {
+            setError(err.message);
         }
     };
 
@@ -82,14 +89,14 @@ export default function AuthPage({ onLogin, onAdminLogin }) {
 
             {tab === 'login' && (
                 <form onSubmit={handleLogin}>
-                    <div className="form-group">
-                        <label>Username</label>
-                        <input className="input" placeholder="Your name" value={loginName} onChange={(e) => setLoginName(e.target.value)} required />
-                    </div>
                     <div className="form-group">
                         <label>Member ID</label>
                         <input className="input" type="number" placeholder="e.g. 1" value={loginId} onChange={(e) => setLoginId(e.target.value)} required />
                     </div>
+                    <div className="form-group">
+                        <label>Password</label>
+                        <input className="input" type="password" value={loginPass} onChange={(e) => setLoginPass(e.target.value)} required />
+                    </div>
                     <button className="btn btn-primary" style={{ width: '100%' }}>Login</button>
                 </form>
             )}
@@ -120,6 +127,10 @@ export default function AuthPage({ onLogin, onAdminLogin }) {
                         <label>Address</label>
                         <input className="input" value={address} onChange={(e) => setAddress(e.target.value)} />
                     </div>
+                    <div className="form-group">
+                        <label>Password</label>
+                        <input className="input" type="password" minLength={8} value={password} onChange={(e) => setPassword(e.target.value)} required />
+                    </div>
                     <button className="btn btn-primary" style={{ width: '100%' }}>Register</button>
                 </form>
             )}
@@ -128,7 +139,7 @@ export default function AuthPage({ onLogin, onAdminLogin }) {
                 <form onSubmit={handleAdminLogin}>
                     <div className="form-group">
                         <label>Username</label>
-                        <input className="input" value="admin" disabled />
+                        <input className="input" value={adminUser} onChange={(e) => setAdminUser(e.target.value)} required />
                     </div>
                     <div className="form-group">
                         <label>Password</label>
@@ -5,6 +5,8 @@ export default function BorrowedPage({ user }) {
     const [books, setBooks] = useState([]);
     const [loading, setLoading] = useState(true);
     const [msg, setMsg] = useState('');
+    const [conditions, setConditions] = useState({});   // ledger id -> condition
+    const [balance, setBalance] = useState(0);          // cents
 
     const load = () => {
         setLoading(true);
@@ -12,13 +14,38 @@ export default function BorrowedPage({ user }) {
             .then((data) => setBooks(data || []))
             .catch(console.error)
             .finally(() => setLoading(false));
+        api.fines(user.member_id)
+            .then((data) => setBalance(data.balance_cents))
+            .catch(console.error);
+    };
+
+    const handleRenew = async (loan) => {
+        try {
+            const data = await api.renewLoan(user.member_id, loan.id);
+            setMsg(`Renewed until ${fmtDate(data.expected_return)}.`);
+            load();
+            setTimeout(() => setMsg(''), 3000);
+        } catch (err) {
+            setMsg(err.message);
+        }
+    };
+
+    const handlePay = async () => {
+        try {
+            await api.payFine(user.member_id, balance);
+            setMsg('Fines paid. Thank you!');
+            load();
+            setTimeout(() => setMsg(''), 3000);
+        } catch (err) {
+            setMsg(err.message);
+        }
     };
 
     useEffect(load, [user.member_id]);
 
-    const handleReturn = async (bookId) => {
+    const handleReturn = async (loan) => {
         try {
-            await api.returnBook(user.member_id, bookId);
+            await api.returnBook(user.member_id, loan.book_id, conditions[loan.id] || 'good');
             setMsg('Book returned successfully!');
             load();
             setTimeout(() => setMsg(''), 3000);
@@ -38,6 +65,13 @@ export default function BorrowedPage({ user }) {
 
             {msg && <div className="alert alert-success">{msg}</div>}
 
+            {balance > 0 && (
+                <div className="alert alert-error">
+                    Outstanding fines: ${(balance / 100).toFixed(2)}{' '}
+                    <button className="btn btn-ghost btn-sm" onClick={handlePay}>Pay now</button>
+                </div>
+            )}
+
             {loading ? (
                 <p style={{ textAlign: 'center', color: 'var(--text-secondary)' }}>Loading…</p>
             ) : books.length === 0 ? (
@@ -68,9 +102,26 @@ export default function BorrowedPage({ user }) {
                                         {b.is_overdue ? '⚠ Overdue' : 'Active'}
                                     </td>
                                     <td>
-                                        <button className="btn btn-ghost btn-sm" onClick={() => handleReturn(b.book_id)}>
+                                        <select
+                                            className="input"
+                                            style={{ width: 'auto', marginRight: 8 }}
+                                            value={conditions[b.id] || 'good'}
+                                            onChange={(e) => setConditions({ ...conditions, [b.id]: e.target.value })}
+                                        >
+                                            <option value="good">Good</option>
+                                            <option value="damaged">Damaged</option>
+                                            <option value="lost">Lost</option>
+                                        </select>
+                                        <button className="btn btn-ghost btn-sm" onClick={() => handleReturn(b)}>
                                             Return
                                         </button>
+                                        <button
+                                            className="btn btn-ghost btn-sm"
+                                            disabled={b.is_overdue || b.renewals_left === 0}
+                                            onClick={() => handleRenew(b)}
+                                        >
+                                            Renew
+                                        </button>
                                     </td>
                                 </tr>
                             ))}
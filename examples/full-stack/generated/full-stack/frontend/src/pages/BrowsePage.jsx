@@ -3,13 +3,49 @@ import { api } from '../api';
 
 export default function BrowsePage({ user, cart, onAddToCart }) {
     const [books, setBooks] = useState([]);
+    const [nextCursor, setNextCursor] = useState(null);
+    const [search, setSearch] = useState('');
+    const [holds, setHolds] = useState([]);
     const [loading, setLoading] = useState(true);
+    const [error, setError] = useState('');
+
+    const loadHolds = () => {
+        api.holds(user.member_id).then(setHolds).catch(console.error);
+    };
+
+    const loadBooks = (cursor) => {
+        api.listBooks({ q: search, cursor })
+            .then((page) => {
+                setBooks(cursor ? (prev) => [...prev, ...page.items] : page.items);
+                setNextCursor(page.next_cursor);
+            })
+            .catch(console.error)
+            .finally(() => setLoading(false));
+    };
 
     useEffect(() => {
-        api.listBooks().then(setBooks).catch(console.error).finally(() => setLoading(false));
-    }, []);
+        loadBooks();
+        loadHolds();
+    }, [user.member_id]);
+
+    const handleSearch = (e) => {
+        e.preventDefault();
+        setLoading(true);
+        loadBooks();
+    };
 
     const inCart = (id) => cart.some((b) => b.book_id === id);
+    const holdFor = (id) => holds.find((h) => h.book_id === id);
+
+    const handleHold = async (bookId) => {
+        setError('');
+        try {
+            await api.placeHold(user.member_id, bookId);
+            loadHolds();
+        } catch (err) {
+            setError(err.message);
+        }
+    };
 
     return (
         <>
@@ -18,6 +54,18 @@ export default function BrowsePage({ user, cart, onAddToCart }) {
                 <p>Select books to add to your cart</p>
             </div>
 
+            {error && <div className="alert alert-error">{error}</div>}
+
+            <form onSubmit={handleSearch} style={{ display: 'flex', gap: 8, marginBottom: 24 }}>
+                <input
+                    className="input"
+                    placeholder="Search by title or author"
+                    value={search}
+                    onChange={(e) => setSearch(e.target.value)}
+                />
+                <button className="btn btn-primary">Search</button>
+            </form>
+
             {loading ? (
                 <p style={{ textAlign: 'center', color: 'var(--text-secondary)' }}>Loading books…</p>
             ) : books.length === 0 ? (
@@ -26,28 +74,54 @@ export default function BrowsePage({ user, cart, onAddToCart }) {
                 </p>
             ) : (
                 <div className="grid grid-3">
-                    {books.map((book) => (
+                    {books.map((book) => {
+                        const hold = holdFor(book.book_id);
+                        const reserved = hold && hold.status === 'ready';
+                        return (
                         <div key={book.book_id} className="book-card glass">
                             <h3>{book.name}</h3>
                             <div className="meta">
                                 {book.author} · {book.publication_year} · {book.edition}
                             </div>
                             <span className={`copies ${book.number_of_copies > 0 ? 'copies-available' : 'copies-none'}`}>
-                                {book.number_of_copies > 0
+                                {reserved
+                                    ? 'Reserved for you'
+                                    : book.number_of_copies > 0
                                     ? `${book.number_of_copies} available`
+                                    : hold
+                                    ? `On hold · #${hold.queue_position} in line`
                                     : 'Unavailable'}
                             </span>
                             <div className="actions">
-                                <button
-                                    className="btn btn-primary btn-sm"
-                                    disabled={book.number_of_copies <= 0 || inCart(book.book_id)}
-                                    onClick={() => onAddToCart(book)}
-                                >
-                                    {inCart(book.book_id) ? '✓ In Cart' : 'Add to Cart'}
-                                </button>
+                                {book.number_of_copies > 0 || reserved ? (
+                                    <button
+                                        className="btn btn-primary btn-sm"
+                                        disabled={inCart(book.book_id)}
+                                        onClick={() => onAddToCart(book)}
+                                    >
+                                        {inCart(book.book_id) ? '✓ In Cart' : 'Add to Cart'}
+                                    </button>
+                                ) : (
+                                    <button
+                                        className="btn btn-ghost btn-sm"
+                                        disabled={!!hold}
+                                        onClick={() => handleHold(book.book_id)}
+                                    >
+                                        {hold ? '✓ On Hold' : 'Place Hold'}
+                                    </button>
+                                )}
                             </div>
                         </div>
-                    ))}
+                        );
+                    })}
+                </div>
+            )}
+
+            {!loading && nextCursor && (
+                <div style={{ textAlign: 'center', marginTop: 24 }}>
+                    <button className="btn btn-ghost" onClick={() => loadBooks(nextCursor)}>
+                        Load more
+                    </button>
                 </div>
             )}
         </>